@@ -0,0 +1,932 @@
+// Software stand-in for the configured displays. It opens a pseudo terminal (or
+// listens on a tcp port), speaks the same command set as `Device` and dumps every
+// received frame as png, so the whole pipeline can be run without the hardware.
+// Button presses and encoder steps of the teensy are typed on stdin, one per line:
+// `press <button>`, `long <button>` or `rotate <encoder> <steps>`.
+//
+// usage: device_emulator <teensy|esp32> [output directory] [--tcp <port>] [--legacy]
+
+// shared with the application, parts of it are only used there
+#[allow(dead_code)]
+#[path = "../../capabilities.rs"]
+mod capabilities;
+#[allow(dead_code)]
+#[path = "../../helpers/convert_image.rs"]
+mod convert_image;
+#[allow(dead_code)]
+#[path = "../../dada_packet.rs"]
+mod dada_packet;
+#[allow(dead_code)]
+#[path = "../../helpers/dirty_rect.rs"]
+mod dirty_rect;
+#[allow(dead_code)]
+#[path = "../../protocol.rs"]
+mod protocol;
+#[cfg(unix)]
+mod pty;
+
+use capabilities::*;
+use convert_image::{convert_from_gray_scale, convert_from_rgb565, decode_rle};
+use dada_packet::{split_sequenced_payload, DadaPacketDecoder};
+use dirty_rect::split_region_payload;
+use protocol::{Command, InputEvent, Reply};
+
+use std::{
+    io::{BufRead, Read, Write},
+    net::TcpListener,
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+struct Profile {
+    name: &'static str,
+    width: u32,
+    height: u32,
+    use_dada_packet: bool,
+    has_bme_sensor: bool,
+    has_buttons: bool,
+}
+
+impl Profile {
+    fn capabilities(&self) -> Capabilities {
+        let mut features = FEATURE_FIRMWARE_UPDATE;
+        if self.has_bme_sensor {
+            features |= FEATURE_BME_SENSOR | FEATURE_PRESSURE;
+        }
+        if self.has_buttons {
+            features |= FEATURE_BUTTONS;
+        }
+        if self.use_dada_packet {
+            features |= FEATURE_BRIGHTNESS | FEATURE_SEQUENCED_FRAMES | FEATURE_PARTIAL_UPDATES;
+        }
+        let (pixel_format, encodings) = if self.use_dada_packet {
+            (
+                PIXEL_FORMAT_RGB888,
+                ENCODING_WEBP | ENCODING_RGB565 | ENCODING_JPEG | ENCODING_QOI,
+            )
+        } else {
+            (PIXEL_FORMAT_GRAY4, ENCODING_GRAY4 | ENCODING_GRAY4_RLE)
+        };
+        Capabilities {
+            version: PROTOCOL_VERSION,
+            width: self.width as u16,
+            height: self.height as u16,
+            pixel_format,
+            encodings,
+            features,
+        }
+    }
+}
+
+const PROFILES: [Profile; 2] = [
+    Profile {
+        name: "teensy",
+        width: 256,
+        height: 64,
+        use_dada_packet: false,
+        has_bme_sensor: true,
+        has_buttons: true,
+    },
+    Profile {
+        name: "esp32",
+        width: 320,
+        height: 170,
+        use_dada_packet: true,
+        has_bme_sensor: false,
+        has_buttons: false,
+    },
+];
+
+// image of a firmware upload, kept over reconnects to test resuming
+#[derive(Default)]
+struct FirmwareUpload {
+    size: u32,
+    crc: u32,
+    image: Vec<u8>,
+}
+
+struct Emulator<T: Read + Write> {
+    profile: &'static Profile,
+    port: T,
+    output_dir: Option<PathBuf>,
+    // behaves like firmware from before the capabilities handshake
+    legacy: bool,
+    frames: u32,
+    keep_alives: u32,
+    awake: bool,
+    brightness: u8,
+    decoder: DadaPacketDecoder,
+    // of frames and regions, changed by the host after the handshake
+    encoding: u8,
+    // what the display currently shows, regions are drawn onto it
+    screen: image::RgbImage,
+    firmware: FirmwareUpload,
+    // typed on stdin, until the host reads them
+    input_events: Arc<Mutex<Vec<InputEvent>>>,
+}
+
+impl<T: Read + Write> Emulator<T> {
+    fn new(
+        profile: &'static Profile,
+        port: T,
+        output_dir: Option<PathBuf>,
+        legacy: bool,
+        input_events: Arc<Mutex<Vec<InputEvent>>>,
+    ) -> Self {
+        Emulator {
+            profile,
+            port,
+            output_dir,
+            legacy,
+            frames: 0,
+            keep_alives: 0,
+            awake: false,
+            brightness: 255,
+            decoder: DadaPacketDecoder::default(),
+            encoding: if profile.use_dada_packet {
+                ENCODING_WEBP
+            } else {
+                ENCODING_GRAY4
+            },
+            screen: image::RgbImage::new(profile.width, profile.height),
+            firmware: FirmwareUpload::default(),
+            input_events,
+        }
+    }
+
+    fn run(&mut self) -> std::io::Result<()> {
+        loop {
+            let mut command = [0u8; 1];
+            self.port.read_exact(&mut command)?;
+            match command[0] {
+                Command::FRAME => self.receive_frame()?,
+                Command::SEQUENCED_FRAME => self.receive_sequenced_frame()?,
+                Command::REGION => self.receive_region()?,
+                Command::COMPRESSED_FRAME => self.receive_compressed_frame()?,
+                Command::SET_ENCODING if !self.legacy => self.receive_encoding()?,
+                Command::KEEP_ALIVE => self.keep_alives += 1,
+                Command::RESET => {
+                    self.awake = true;
+                    self.log("reset");
+                }
+                Command::STAND_BY => {
+                    self.awake = false;
+                    self.log("stand by");
+                }
+                Command::WAKE_UP => {
+                    self.awake = true;
+                    self.log("wake up");
+                }
+                Command::BRIGHTNESS => self.receive_brightness()?,
+                Command::READ_BME => self.send_bme_info(None)?,
+                Command::READ_ENVIRONMENT if !self.legacy => self.send_bme_info(Some(1013.25))?,
+                Command::FIRMWARE_BEGIN if !self.legacy => self.receive_firmware_begin()?,
+                Command::FIRMWARE_CHUNK if !self.legacy => self.receive_firmware_chunk()?,
+                Command::FIRMWARE_VERIFY if !self.legacy => self.verify_firmware()?,
+                Command::READ_INPUT if !self.legacy => self.send_input_events()?,
+                Command::REBOOT => {
+                    self.awake = false;
+                    self.log("reboot");
+                }
+                Command::CAPABILITIES if !self.legacy => {
+                    self.log("handshake");
+                    self.send_reply(Reply::Capabilities(self.profile.capabilities()))?;
+                }
+                unknown => self.log(&format!("unknown command {}", unknown)),
+            }
+        }
+    }
+
+    fn log(&self, message: &str) {
+        println!("[{}] {}", self.profile.name, message);
+    }
+
+    fn receive_frame(&mut self) -> std::io::Result<()> {
+        let payload = if self.profile.use_dada_packet {
+            self.read_dada_packet()?
+        } else {
+            let mut buffer = vec![0u8; (self.profile.width * self.profile.height / 2) as usize];
+            self.port.read_exact(&mut buffer)?;
+            buffer
+        };
+        self.show_frame(&payload);
+        Ok(())
+    }
+
+    fn receive_compressed_frame(&mut self) -> std::io::Result<()> {
+        let mut len = [0u8; 2];
+        self.port.read_exact(&mut len)?;
+        let mut encoded = vec![0u8; u16::from_le_bytes(len) as usize];
+        self.port.read_exact(&mut encoded)?;
+
+        self.log(&format!("compressed frame ({} bytes)", encoded.len()));
+        let expected_len = (self.profile.width * self.profile.height / 2) as usize;
+        match decode_rle(&encoded, expected_len) {
+            Some(frame) => self.show_frame(&frame),
+            None => self.log("could not decompress frame"),
+        }
+        Ok(())
+    }
+
+    fn receive_sequenced_frame(&mut self) -> std::io::Result<()> {
+        if !self.profile.use_dada_packet {
+            self.log("sequenced frames are not supported");
+            return Ok(());
+        }
+        let packet = self.read_dada_packet()?;
+        match split_sequenced_payload(&packet) {
+            Some((sequence, payload)) => {
+                self.send_reply(Reply::Ack(sequence))?;
+                self.show_frame(payload);
+            }
+            None => {
+                self.log("checksum mismatch");
+                let sequence = [packet.first(), packet.get(1)].map(|b| b.copied().unwrap_or(0));
+                self.send_reply(Reply::Nack(u16::from_le_bytes(sequence)))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn send_reply(&mut self, reply: Reply) -> std::io::Result<()> {
+        self.port.write_all(&reply.as_bytes())?;
+        self.port.flush()
+    }
+
+    fn show_frame(&mut self, payload: &[u8]) {
+        self.frames += 1;
+        self.log(&format!(
+            "frame {} ({} bytes, {} keep alives since start)",
+            self.frames,
+            payload.len(),
+            self.keep_alives
+        ));
+
+        match self.decode(payload, self.profile.width, self.profile.height) {
+            Some(image) => {
+                self.screen = image.to_rgb8();
+                self.dump_frame();
+            }
+            None => self.log("could not decode frame"),
+        }
+    }
+
+    fn receive_region(&mut self) -> std::io::Result<()> {
+        if !self.profile.use_dada_packet {
+            self.log("partial updates are not supported");
+            return Ok(());
+        }
+        let packet = self.read_dada_packet()?;
+        let region = split_region_payload(&packet).and_then(|(region, encoded)| {
+            self.decode(encoded, region.width, region.height)
+                .map(|image| (region, image))
+        });
+        match region {
+            Some((region, image)) => {
+                self.frames += 1;
+                self.log(&format!(
+                    "region {}x{} at {},{} ({} bytes)",
+                    region.width,
+                    region.height,
+                    region.x,
+                    region.y,
+                    packet.len()
+                ));
+                image::imageops::replace(
+                    &mut self.screen,
+                    &image.to_rgb8(),
+                    region.x as i64,
+                    region.y as i64,
+                );
+                self.dump_frame();
+            }
+            None => self.log("could not decode region"),
+        }
+        Ok(())
+    }
+
+    fn receive_brightness(&mut self) -> std::io::Result<()> {
+        if !self.profile.use_dada_packet {
+            self.log("brightness is not supported");
+            return Ok(());
+        }
+        let payload = self.read_dada_packet()?;
+        self.brightness = payload.first().copied().unwrap_or(self.brightness);
+        self.log(&format!("brightness {}", self.brightness));
+        Ok(())
+    }
+
+    fn send_bme_info(&mut self, pressure: Option<f32>) -> std::io::Result<()> {
+        if !self.profile.has_bme_sensor {
+            self.log("no bme sensor attached");
+            return Ok(());
+        }
+        self.send_reply(Reply::Bme {
+            temperature: 21.5,
+            humidity: 45.0,
+            pressure,
+        })
+    }
+
+    fn send_input_events(&mut self) -> std::io::Result<()> {
+        let events = std::mem::take(&mut *self.input_events.lock().unwrap());
+        for event in &events {
+            self.log(&format!("input {:?}", event));
+        }
+        self.send_reply(Reply::Input(events))
+    }
+
+    fn read_dada_packet(&mut self) -> std::io::Result<Vec<u8>> {
+        let mut len = [0u8; 4];
+        self.port.read_exact(&mut len[0..3])?;
+        let mut packet = vec![0u8; u32::from_le_bytes(len) as usize];
+        self.port.read_exact(&mut packet)?;
+
+        self.decoder.push(&len[0..3]);
+        self.decoder.push(&packet);
+        match self.decoder.next_packet() {
+            Some(Ok(payload)) => Ok(payload),
+            Some(Err(e)) => {
+                self.log(&format!("malformed dada packet: {}", e));
+                // start over with the next command
+                self.decoder = DadaPacketDecoder::default();
+                Ok(Vec::new())
+            }
+            None => Ok(Vec::new()),
+        }
+    }
+
+    // the same image continues where it stopped, another one starts over
+    fn receive_firmware_begin(&mut self) -> std::io::Result<()> {
+        let payload = self.read_dada_packet()?;
+        let (size, crc) = match payload.as_slice() {
+            [s0, s1, s2, s3, c0, c1, c2, c3] => (
+                u32::from_le_bytes([*s0, *s1, *s2, *s3]),
+                u32::from_le_bytes([*c0, *c1, *c2, *c3]),
+            ),
+            _ => {
+                self.log("malformed firmware upload start");
+                return Ok(());
+            }
+        };
+        if size != self.firmware.size || crc != self.firmware.crc {
+            self.firmware = FirmwareUpload {
+                size,
+                crc,
+                image: Vec::with_capacity(size as usize),
+            };
+            self.log(&format!("firmware upload of {} bytes", size));
+        } else {
+            self.log(&format!(
+                "firmware upload resumed at {}",
+                self.firmware.image.len()
+            ));
+        }
+        self.send_reply(Reply::FirmwareOffset(self.firmware.image.len() as u32))
+    }
+
+    fn receive_firmware_chunk(&mut self) -> std::io::Result<()> {
+        let payload = self.read_dada_packet()?;
+        let expected = self.firmware.image.len();
+        match payload.get(..8) {
+            Some(header) => {
+                let offset = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+                let crc = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+                let data = &payload[8..];
+                if offset as usize == expected
+                    && crc32fast::hash(data) == crc
+                    && expected + data.len() <= self.firmware.size as usize
+                {
+                    self.firmware.image.extend_from_slice(data);
+                } else {
+                    self.log(&format!("rejected firmware chunk at {}", offset));
+                }
+            }
+            None => self.log("malformed firmware chunk"),
+        }
+        self.send_reply(Reply::FirmwareOffset(self.firmware.image.len() as u32))
+    }
+
+    fn verify_firmware(&mut self) -> std::io::Result<()> {
+        let verified = self.firmware.image.len() == self.firmware.size as usize
+            && crc32fast::hash(&self.firmware.image) == self.firmware.crc;
+        self.log(&format!(
+            "firmware {}",
+            if verified { "verified" } else { "broken" }
+        ));
+        if let (true, Some(output_dir)) = (verified, &self.output_dir) {
+            let path = output_dir.join(format!("{}_firmware.bin", self.profile.name));
+            if let Err(e) = std::fs::write(&path, &self.firmware.image) {
+                eprintln!("Could not write {:?}: {:?}", path, e);
+            }
+        }
+        self.send_reply(Reply::FirmwareVerified(verified))
+    }
+
+    fn receive_encoding(&mut self) -> std::io::Result<()> {
+        let payload = self.read_dada_packet()?;
+        match payload.first() {
+            Some(encoding) if self.profile.capabilities().supports_encoding(*encoding) => {
+                self.encoding = *encoding;
+                self.log(&format!("encoding {}", self.encoding));
+            }
+            _ => self.log(&format!("unsupported encoding {:?}", payload)),
+        }
+        Ok(())
+    }
+
+    fn decode(&self, payload: &[u8], width: u32, height: u32) -> Option<image::DynamicImage> {
+        let format = match self.encoding {
+            ENCODING_GRAY4 | ENCODING_GRAY4_RLE => {
+                return Some(image::DynamicImage::ImageLuma8(convert_from_gray_scale(
+                    payload, width, height,
+                )))
+            }
+            ENCODING_RGB565 => {
+                return Some(image::DynamicImage::ImageRgb8(convert_from_rgb565(
+                    payload, width, height,
+                )))
+            }
+            ENCODING_JPEG => image::ImageFormat::Jpeg,
+            ENCODING_QOI => image::ImageFormat::Qoi,
+            _ => image::ImageFormat::WebP,
+        };
+        image::load_from_memory_with_format(payload, format).ok()
+    }
+
+    fn dump_frame(&self) {
+        if let Some(output_dir) = &self.output_dir {
+            let path = output_dir.join(format!("{}_{:06}.png", self.profile.name, self.frames));
+            match self.screen.save(&path) {
+                Ok(_) => {}
+                Err(e) => eprintln!("Could not write {:?}: {:?}", path, e),
+            }
+        }
+    }
+}
+
+fn usage() {
+    eprintln!("usage: device_emulator <teensy|esp32> [output directory] [--tcp <port>] [--legacy]");
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let profile = match PROFILES
+        .iter()
+        .find(|profile| Some(&profile.name.to_string()) == args.get(1))
+    {
+        Some(profile) => profile,
+        None => return usage(),
+    };
+
+    let mut output_dir = None;
+    let mut tcp_port = None;
+    let mut legacy = false;
+    let mut options = args.iter().skip(2);
+    while let Some(option) = options.next() {
+        if option == "--tcp" {
+            tcp_port = options.next().and_then(|port| port.parse::<u16>().ok());
+            if tcp_port.is_none() {
+                return usage();
+            }
+        } else if option == "--legacy" {
+            legacy = true;
+        } else {
+            output_dir = Some(PathBuf::from(option));
+        }
+    }
+    if let Some(output_dir) = &output_dir {
+        if let Err(e) = std::fs::create_dir_all(output_dir) {
+            eprintln!("Could not create {:?}: {:?}", output_dir, e);
+            return;
+        }
+    }
+
+    let input_events = Arc::new(Mutex::new(Vec::new()));
+    if profile.has_buttons {
+        read_input_events(input_events.clone());
+    }
+    match tcp_port {
+        Some(port) => serve_tcp(profile, port, output_dir, legacy, input_events),
+        None => serve_pty(profile, output_dir, legacy, input_events),
+    }
+}
+
+fn read_input_events(input_events: Arc<Mutex<Vec<InputEvent>>>) {
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lock().lines().map_while(Result::ok) {
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            let event = match parts.as_slice() {
+                ["press", button] => button.parse().ok().map(InputEvent::Press),
+                ["long", button] => button.parse().ok().map(InputEvent::LongPress),
+                ["rotate", encoder, steps] => match (encoder.parse(), steps.parse()) {
+                    (Ok(encoder), Ok(steps)) => Some(InputEvent::Rotate { encoder, steps }),
+                    _ => None,
+                },
+                _ => None,
+            };
+            match event {
+                Some(event) => input_events.lock().unwrap().push(event),
+                None => eprintln!("unknown input {:?}", line),
+            }
+        }
+    });
+}
+
+// serves one host after another, like the companion does on wifi
+fn serve_tcp(
+    profile: &'static Profile,
+    port: u16,
+    output_dir: Option<PathBuf>,
+    legacy: bool,
+    input_events: Arc<Mutex<Vec<InputEvent>>>,
+) {
+    let listener = match TcpListener::bind(("0.0.0.0", port)) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("Could not listen on port {}: {:?}", port, e);
+            return;
+        }
+    };
+    println!("{} listening on tcp port {}", profile.name, port);
+
+    let mut frames = 0;
+    let mut firmware = FirmwareUpload::default();
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let mut emulator = Emulator::new(
+                    profile,
+                    stream,
+                    output_dir.clone(),
+                    legacy,
+                    input_events.clone(),
+                );
+                emulator.frames = frames;
+                emulator.firmware = firmware;
+                emulator.log("host connected");
+                if let Err(e) = emulator.run() {
+                    emulator.log(&format!("host disconnected: {:?}", e));
+                }
+                frames = emulator.frames;
+                firmware = std::mem::take(&mut emulator.firmware);
+            }
+            Err(e) => eprintln!("Could not accept connection: {:?}", e),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn serve_pty(
+    profile: &'static Profile,
+    output_dir: Option<PathBuf>,
+    legacy: bool,
+    input_events: Arc<Mutex<Vec<InputEvent>>>,
+) {
+    let pty = match pty::Pty::open() {
+        Ok(pty) => pty,
+        Err(e) => {
+            eprintln!("Could not open pseudo terminal: {:?}", e);
+            return;
+        }
+    };
+    println!("{} listening on {}", profile.name, pty.slave_name());
+
+    let port = match pty.master.try_clone() {
+        Ok(port) => port,
+        Err(e) => {
+            eprintln!("Could not clone pseudo terminal: {:?}", e);
+            return;
+        }
+    };
+    match Emulator::new(profile, port, output_dir, legacy, input_events).run() {
+        Ok(_) => {}
+        Err(e) => eprintln!("Emulator stopped: {:?}", e),
+    }
+}
+
+#[cfg(not(unix))]
+fn serve_pty(
+    _profile: &'static Profile,
+    _output_dir: Option<PathBuf>,
+    _legacy: bool,
+    _input_events: Arc<Mutex<Vec<InputEvent>>>,
+) {
+    eprintln!("Pseudo terminals are only available on unix, use --tcp <port> instead.");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dada_packet::DadaPacket;
+    use dirty_rect::{region_payload, Rect};
+    use std::{io::Cursor, path::Path};
+
+    // what the host sent and everything the emulator answered
+    struct Link {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Read for Link {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for Link {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.output.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn directory(name: &str) -> PathBuf {
+        let directory =
+            std::env::temp_dir().join(format!("device_emulator_{}_{}", name, std::process::id()));
+        std::fs::remove_dir_all(&directory).ok();
+        std::fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    // runs until everything sent is read
+    fn emulate(
+        profile: &'static Profile,
+        sent: Vec<u8>,
+        output_dir: Option<&Path>,
+    ) -> Emulator<Link> {
+        let link = Link {
+            input: Cursor::new(sent),
+            output: Vec::new(),
+        };
+        let mut emulator = Emulator::new(
+            profile,
+            link,
+            output_dir.map(Path::to_path_buf),
+            false,
+            Arc::new(Mutex::new(Vec::new())),
+        );
+        let error = emulator.run().unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::UnexpectedEof);
+        emulator
+    }
+
+    fn command(command: Command) -> Vec<u8> {
+        let mut bytes = vec![command.id()];
+        bytes.extend(command.payload().unwrap_or_default());
+        bytes
+    }
+
+    fn replies(emulator: &Emulator<Link>) -> Vec<Vec<u8>> {
+        DadaPacketDecoder::default()
+            .decode(&emulator.port.output)
+            .into_iter()
+            .map(Result::unwrap)
+            .collect()
+    }
+
+    #[test]
+    fn teensy_dumps_gray_scale_frames() {
+        let directory = directory("teensy");
+        let mut frame = vec![0xf0; 256 * 64 / 2];
+        frame[0] = 0x0f;
+        let mut sent = command(Command::Reset);
+        sent.extend(command(Command::Frame {
+            data: &frame,
+            use_dada_packet: false,
+        }));
+        sent.extend(command(Command::KeepAlive));
+        sent.extend(command(Command::KeepAlive));
+
+        let emulator = emulate(&PROFILES[0], sent, Some(&directory));
+        assert!(emulator.awake);
+        assert_eq!(emulator.frames, 1);
+        assert_eq!(emulator.keep_alives, 2);
+
+        let png = image::open(directory.join("teensy_000001.png"))
+            .unwrap()
+            .to_rgb8();
+        assert_eq!(png.dimensions(), (256, 64));
+        assert_eq!(png.get_pixel(0, 0), &image::Rgb([0, 0, 0]));
+        assert_eq!(png.get_pixel(1, 0), &image::Rgb([255, 255, 255]));
+        assert_eq!(png.get_pixel(2, 0), &image::Rgb([255, 255, 255]));
+        std::fs::remove_dir_all(&directory).ok();
+    }
+
+    #[test]
+    fn teensy_answers_bme_requests() {
+        let emulator = emulate(&PROFILES[0], command(Command::ReadBme), None);
+        let reply = Command::ReadBme.parse_reply(&emulator.port.output).unwrap();
+        assert_eq!(
+            reply,
+            Reply::Bme {
+                temperature: 21.5,
+                humidity: 45.0,
+                pressure: None,
+            }
+        );
+
+        // the esp32 has no sensor and stays silent
+        let emulator = emulate(&PROFILES[1], command(Command::ReadBme), None);
+        assert!(emulator.port.output.is_empty());
+    }
+
+    #[test]
+    fn esp32_reads_brightness_from_dada_packets() {
+        // a brightness of 65 is an "A" and escaped in the packet
+        let mut sent = command(Command::Brightness(65));
+        sent.extend(command(Command::StandBy));
+
+        let emulator = emulate(&PROFILES[1], sent, None);
+        assert_eq!(emulator.brightness, 65);
+        assert!(!emulator.awake);
+
+        // the teensy has no brightness command
+        let emulator = emulate(&PROFILES[0], vec![Command::BRIGHTNESS], None);
+        assert_eq!(emulator.brightness, 255);
+    }
+
+    #[test]
+    fn teensy_decompresses_frames() {
+        let frame = vec![0x11; 256 * 64 / 2];
+        let compressed = convert_image::encode_rle(&frame);
+        let sent = command(Command::CompressedFrame(&compressed));
+
+        let emulator = emulate(&PROFILES[0], sent, None);
+        assert_eq!(emulator.frames, 1);
+        assert_eq!(
+            emulator.screen.get_pixel(255, 63),
+            &image::Rgb([17, 17, 17])
+        );
+    }
+
+    #[test]
+    fn esp32_acknowledges_sequenced_frames() {
+        let frame = convert_image::convert_to_rgb565(&vec![255; 320 * 170 * 3]);
+        let mut sent = command(Command::Capabilities);
+        sent.extend(command(Command::SetEncoding(ENCODING_RGB565)));
+        sent.extend(command(Command::SequencedFrame {
+            sequence: 5,
+            data: &frame,
+        }));
+        // a packet with a broken checksum is answered with a nack
+        sent.push(Command::SEQUENCED_FRAME);
+        sent.extend(DadaPacket::new(vec![6, 0, 1, 2, 3, 4, 5]).as_bytes());
+
+        let emulator = emulate(&PROFILES[1], sent, None);
+        let replies = replies(&emulator);
+        assert_eq!(replies.len(), 3);
+        assert_eq!(
+            Command::Capabilities.parse_reply(&replies[0]).unwrap(),
+            Reply::Capabilities(PROFILES[1].capabilities())
+        );
+        let frame = Command::SequencedFrame {
+            sequence: 0,
+            data: &[],
+        };
+        assert_eq!(frame.parse_reply(&replies[1]).unwrap(), Reply::Ack(5));
+        assert_eq!(frame.parse_reply(&replies[2]).unwrap(), Reply::Nack(6));
+        assert_eq!(emulator.frames, 1);
+        assert_eq!(
+            emulator.screen.get_pixel(319, 169),
+            &image::Rgb([255, 255, 255])
+        );
+    }
+
+    #[test]
+    fn esp32_draws_regions_onto_the_last_frame() {
+        let rect = Rect {
+            x: 10,
+            y: 20,
+            width: 4,
+            height: 2,
+        };
+        let region = convert_image::convert_to_rgb565(&[255; 4 * 2 * 3]);
+        let mut sent = command(Command::SetEncoding(ENCODING_RGB565));
+        sent.extend(command(Command::Region(&region_payload(&rect, &region))));
+
+        let emulator = emulate(&PROFILES[1], sent, None);
+        assert_eq!(emulator.frames, 1);
+        let white = image::Rgb([255, 255, 255]);
+        assert_eq!(emulator.screen.get_pixel(10, 20), &white);
+        assert_eq!(emulator.screen.get_pixel(13, 21), &white);
+        assert_eq!(emulator.screen.get_pixel(14, 21), &image::Rgb([0, 0, 0]));
+        assert_eq!(emulator.screen.get_pixel(13, 22), &image::Rgb([0, 0, 0]));
+    }
+
+    #[test]
+    fn legacy_firmware_ignores_the_handshake() {
+        let mut emulator = Emulator::new(
+            &PROFILES[1],
+            Link {
+                input: Cursor::new(command(Command::Capabilities)),
+                output: Vec::new(),
+            },
+            None,
+            true,
+            Arc::new(Mutex::new(Vec::new())),
+        );
+        assert!(emulator.run().is_err());
+        assert!(emulator.port.output.is_empty());
+    }
+
+    #[test]
+    fn typed_input_is_handed_out_once() {
+        let input_events = Arc::new(Mutex::new(vec![
+            InputEvent::Press(1),
+            InputEvent::Rotate {
+                encoder: 0,
+                steps: -2,
+            },
+        ]));
+        let mut sent = command(Command::ReadInput);
+        sent.extend(command(Command::ReadInput));
+        let mut emulator = Emulator::new(
+            &PROFILES[0],
+            Link {
+                input: Cursor::new(sent),
+                output: Vec::new(),
+            },
+            None,
+            false,
+            input_events.clone(),
+        );
+        assert!(emulator.run().is_err());
+
+        let replies = replies(&emulator);
+        assert_eq!(
+            Command::ReadInput.parse_reply(&replies[0]).unwrap(),
+            Reply::Input(vec![
+                InputEvent::Press(1),
+                InputEvent::Rotate {
+                    encoder: 0,
+                    steps: -2
+                }
+            ])
+        );
+        assert_eq!(
+            Command::ReadInput.parse_reply(&replies[1]).unwrap(),
+            Reply::Input(Vec::new())
+        );
+        assert!(input_events.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn firmware_upload_is_verified_and_saved() {
+        let directory = directory("firmware");
+        let image: Vec<u8> = (0..100u8).collect();
+        let crc = crc32fast::hash(&image);
+        let mut sent = command(Command::FirmwareBegin { size: 100, crc });
+        sent.extend(command(Command::FirmwareChunk {
+            offset: 0,
+            data: &image[..60],
+        }));
+        // a chunk at the wrong offset is rejected
+        sent.extend(command(Command::FirmwareChunk {
+            offset: 70,
+            data: &image[70..],
+        }));
+        sent.extend(command(Command::FirmwareChunk {
+            offset: 60,
+            data: &image[60..],
+        }));
+        sent.extend(command(Command::FirmwareVerify));
+
+        let emulator = emulate(&PROFILES[1], sent, Some(&directory));
+        let offsets: Vec<Reply> = replies(&emulator)[..4]
+            .iter()
+            .map(|reply| {
+                Command::FirmwareChunk {
+                    offset: 0,
+                    data: &[],
+                }
+                .parse_reply(reply)
+                .unwrap()
+            })
+            .collect();
+        assert_eq!(
+            offsets,
+            vec![
+                Reply::FirmwareOffset(0),
+                Reply::FirmwareOffset(60),
+                Reply::FirmwareOffset(60),
+                Reply::FirmwareOffset(100)
+            ]
+        );
+        assert_eq!(
+            Command::FirmwareVerify
+                .parse_reply(&replies(&emulator)[4])
+                .unwrap(),
+            Reply::FirmwareVerified(true)
+        );
+        assert_eq!(
+            std::fs::read(directory.join("esp32_firmware.bin")).unwrap(),
+            image
+        );
+        std::fs::remove_dir_all(&directory).ok();
+    }
+}
@@ -0,0 +1,56 @@
+use std::{
+    ffi::CStr,
+    fs::File,
+    io,
+    os::unix::io::{AsRawFd, FromRawFd},
+};
+
+pub struct Pty {
+    pub master: File,
+    // the slave side is kept open, so the master does not run into EIO
+    // while the host closes and reopens the port
+    slave: File,
+}
+
+impl Pty {
+    pub fn open() -> io::Result<Pty> {
+        let mut master: libc::c_int = 0;
+        let mut slave: libc::c_int = 0;
+        unsafe {
+            if libc::openpty(
+                &mut master,
+                &mut slave,
+                std::ptr::null_mut(),
+                std::ptr::null(),
+                std::ptr::null(),
+            ) != 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+
+            // raw mode, otherwise the line discipline eats our command bytes
+            let mut termios: libc::termios = std::mem::zeroed();
+            if libc::tcgetattr(slave, &mut termios) == 0 {
+                libc::cfmakeraw(&mut termios);
+                libc::tcsetattr(slave, libc::TCSANOW, &termios);
+            }
+
+            Ok(Pty {
+                master: File::from_raw_fd(master),
+                slave: File::from_raw_fd(slave),
+            })
+        }
+    }
+
+    pub fn slave_name(&self) -> String {
+        let mut buffer = [0 as libc::c_char; 256];
+        unsafe {
+            if libc::ttyname_r(self.slave.as_raw_fd(), buffer.as_mut_ptr(), buffer.len()) != 0 {
+                return String::new();
+            }
+            CStr::from_ptr(buffer.as_ptr())
+                .to_string_lossy()
+                .into_owned()
+        }
+    }
+}
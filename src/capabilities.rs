@@ -0,0 +1,75 @@
+// Answer of a device to the capabilities command (232), sent as dada packet.
+// Newer firmware may append fields, older hosts just ignore them.
+
+pub const PROTOCOL_VERSION: u8 = 1;
+
+pub const PIXEL_FORMAT_GRAY4: u8 = 0;
+pub const PIXEL_FORMAT_RGB888: u8 = 1;
+
+// supported frame encodings, bit mask
+pub const ENCODING_GRAY4: u8 = 1;
+pub const ENCODING_WEBP: u8 = 2;
+// run length encoded gray scale, see `convert_image::encode_rle`
+pub const ENCODING_GRAY4_RLE: u8 = 4;
+pub const ENCODING_RGB565: u8 = 8;
+pub const ENCODING_JPEG: u8 = 16;
+pub const ENCODING_QOI: u8 = 32;
+
+// optional features, bit mask
+pub const FEATURE_BME_SENSOR: u16 = 1;
+// buttons or a rotary encoder, see `Command::ReadInput`
+pub const FEATURE_BUTTONS: u16 = 2;
+pub const FEATURE_BRIGHTNESS: u16 = 4;
+pub const FEATURE_SEQUENCED_FRAMES: u16 = 8;
+pub const FEATURE_PARTIAL_UPDATES: u16 = 16;
+// the bme sensor can be read with pressure, see `Command::ReadEnvironment`
+pub const FEATURE_PRESSURE: u16 = 32;
+// firmware images can be uploaded over the link, see `firmware`
+pub const FEATURE_FIRMWARE_UPDATE: u16 = 64;
+
+const CAPABILITIES_LENGTH: usize = 9;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Capabilities {
+    pub version: u8,
+    pub width: u16,
+    pub height: u16,
+    pub pixel_format: u8,
+    pub encodings: u8,
+    pub features: u16,
+}
+
+impl Capabilities {
+    pub fn parse(payload: &[u8]) -> Option<Capabilities> {
+        if payload.len() < CAPABILITIES_LENGTH {
+            return None;
+        }
+        Some(Capabilities {
+            version: payload[0],
+            width: u16::from_le_bytes([payload[1], payload[2]]),
+            height: u16::from_le_bytes([payload[3], payload[4]]),
+            pixel_format: payload[5],
+            encodings: payload[6],
+            features: u16::from_le_bytes([payload[7], payload[8]]),
+        })
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(CAPABILITIES_LENGTH);
+        bytes.push(self.version);
+        bytes.extend_from_slice(&self.width.to_le_bytes());
+        bytes.extend_from_slice(&self.height.to_le_bytes());
+        bytes.push(self.pixel_format);
+        bytes.push(self.encodings);
+        bytes.extend_from_slice(&self.features.to_le_bytes());
+        bytes
+    }
+
+    pub fn supports_encoding(&self, encoding: u8) -> bool {
+        self.encodings & encoding != 0
+    }
+
+    pub fn has_feature(&self, feature: u16) -> bool {
+        self.features & feature != 0
+    }
+}
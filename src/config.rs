@@ -1,4 +1,7 @@
+use crate::helpers::{convert_image::Dithering, transform::Transform, transition::TransitionKind};
+use crate::protocol::InputEvent;
 use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, time::Duration};
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Config {
@@ -12,4 +15,533 @@ pub struct Config {
     pub current_date_screen_active: bool,
     pub brightness: u16,
     pub companion_brightness: u16,
+    #[serde(default = "default_devices")]
+    pub devices: Vec<DeviceConfig>,
+    // persists sensor readings, off if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sensor_log: Option<SensorLogConfig>,
+    #[serde(default)]
+    pub carousel: CarouselConfig,
+    #[serde(default)]
+    pub transition: TransitionConfig,
+    #[serde(default)]
+    pub marquee: MarqueeConfig,
+    // screens drawn by external programs
+    #[serde(default)]
+    pub plugins: Vec<PluginConfig>,
+}
+
+impl Config {
+    // the brightness of a device itself wins over the one of its role
+    pub fn device_brightness(&self, index: usize) -> u16 {
+        match self.devices.get(index) {
+            Some(DeviceConfig {
+                brightness: Some(brightness),
+                ..
+            }) => *brightness,
+            Some(device) if device.role == DisplayRole::Companion => self.companion_brightness,
+            _ => self.brightness,
+        }
+    }
+
+    // screens render for the first device of a role, the others get a resized frame
+    pub fn canvas_size(&self, role: DisplayRole) -> Option<(u32, u32)> {
+        self.devices
+            .iter()
+            .find(|device| device.role == role)
+            .map(|device| device.frame_size())
+    }
+}
+
+// switches through the enabled screens on its own
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct CarouselConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    // for screens without a dwell time of their own
+    #[serde(default = "default_dwell_seconds")]
+    pub dwell_seconds: u64,
+    // by screen key, e.g. "weather_screen"
+    #[serde(default)]
+    pub screen_dwell_seconds: HashMap<String, u64>,
+    // switching screens by hand pauses the carousel for this long
+    #[serde(default = "default_resume_seconds")]
+    pub resume_after_seconds: u64,
+}
+
+impl Default for CarouselConfig {
+    fn default() -> CarouselConfig {
+        CarouselConfig {
+            enabled: false,
+            dwell_seconds: default_dwell_seconds(),
+            screen_dwell_seconds: HashMap::new(),
+            resume_after_seconds: default_resume_seconds(),
+        }
+    }
+}
+
+impl CarouselConfig {
+    pub fn dwell_time(&self, screen: &str) -> Duration {
+        let seconds = self
+            .screen_dwell_seconds
+            .get(screen)
+            .copied()
+            .unwrap_or(self.dwell_seconds);
+        Duration::from_secs(seconds.max(1))
+    }
+
+    pub fn resume_after(&self) -> Duration {
+        Duration::from_secs(self.resume_after_seconds)
+    }
+}
+
+// how screens are switched, per role
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TransitionConfig {
+    #[serde(default = "default_main_transition")]
+    pub main: TransitionKind,
+    #[serde(default = "default_companion_transition")]
+    pub companion: TransitionKind,
+    #[serde(default = "default_transition_ms")]
+    pub duration_ms: u64,
+}
+
+impl Default for TransitionConfig {
+    fn default() -> TransitionConfig {
+        TransitionConfig {
+            main: default_main_transition(),
+            companion: default_companion_transition(),
+            duration_ms: default_transition_ms(),
+        }
+    }
+}
+
+impl TransitionConfig {
+    pub fn duration(&self) -> Duration {
+        Duration::from_millis(self.duration_ms)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum MarqueeMode {
+    // the start follows the end of the text after a gap
+    Loop,
+    // scrolls to the end of the text and back
+    PingPong,
+}
+
+// scrolling of text, which is wider than its place on a display
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct MarqueeConfig {
+    // layout pixels per second
+    #[serde(default = "default_marquee_speed")]
+    pub speed: u32,
+    // at the start of every pass
+    #[serde(default = "default_marquee_pause_ms")]
+    pub pause_ms: u64,
+    #[serde(default = "default_marquee_mode")]
+    pub mode: MarqueeMode,
+    // layout pixels between the end and the start in the loop mode
+    #[serde(default = "default_marquee_gap")]
+    pub gap: u32,
+}
+
+impl Default for MarqueeConfig {
+    fn default() -> MarqueeConfig {
+        MarqueeConfig {
+            speed: default_marquee_speed(),
+            pause_ms: default_marquee_pause_ms(),
+            mode: default_marquee_mode(),
+            gap: default_marquee_gap(),
+        }
+    }
+}
+
+impl MarqueeConfig {
+    pub fn pause(&self) -> Duration {
+        Duration::from_millis(self.pause_ms)
+    }
+}
+
+// a screen drawn by an external program, see `screens::plugin_screen`
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PluginConfig {
+    // shown in the list of screens
+    pub name: String,
+    // unique among all screens, e.g. "plugin_clock"
+    pub key: String,
+    pub command: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default = "default_plugin_active")]
+    pub active: bool,
+    // how often the plugin is asked to render while its screen is shown
+    #[serde(default = "default_plugin_interval_ms")]
+    pub interval_ms: u64,
+    // a plugin, which does not answer a render request in time, is restarted
+    #[serde(default = "default_plugin_timeout_ms")]
+    pub timeout_ms: u64,
+}
+
+impl PluginConfig {
+    pub fn interval(&self) -> Duration {
+        Duration::from_millis(self.interval_ms)
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_ms)
+    }
+}
+
+// which screen buffer is shown on a device
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum DisplayRole {
+    Main,
+    Companion,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum DeviceImageFormat {
+    // raw 4 bit gray scale, two pixels per byte
+    Bmp,
+    WebP,
+    // raw, two bytes per pixel (little endian)
+    Rgb565,
+    Jpeg,
+    Qoi,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DeviceConfig {
+    pub name: String,
+    // usb vendor and product id (e.g. "16c00483") or a port name (e.g. "COM3")
+    pub identifier: String,
+    // tells identical devices (same vendor and product id) apart
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub serial_number: Option<String>,
+    pub baud: u32,
+    pub use_dada_packet: bool,
+    pub image_format: DeviceImageFormat,
+    pub width: u32,
+    pub height: u32,
+    pub role: DisplayRole,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub brightness: Option<u16>,
+    // gray scale devices only, if not set the current screen decides
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dithering: Option<Dithering>,
+    #[serde(default = "default_gamma")]
+    pub gamma: f32,
+    // the highest one, lowered while the link can't keep up with the target frame rate
+    #[serde(default = "default_jpeg_quality")]
+    pub jpeg_quality: u8,
+    #[serde(default = "default_target_fps")]
+    pub target_fps: f32,
+    // share of the time (in percent) the link may be busy writing frames
+    #[serde(default = "default_link_budget")]
+    pub link_budget: u8,
+    pub has_bme_sensor: bool,
+    #[serde(default)]
+    pub reliable_transfer: bool,
+    // only send the changed regions of a frame, needs firmware support
+    #[serde(default)]
+    pub partial_updates: bool,
+    #[serde(default)]
+    pub transport: TransportConfig,
+    // rotation, mirroring and offset on the panel, `width` and `height` are the ones of the panel
+    #[serde(default, skip_serializing_if = "Transform::is_identity")]
+    pub transform: Transform,
+    // buttons and encoders, only polled if the firmware reports them
+    #[serde(default = "default_input_bindings")]
+    pub inputs: Vec<InputBinding>,
+}
+
+impl DeviceConfig {
+    // size of the frame before the transform, turned by 90 or 270 degrees it's a portrait one
+    pub fn frame_size(&self) -> (u32, u32) {
+        if self.transform.rotation.swaps_sides() {
+            (self.height, self.width)
+        } else {
+            (self.width, self.height)
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum InputTrigger {
+    Press,
+    LongPress,
+    Rotate,
+}
+
+// what the host does for an input event of a device
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum InputAction {
+    // turned counter clockwise, the encoder goes the other way
+    NextScreen,
+    PreviousScreen,
+    // of the current screen, e.g. 1 is the volume mode of the media screen
+    ScreenMode(u32),
+    // of the device itself, in steps of 5 %
+    Brightness,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct InputBinding {
+    pub trigger: InputTrigger,
+    // of the button or encoder on the device
+    #[serde(default)]
+    pub index: u8,
+    pub action: InputAction,
+}
+
+impl InputBinding {
+    // how often the action is applied, negative for encoders turned counter clockwise
+    pub fn steps(&self, event: &InputEvent) -> Option<i16> {
+        match (self.trigger, event) {
+            (InputTrigger::Press, InputEvent::Press(button))
+            | (InputTrigger::LongPress, InputEvent::LongPress(button))
+                if *button == self.index =>
+            {
+                Some(1)
+            }
+            (InputTrigger::Rotate, InputEvent::Rotate { encoder, steps })
+                if *encoder == self.index =>
+            {
+                Some(*steps)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum SensorLogFormat {
+    Csv,
+    Sqlite,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SensorLogConfig {
+    pub format: SensorLogFormat,
+    pub path: String,
+    #[serde(default = "default_sampling_interval")]
+    pub interval_seconds: u64,
+    // older samples are removed, 0 keeps everything
+    #[serde(default = "default_retention_days")]
+    pub retention_days: u32,
+    // cpu and ram usage, as shown on the system info screen
+    #[serde(default)]
+    pub system_stats: bool,
+}
+
+// how a device is attached, see `sinks`
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum TransportConfig {
+    #[default]
+    Serial,
+    Tcp {
+        host: String,
+        port: u16,
+    },
+    // writes every received frame as png into the given directory
+    File {
+        path: String,
+    },
+    Null,
+}
+
+pub fn default_sampling_interval() -> u64 {
+    60
+}
+
+pub fn default_retention_days() -> u32 {
+    90
+}
+
+pub fn default_dwell_seconds() -> u64 {
+    15
+}
+
+pub fn default_resume_seconds() -> u64 {
+    60
+}
+
+pub fn default_main_transition() -> TransitionKind {
+    TransitionKind::Dissolve
+}
+
+pub fn default_companion_transition() -> TransitionKind {
+    TransitionKind::Crossfade
+}
+
+pub fn default_transition_ms() -> u64 {
+    400
+}
+
+pub fn default_marquee_speed() -> u32 {
+    30
+}
+
+pub fn default_marquee_pause_ms() -> u64 {
+    1500
+}
+
+pub fn default_marquee_mode() -> MarqueeMode {
+    MarqueeMode::Loop
+}
+
+pub fn default_marquee_gap() -> u32 {
+    32
+}
+
+pub fn default_plugin_active() -> bool {
+    true
+}
+
+pub fn default_plugin_interval_ms() -> u64 {
+    1000
+}
+
+pub fn default_plugin_timeout_ms() -> u64 {
+    5000
+}
+
+pub fn default_gamma() -> f32 {
+    2.2
+}
+
+pub fn default_jpeg_quality() -> u8 {
+    85
+}
+
+// the screens are rendered at the highest target frame rate of all devices
+pub fn default_target_fps() -> f32 {
+    4.0
+}
+
+pub fn default_link_budget() -> u8 {
+    80
+}
+
+// first button and encoder switch screens and change the brightness
+pub fn default_input_bindings() -> Vec<InputBinding> {
+    vec![
+        InputBinding {
+            trigger: InputTrigger::Press,
+            index: 0,
+            action: InputAction::NextScreen,
+        },
+        InputBinding {
+            trigger: InputTrigger::LongPress,
+            index: 0,
+            action: InputAction::PreviousScreen,
+        },
+        InputBinding {
+            trigger: InputTrigger::Rotate,
+            index: 0,
+            action: InputAction::Brightness,
+        },
+    ]
+}
+
+pub fn default_devices() -> Vec<DeviceConfig> {
+    vec![
+        DeviceConfig {
+            name: String::from("teensy"),
+            identifier: String::from("16c00483"),
+            serial_number: None,
+            baud: 4608000,
+            use_dada_packet: false,
+            image_format: DeviceImageFormat::Bmp,
+            width: 256,
+            height: 64,
+            role: DisplayRole::Main,
+            brightness: None,
+            dithering: None,
+            gamma: default_gamma(),
+            jpeg_quality: default_jpeg_quality(),
+            target_fps: default_target_fps(),
+            link_budget: default_link_budget(),
+            has_bme_sensor: true,
+            reliable_transfer: false,
+            partial_updates: false,
+            transport: TransportConfig::Serial,
+            transform: Transform::default(),
+            inputs: default_input_bindings(),
+        },
+        DeviceConfig {
+            name: String::from("esp32"),
+            identifier: String::from("303a1001"),
+            serial_number: None,
+            baud: 921600,
+            use_dada_packet: true,
+            image_format: DeviceImageFormat::WebP,
+            width: 320,
+            height: 170,
+            role: DisplayRole::Companion,
+            brightness: None,
+            dithering: None,
+            gamma: default_gamma(),
+            jpeg_quality: default_jpeg_quality(),
+            target_fps: default_target_fps(),
+            link_budget: default_link_budget(),
+            has_bme_sensor: false,
+            reliable_transfer: false,
+            partial_updates: false,
+            transport: TransportConfig::Serial,
+            transform: Transform::default(),
+            inputs: default_input_bindings(),
+        },
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn binding(trigger: InputTrigger, index: u8) -> InputBinding {
+        InputBinding {
+            trigger,
+            index,
+            action: InputAction::NextScreen,
+        }
+    }
+
+    #[test]
+    fn bindings_match_trigger_and_index() {
+        let press = binding(InputTrigger::Press, 1);
+        assert_eq!(press.steps(&InputEvent::Press(1)), Some(1));
+        assert_eq!(press.steps(&InputEvent::Press(0)), None);
+        assert_eq!(press.steps(&InputEvent::LongPress(1)), None);
+
+        let long_press = binding(InputTrigger::LongPress, 0);
+        assert_eq!(long_press.steps(&InputEvent::LongPress(0)), Some(1));
+        assert_eq!(long_press.steps(&InputEvent::Press(0)), None);
+
+        let rotate = binding(InputTrigger::Rotate, 2);
+        let turned = |encoder, steps| InputEvent::Rotate { encoder, steps };
+        assert_eq!(rotate.steps(&turned(2, -3)), Some(-3));
+        assert_eq!(rotate.steps(&turned(1, 3)), None);
+        assert_eq!(rotate.steps(&InputEvent::Press(2)), None);
+    }
+
+    #[test]
+    fn bindings_are_read_from_the_settings() {
+        let bindings: Vec<InputBinding> = serde_json::from_str(
+            r#"[{"trigger": "long_press", "action": "previous_screen"},
+                {"trigger": "press", "index": 2, "action": {"screen_mode": 1}}]"#,
+        )
+        .unwrap();
+        assert_eq!(bindings[0].index, 0);
+        assert_eq!(bindings[0].trigger, InputTrigger::LongPress);
+        assert_eq!(bindings[1].action, InputAction::ScreenMode(1));
+    }
 }
@@ -1,4 +1,4 @@
-use crate::config::Config;
+use crate::config::{default_devices, Config};
 
 use serde::{Deserialize, Serialize};
 
@@ -24,6 +24,12 @@ impl ConfigManager {
             current_date_screen_active: true,
             brightness: 100,
             companion_brightness: 100,
+            devices: default_devices(),
+            sensor_log: None,
+            carousel: Default::default(),
+            transition: Default::default(),
+            marquee: Default::default(),
+            plugins: Vec::new(),
         };
         let mut this = ConfigManager {
             config,
@@ -34,7 +40,11 @@ impl ConfigManager {
         match contents {
             Ok(config) => {
                 this.config_hash = config.clone();
-                this.config = serde_json::from_str(&config).unwrap_or(this.config);
+                // e.g. a rotation other than 0, 90, 180 or 270, the defaults are used then
+                match serde_json::from_str(&config) {
+                    Ok(config) => this.config = config,
+                    Err(e) => eprintln!("Invalid settings: {}", e),
+                }
             }
             Err(e) => {
                 eprintln!("Error: {:?}", e);
@@ -72,7 +82,11 @@ impl ConfigManager {
             "system_info_screen" => self.config.system_info_screen_active,
             "current_date_screen" => self.config.current_date_screen_active,
             "ice_screen" => self.config.ice_screen_active,
-            _ => false,
+            _ => self
+                .config
+                .plugins
+                .iter()
+                .any(|plugin| plugin.key == screen && plugin.active),
         }
     }
 
@@ -97,7 +111,13 @@ impl ConfigManager {
             "ice_screen" => {
                 self.config.ice_screen_active = enabled;
             }
-            _ => {}
+            _ => {
+                for plugin in self.config.plugins.iter_mut() {
+                    if plugin.key == screen {
+                        plugin.active = enabled;
+                    }
+                }
+            }
         }
     }
 
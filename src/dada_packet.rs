@@ -1,3 +1,7 @@
+// answers of the device to a sequenced packet, followed by the sequence number
+pub const ACK: u8 = 6;
+pub const NACK: u8 = 21;
+
 #[derive(Debug)]
 pub struct DadaPacket {
     start_bytes: Vec<u8>,
@@ -14,6 +18,16 @@ impl DadaPacket {
         }
     }
 
+    // payload layout for reliable transfers:
+    // 2 byte sequence number, data, crc32 of sequence number and data (all little endian)
+    pub fn with_sequence(sequence: u16, data: Vec<u8>) -> DadaPacket {
+        let mut payload = sequence.to_le_bytes().to_vec();
+        payload.extend(data);
+        let crc = crc32fast::hash(&payload);
+        payload.extend(crc.to_le_bytes());
+        DadaPacket::new(payload)
+    }
+
     pub fn as_bytes(&mut self) -> Vec<u8> {
         let mut returnable_vector: Vec<u8> = Vec::new();
 
@@ -51,3 +65,249 @@ impl DadaPacket {
         escaped_vec
     }
 }
+
+// every packet consists of the 3 byte length, the start and the end marker
+const LENGTH_BYTES: usize = 3;
+const MARKER_LENGTH: usize = 4;
+const DEFAULT_MAX_PACKET_LEN: usize = 1024 * 1024;
+
+#[derive(Debug, PartialEq)]
+pub enum DadaPacketError {
+    Garbage(usize),
+    InvalidLength(usize),
+    MissingEndMarker,
+    InvalidEscape,
+}
+
+impl std::fmt::Display for DadaPacketError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Garbage(count) => write!(f, "skipped {} bytes before start marker", count),
+            Self::InvalidLength(len) => write!(f, "invalid packet length {}", len),
+            Self::MissingEndMarker => write!(f, "end marker missing"),
+            Self::InvalidEscape => write!(f, "payload contains unescaped marker byte"),
+        }
+    }
+}
+
+impl std::error::Error for DadaPacketError {}
+
+// Streaming counterpart of `DadaPacket::as_bytes`: consumes arbitrary chunks of
+// a byte stream and hands out complete payloads as soon as they are available.
+pub struct DadaPacketDecoder {
+    buffer: Vec<u8>,
+    max_packet_len: usize,
+}
+
+impl Default for DadaPacketDecoder {
+    fn default() -> DadaPacketDecoder {
+        DadaPacketDecoder {
+            buffer: Vec::new(),
+            max_packet_len: DEFAULT_MAX_PACKET_LEN,
+        }
+    }
+}
+
+impl DadaPacketDecoder {
+    pub fn new(max_packet_len: usize) -> DadaPacketDecoder {
+        DadaPacketDecoder {
+            max_packet_len,
+            ..Default::default()
+        }
+    }
+
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    // pushes the bytes and returns every packet (or error) that got complete by them
+    pub fn decode(&mut self, bytes: &[u8]) -> Vec<Result<Vec<u8>, DadaPacketError>> {
+        self.push(bytes);
+        let mut packets = Vec::new();
+        while let Some(packet) = self.next_packet() {
+            packets.push(packet);
+        }
+        packets
+    }
+
+    pub fn next_packet(&mut self) -> Option<Result<Vec<u8>, DadaPacketError>> {
+        let header_len = LENGTH_BYTES + MARKER_LENGTH;
+        if self.buffer.len() < header_len {
+            return None;
+        }
+
+        // resynchronise on the start marker, everything in front of it is garbage
+        if !self.buffer[LENGTH_BYTES..].starts_with(b"DADA") {
+            let skipped = match self.buffer[LENGTH_BYTES..]
+                .windows(MARKER_LENGTH)
+                .position(|window| window == b"DADA")
+            {
+                Some(position) => position,
+                // keep the tail, it might be the beginning of the next packet
+                None => self.buffer.len() - (header_len - 1),
+            };
+            self.buffer.drain(..skipped);
+            return Some(Err(DadaPacketError::Garbage(skipped)));
+        }
+
+        let mut len_as_bytes = [0u8; 4];
+        len_as_bytes[..LENGTH_BYTES].copy_from_slice(&self.buffer[..LENGTH_BYTES]);
+        let len = u32::from_le_bytes(len_as_bytes) as usize;
+        if len < 2 * MARKER_LENGTH || len > self.max_packet_len {
+            // drop the first byte, so the next call searches for a new start marker
+            self.buffer.remove(0);
+            return Some(Err(DadaPacketError::InvalidLength(len)));
+        }
+
+        if self.buffer.len() < LENGTH_BYTES + len {
+            return None;
+        }
+
+        let packet: Vec<u8> = self.buffer.drain(..LENGTH_BYTES + len).collect();
+        if !packet.ends_with(b"ADAD") {
+            // the length was wrong, so give the bytes behind the start marker another chance
+            self.buffer
+                .splice(0..0, packet[LENGTH_BYTES + MARKER_LENGTH..].iter().copied());
+            return Some(Err(DadaPacketError::MissingEndMarker));
+        }
+
+        Some(unescape_bytes(
+            &packet[LENGTH_BYTES + MARKER_LENGTH..packet.len() - MARKER_LENGTH],
+        ))
+    }
+}
+
+// counterpart of `DadaPacket::with_sequence`, returns none if the checksum does not match
+pub fn split_sequenced_payload(payload: &[u8]) -> Option<(u16, &[u8])> {
+    if payload.len() < 6 {
+        return None;
+    }
+    let (content, crc) = payload.split_at(payload.len() - 4);
+    if crc32fast::hash(content).to_le_bytes() != crc {
+        return None;
+    }
+    Some((u16::from_le_bytes([content[0], content[1]]), &content[2..]))
+}
+
+fn unescape_bytes(escaped_bytes: &[u8]) -> Result<Vec<u8>, DadaPacketError> {
+    let mut payload = Vec::with_capacity(escaped_bytes.len());
+    let mut bytes = escaped_bytes.iter();
+
+    while let Some(byte) = bytes.next() {
+        // markers are escaped by doubling them
+        if (*byte == 65 || *byte == 68) && bytes.next() != Some(byte) {
+            return Err(DadaPacketError::InvalidEscape);
+        }
+        payload.push(*byte);
+    }
+    Ok(payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(payload: &[u8]) -> Vec<u8> {
+        DadaPacket::new(payload.to_vec()).as_bytes()
+    }
+
+    #[test]
+    fn round_trip_with_marker_bytes() {
+        let payloads: Vec<&[u8]> = vec![
+            b"DADA",
+            b"ADAD",
+            b"A",
+            b"D",
+            b"DADAADADAD",
+            b"xxDADAxxADADxx",
+            &[0, 65, 68, 255, 68, 65, 0],
+        ];
+        for payload in payloads {
+            let mut decoder = DadaPacketDecoder::default();
+            assert_eq!(decoder.decode(&encode(payload)), vec![Ok(payload.to_vec())]);
+        }
+    }
+
+    #[test]
+    fn round_trip_empty_payload() {
+        let mut decoder = DadaPacketDecoder::default();
+        assert_eq!(decoder.decode(&encode(&[])), vec![Ok(Vec::new())]);
+    }
+
+    #[test]
+    fn round_trip_byte_by_byte() {
+        let mut stream = encode(b"first DADA");
+        stream.extend(encode(b"second ADAD"));
+        stream.extend(encode(&[65; 300]));
+
+        let mut decoder = DadaPacketDecoder::default();
+        let mut packets = Vec::new();
+        for byte in stream {
+            packets.extend(decoder.decode(&[byte]));
+        }
+        assert_eq!(
+            packets,
+            vec![
+                Ok(b"first DADA".to_vec()),
+                Ok(b"second ADAD".to_vec()),
+                Ok(vec![65; 300]),
+            ]
+        );
+    }
+
+    #[test]
+    fn resync_after_garbage() {
+        let mut stream = vec![1, 2, 3, 4, 5];
+        stream.extend(encode(b"payload"));
+
+        let mut decoder = DadaPacketDecoder::default();
+        assert_eq!(
+            decoder.decode(&stream),
+            vec![Err(DadaPacketError::Garbage(5)), Ok(b"payload".to_vec())]
+        );
+    }
+
+    #[test]
+    fn resync_after_truncated_packet() {
+        let truncated = encode(b"lost in transit");
+        let mut stream = truncated[..truncated.len() - 6].to_vec();
+        stream.extend(encode(b"payload"));
+
+        let mut decoder = DadaPacketDecoder::default();
+        let packets = decoder.decode(&stream);
+        assert_eq!(
+            packets.first(),
+            Some(&Err(DadaPacketError::MissingEndMarker))
+        );
+        assert_eq!(packets.last(), Some(&Ok(b"payload".to_vec())));
+    }
+
+    #[test]
+    fn maximum_length() {
+        let payload = vec![0; DEFAULT_MAX_PACKET_LEN - 2 * MARKER_LENGTH];
+        let mut decoder = DadaPacketDecoder::default();
+        assert_eq!(decoder.decode(&encode(&payload)), vec![Ok(payload)]);
+
+        let payload = vec![0; DEFAULT_MAX_PACKET_LEN - 2 * MARKER_LENGTH + 1];
+        let mut decoder = DadaPacketDecoder::default();
+        assert_eq!(
+            decoder.decode(&encode(&payload)).first(),
+            Some(&Err(DadaPacketError::InvalidLength(
+                DEFAULT_MAX_PACKET_LEN + 1
+            )))
+        );
+    }
+
+    #[test]
+    fn configured_maximum_length_counts_escaped_bytes() {
+        // 4 escaped marker bytes take 8 bytes on the wire
+        let mut decoder = DadaPacketDecoder::new(16);
+        assert_eq!(decoder.decode(&encode(b"DADA")), vec![Ok(b"DADA".to_vec())]);
+
+        let mut decoder = DadaPacketDecoder::new(16);
+        assert_eq!(
+            decoder.decode(&encode(b"DADAD")).first(),
+            Some(&Err(DadaPacketError::InvalidLength(18)))
+        );
+    }
+}
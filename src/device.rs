@@ -1,50 +1,167 @@
-use std::{sync::atomic::Ordering, thread};
+use std::{
+    sync::atomic::{AtomicU16, AtomicU32, AtomicU8, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
 
-use crossbeam_channel::{bounded, Receiver, Sender};
-use image::ImageFormat;
+use crossbeam_channel::{bounded, Receiver, Sender, TrySendError};
 
 use crate::{
-    dada_packet::DadaPacket,
-    helpers::{convert_image::convert_to_webp, display_serial_com::*},
-    CLOSE_REQUESTED, HIBERNATING, LAST_BME_INFO,
+    capabilities::*,
+    config::{DeviceConfig, DeviceImageFormat},
+    dada_packet::DadaPacketDecoder,
+    frame_pacer::{FramePacer, PacingStats},
+    helpers::{
+        convert_image::{
+            convert_to_jpeg, convert_to_qoi, convert_to_rgb565, convert_to_webp, encode_rle,
+        },
+        dirty_rect::{crop_rgb, dirty_rects, region_payload, Rect},
+        display_serial_com::*,
+    },
+    protocol::{Command, InputEvent, ProtocolError, Reply, ReplyFormat, MAX_COMPRESSED_FRAME_LEN},
+    sensor::SensorReading,
+    sinks::{open_sink, reconnect_delay, DisplaySink},
+    CLOSE_REQUESTED, HIBERNATING, SENSOR_HISTORY,
 };
+
+const MAX_RETRANSMISSIONS: u32 = 3;
+// replies are small, garbage with a huge length should not hold up the next request
+const MAX_REPLY_LEN: usize = 4096;
+// incompatible firmware is not asked again right away
+const INCOMPATIBLE_RETRY_DELAY: Duration = Duration::from_secs(30);
+// short enough, that a button press feels immediate
+const INPUT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+#[derive(Default)]
+pub struct DeliveryStats {
+    pub sent: AtomicU32,
+    pub acknowledged: AtomicU32,
+    pub retransmitted: AtomicU32,
+    pub failed: AtomicU32,
+}
+
+// a frame waiting for the writer of a device
+pub struct QueuedFrame {
+    pub data: Vec<u8>,
+    pub queued: Instant,
+    // transition frames are sent right away, not at the target fps
+    pub paced: bool,
+}
+
+impl QueuedFrame {
+    // screens are held back to the target fps of the device, transitions pass right away
+    fn wait_time(&self, pacer: &FramePacer) -> Duration {
+        if self.paced {
+            pacer.wait_time()
+        } else {
+            Duration::ZERO
+        }
+    }
+}
+
+// opens the transport of a device, `open_sink` unless a test brings its own
+type Connector = Box<dyn Fn(&DeviceConfig) -> Option<Box<dyn DisplaySink>> + Send + Sync>;
+
+// what the writer remembers between two frames
+struct Writer {
+    last_sum: u32,
+    last_frame: Vec<u8>,
+    brightness_set: bool,
+    pacer: FramePacer,
+}
+
+impl Writer {
+    fn new(config: &DeviceConfig) -> Writer {
+        Writer {
+            last_sum: 0,
+            last_frame: Vec::new(),
+            brightness_set: false,
+            pacer: FramePacer::new(config.target_fps, config.link_budget),
+        }
+    }
+}
+
 pub struct Device {
-    identifier: String,
-    baud: u32,
-    use_dada_packet: bool,
-    has_bme_sensor: bool,
+    pub config: DeviceConfig,
+    // frames are sent with sequence number and checksum and have to be acknowledged,
+    // switched off until the next connect, if firmware without handshake never answers
+    reliable_transfer: std::sync::atomic::AtomicBool,
+    sequence: AtomicU16,
+    pub delivery_stats: DeliveryStats,
+    pub pacing_stats: PacingStats,
+    // lowered by the writer, if the link can't keep up with the target frame rate
+    jpeg_quality: AtomicU8,
     background_workers_started: std::sync::atomic::AtomicBool,
     pub brightness: std::sync::atomic::AtomicU8,
-    pub image_format: ImageFormat,
-    pub sender: Sender<Vec<u8>>,
-    pub receiver: Receiver<Vec<u8>>,
+    sender: Sender<QueuedFrame>,
+    receiver: Receiver<QueuedFrame>,
     pub awake: std::sync::Mutex<bool>,
-    pub port: std::sync::Mutex<Option<Box<dyn serialport::SerialPort>>>,
+    pub port: std::sync::Mutex<Option<Box<dyn DisplaySink>>>,
+    connector: Connector,
     pub connected: std::sync::atomic::AtomicBool,
+    failed_connects: AtomicU32,
+    next_connect: std::sync::Mutex<Option<Instant>>,
+    // reported on connect, none for firmware without handshake support
+    capabilities: std::sync::Mutex<Option<Capabilities>>,
+    // no frames are sent, while a firmware image is uploaded
+    updating: std::sync::atomic::AtomicBool,
+    // progress or result of the last firmware upload, shown in the ui
+    pub firmware_status: std::sync::Mutex<Option<String>>,
+    // read from the device, until the ui takes them
+    input_events: std::sync::Mutex<Vec<InputEvent>>,
 }
 
 impl Device {
-    pub fn new(
-        identifier: String,
-        baud: u32,
-        use_dada_packet: bool,
-        image_format: ImageFormat,
-        has_bme_sensor: bool,
-    ) -> Device {
-        let (sender, receiver): (Sender<Vec<u8>>, Receiver<Vec<u8>>) = bounded(1);
+    pub fn new(config: &DeviceConfig) -> Device {
+        Device::with_connector(config, Box::new(open_sink))
+    }
+
+    pub fn with_connector(config: &DeviceConfig, connector: Connector) -> Device {
+        let (sender, receiver): (Sender<QueuedFrame>, Receiver<QueuedFrame>) = bounded(1);
         Device {
-            identifier,
-            baud,
-            use_dada_packet,
+            config: config.clone(),
             sender,
             receiver,
-            image_format,
-            has_bme_sensor,
+            reliable_transfer: std::sync::atomic::AtomicBool::new(config.reliable_transfer),
+            sequence: AtomicU16::new(0),
+            delivery_stats: Default::default(),
+            pacing_stats: Default::default(),
+            jpeg_quality: AtomicU8::new(config.jpeg_quality),
             brightness: std::sync::atomic::AtomicU8::new(100),
             background_workers_started: std::sync::atomic::AtomicBool::new(false),
             awake: std::sync::Mutex::new(false),
             port: std::sync::Mutex::new(None),
+            connector,
             connected: std::sync::atomic::AtomicBool::new(false),
+            failed_connects: AtomicU32::new(0),
+            next_connect: std::sync::Mutex::new(None),
+            capabilities: std::sync::Mutex::new(None),
+            updating: std::sync::atomic::AtomicBool::new(false),
+            firmware_status: std::sync::Mutex::new(None),
+            input_events: std::sync::Mutex::new(Vec::new()),
+        }
+    }
+
+    // replaces a frame the writer did not pick up yet, so it always gets the newest one
+    pub fn queue_frame(&self, data: Vec<u8>) {
+        self.queue(data, true)
+    }
+
+    pub fn queue_transition_frame(&self, data: Vec<u8>) {
+        self.queue(data, false)
+    }
+
+    fn queue(&self, data: Vec<u8>, paced: bool) {
+        let frame = QueuedFrame {
+            data,
+            queued: Instant::now(),
+            paced,
+        };
+        if let Err(TrySendError::Full(frame)) = self.sender.try_send(frame) {
+            if self.receiver.try_recv().is_ok() {
+                self.pacing_stats.dropped.fetch_add(1, Ordering::AcqRel);
+            }
+            self.sender.try_send(frame).unwrap_or_default();
         }
     }
 
@@ -56,7 +173,7 @@ impl Device {
         self.connected.store(status, Ordering::Release);
     }
 
-    pub fn set_port(&self, port: Option<std::boxed::Box<dyn serialport::SerialPort>>) -> bool {
+    pub fn set_port(&self, port: Option<std::boxed::Box<dyn DisplaySink>>) -> bool {
         let port_valid = port.is_some();
         self.set_connected(port_valid);
         *self.port.lock().unwrap() = port;
@@ -64,7 +181,125 @@ impl Device {
     }
 
     pub fn connect(&self) -> bool {
-        return self.set_port(init_serial(&self.identifier, self.baud));
+        if let Some(next_connect) = *self.next_connect.lock().unwrap() {
+            if Instant::now() < next_connect {
+                return false;
+            }
+        }
+
+        // another firmware may have been flashed in the meantime
+        self.reliable_transfer
+            .store(self.config.reliable_transfer, Ordering::Release);
+        if self.set_port((self.connector)(&self.config)) {
+            match self.handshake() {
+                Ok(_) => {
+                    self.failed_connects.store(0, Ordering::Release);
+                    *self.next_connect.lock().unwrap() = None;
+                    return true;
+                }
+                Err(reason) => {
+                    eprintln!("Device {} is not compatible: {}", self.config.name, reason);
+                    self.disconnect();
+                    *self.next_connect.lock().unwrap() =
+                        Some(Instant::now() + INCOMPATIBLE_RETRY_DELAY);
+                    return false;
+                }
+            }
+        }
+
+        let failed_connects = self.failed_connects.fetch_add(1, Ordering::AcqRel) + 1;
+        *self.next_connect.lock().unwrap() =
+            Some(Instant::now() + reconnect_delay(&self.config, failed_connects));
+        false
+    }
+
+    // asks the firmware what it supports, older firmware does not answer and is
+    // driven as configured
+    fn handshake(&self) -> Result<(), String> {
+        let capabilities = match self.request(Command::Capabilities) {
+            Ok(Some(Reply::Capabilities(capabilities))) => Some(capabilities),
+            _ => None,
+        };
+
+        let encoding = image_encoding(self.config.image_format);
+        match &capabilities {
+            Some(capabilities) => {
+                self.check_capabilities(capabilities)?;
+                if self.config.use_dada_packet
+                    && self.request(Command::SetEncoding(encoding)).is_err()
+                {
+                    return Err(String::from("could not set encoding"));
+                }
+            }
+            // older firmware only knows its default encoding
+            None if encoding != ENCODING_GRAY4 && encoding != ENCODING_WEBP => {
+                return Err(format!(
+                    "{:?} frames need firmware with capabilities handshake",
+                    self.config.image_format
+                ));
+            }
+            None => {}
+        }
+        *self.capabilities.lock().unwrap() = capabilities;
+        Ok(())
+    }
+
+    fn check_capabilities(&self, capabilities: &Capabilities) -> Result<(), String> {
+        if capabilities.version < PROTOCOL_VERSION {
+            return Err(format!(
+                "protocol version {} is too old",
+                capabilities.version
+            ));
+        }
+        if capabilities.width as u32 != self.config.width
+            || capabilities.height as u32 != self.config.height
+        {
+            return Err(format!(
+                "resolution {}x{} does not match the configured {}x{}",
+                capabilities.width, capabilities.height, self.config.width, self.config.height
+            ));
+        }
+        let pixel_format = match self.config.image_format {
+            DeviceImageFormat::Bmp => PIXEL_FORMAT_GRAY4,
+            _ => PIXEL_FORMAT_RGB888,
+        };
+        if capabilities.pixel_format != pixel_format
+            || !capabilities.supports_encoding(image_encoding(self.config.image_format))
+        {
+            return Err(format!(
+                "{:?} frames are not supported",
+                self.config.image_format
+            ));
+        }
+        Ok(())
+    }
+
+    // features of firmware without handshake support are taken from the config
+    pub fn supports(&self, feature: u16, legacy: bool) -> bool {
+        match &*self.capabilities.lock().unwrap() {
+            Some(capabilities) => capabilities.has_feature(feature),
+            None => legacy,
+        }
+    }
+
+    // optional encodings are only used, if the firmware reports them
+    fn supports_encoding(&self, encoding: u8) -> bool {
+        match &*self.capabilities.lock().unwrap() {
+            Some(capabilities) => capabilities.supports_encoding(encoding),
+            None => false,
+        }
+    }
+
+    pub fn is_updating(&self) -> bool {
+        self.updating.load(Ordering::Acquire)
+    }
+
+    pub fn set_updating(&self, updating: bool) {
+        self.updating.store(updating, Ordering::Release);
+    }
+
+    pub fn has_bme_sensor(&self) -> bool {
+        self.supports(FEATURE_BME_SENSOR, self.config.has_bme_sensor)
     }
 
     pub fn disconnect(&self) {
@@ -72,57 +307,227 @@ impl Device {
     }
 
     pub fn write(&self, payload: &[u8]) -> bool {
-        if self.send_command(228) {
-            if self.use_dada_packet {
-                return write_screen_buffer(
-                    &mut self.port.lock().unwrap(),
-                    &DadaPacket::new(payload.to_vec()).as_bytes(),
-                );
+        if self.config.use_dada_packet && self.uses_reliable_transfer() {
+            match self.write_reliable(payload) {
+                Some(delivered) => return delivered,
+                None => {
+                    eprintln!(
+                        "Device {} does not acknowledge frames, falling back to unreliable transfer until it reconnects",
+                        self.config.identifier
+                    );
+                    self.reliable_transfer.store(false, Ordering::Release);
+                }
             }
+        }
 
-            return write_screen_buffer(&mut self.port.lock().unwrap(), payload);
+        self.delivery_stats.sent.fetch_add(1, Ordering::AcqRel);
+        self.request(Command::Frame {
+            data: payload,
+            use_dada_packet: self.config.use_dada_packet,
+        })
+        .is_ok()
+    }
+
+    // firmware with handshake reports, if it acknowledges frames, older firmware is
+    // tried as configured
+    fn uses_reliable_transfer(&self) -> bool {
+        match &*self.capabilities.lock().unwrap() {
+            Some(capabilities) => {
+                self.config.reliable_transfer && capabilities.has_feature(FEATURE_SEQUENCED_FRAMES)
+            }
+            None => self.reliable_transfer.load(Ordering::Acquire),
         }
-        false
     }
 
-    pub fn get_bme_info(&self) -> (String, String) {
-        if self.send_command(205) {
-            let mut result = read_bme_sensor(&mut self.port.lock().unwrap());
-            result = result.trim_end_matches('\0').into();
-            let mut parts = result.split(' ');
-            return (
-                parts.next().unwrap_or_default().into(),
-                parts.next().unwrap_or_default().into(),
+    // returns none, if firmware without handshake never answered a single attempt
+    fn write_reliable(&self, payload: &[u8]) -> Option<bool> {
+        let sequence = self.sequence.fetch_add(1, Ordering::AcqRel);
+        let mut answered = self.delivery_stats.acknowledged.load(Ordering::Acquire) > 0
+            || self.capabilities.lock().unwrap().is_some();
+
+        for attempt in 0..=MAX_RETRANSMISSIONS {
+            if attempt > 0 {
+                self.delivery_stats
+                    .retransmitted
+                    .fetch_add(1, Ordering::AcqRel);
+            }
+            self.delivery_stats.sent.fetch_add(1, Ordering::AcqRel);
+
+            // a timeout, a nack or an answer for an older frame lead to a retransmission
+            match self.request(Command::SequencedFrame {
+                sequence,
+                data: payload,
+            }) {
+                Ok(Some(Reply::Ack(acknowledged))) if acknowledged == sequence => {
+                    self.delivery_stats
+                        .acknowledged
+                        .fetch_add(1, Ordering::AcqRel);
+                    return Some(true);
+                }
+                Err(ProtocolError::Disconnected) => {
+                    self.delivery_stats.failed.fetch_add(1, Ordering::AcqRel);
+                    return Some(false);
+                }
+                Err(ProtocolError::Timeout(_)) => {}
+                _ => answered = true,
+            }
+        }
+        if !answered {
+            return None;
+        }
+        self.delivery_stats.failed.fetch_add(1, Ordering::AcqRel);
+        Some(false)
+    }
+
+    // sends only the regions, which differ from the last frame, if the device supports it
+    fn write_frame(&self, frame: &[u8], last_frame: &[u8]) -> bool {
+        if self.config.image_format == DeviceImageFormat::Bmp {
+            if self.supports_encoding(ENCODING_GRAY4_RLE) {
+                let compressed = encode_rle(frame);
+                // bigger panels may not compress below the limit of the length prefix
+                if compressed.len() < frame.len() && compressed.len() <= MAX_COMPRESSED_FRAME_LEN {
+                    self.delivery_stats.sent.fetch_add(1, Ordering::AcqRel);
+                    return self.request(Command::CompressedFrame(&compressed)).is_ok();
+                }
+            }
+            return self.write(frame);
+        }
+
+        if self.config.partial_updates
+            && self.config.use_dada_packet
+            && self.supports(FEATURE_PARTIAL_UPDATES, true)
+        {
+            if let Some(regions) =
+                dirty_rects(last_frame, frame, self.config.width, self.config.height)
+            {
+                return regions
+                    .iter()
+                    .all(|region| self.write_region(frame, region));
+            }
+        }
+        self.write(&self.encode(frame, self.config.width, self.config.height))
+    }
+
+    // rgb frames and regions in the configured format
+    fn encode(&self, bytes: &[u8], width: u32, height: u32) -> Vec<u8> {
+        match self.config.image_format {
+            DeviceImageFormat::Bmp => bytes.to_vec(),
+            DeviceImageFormat::WebP => convert_to_webp(bytes, width, height),
+            DeviceImageFormat::Rgb565 => convert_to_rgb565(bytes),
+            DeviceImageFormat::Jpeg => convert_to_jpeg(
+                bytes,
+                width,
+                height,
+                self.jpeg_quality.load(Ordering::Acquire),
+            ),
+            DeviceImageFormat::Qoi => convert_to_qoi(bytes, width, height),
+        }
+    }
+
+    fn adapt_jpeg_quality(&self, pacer: &FramePacer) {
+        if self.config.image_format == DeviceImageFormat::Jpeg {
+            let quality = self.jpeg_quality.load(Ordering::Acquire);
+            self.jpeg_quality.store(
+                pacer.adapt_jpeg_quality(quality, self.config.jpeg_quality),
+                Ordering::Release,
             );
         }
-        (String::new(), String::new())
+    }
+
+    fn write_region(&self, frame: &[u8], region: &Rect) -> bool {
+        let encoded = self.encode(
+            &crop_rgb(frame, self.config.width, region),
+            region.width,
+            region.height,
+        );
+        self.delivery_stats.sent.fetch_add(1, Ordering::AcqRel);
+        self.request(Command::Region(&region_payload(region, &encoded)))
+            .is_ok()
+    }
+
+    // none, if the sensor did not answer or the reading is out of range
+    pub fn read_sensor(&self) -> Option<SensorReading> {
+        let command = if self.supports(FEATURE_PRESSURE, false) {
+            Command::ReadEnvironment
+        } else {
+            Command::ReadBme
+        };
+        let reading = match self.request(command) {
+            Ok(Some(Reply::Bme {
+                temperature,
+                humidity,
+                pressure,
+            })) => SensorReading {
+                temperature,
+                humidity,
+                pressure,
+                timestamp: chrono::Local::now(),
+                device: self.config.name.clone(),
+            },
+            _ => return None,
+        };
+        match reading.validate() {
+            Ok(_) => Some(reading),
+            Err(reason) => {
+                eprintln!("Invalid sensor reading of {}: {}", reading.device, reason);
+                None
+            }
+        }
+    }
+
+    pub fn take_input_events(&self) -> Vec<InputEvent> {
+        std::mem::take(&mut *self.input_events.lock().unwrap())
     }
 
     pub fn reset_display(&self) {
-        self.send_command(17);
+        self.request(Command::Reset).ok();
     }
 
-    pub fn send_command(&self, command: u8) -> bool {
-        return send_command(&mut self.port.lock().unwrap(), &command.to_le_bytes());
+    // sends the command with its payload and waits for the reply, if the command has one
+    pub fn request(&self, command: Command) -> Result<Option<Reply>, ProtocolError> {
+        let mut port = self.port.lock().unwrap();
+        if !send_command(&mut port, &[command.id()]) {
+            return Err(ProtocolError::Disconnected);
+        }
+        if let Some(payload) = command.payload() {
+            if !write_screen_buffer(&mut port, &payload) {
+                return Err(ProtocolError::Disconnected);
+            }
+        }
+
+        let reply = match command.reply_format() {
+            ReplyFormat::None => return Ok(None),
+            ReplyFormat::Raw(length) => read_bytes(&mut port, length, command.reply_timeout()),
+            // a fresh decoder, so the rest of a late reply is not taken for this one
+            ReplyFormat::DadaPacket => read_packet(
+                &mut port,
+                &mut DadaPacketDecoder::new(MAX_REPLY_LEN),
+                command.reply_timeout(),
+            ),
+        };
+        match reply {
+            Some(reply) => command.parse_reply(&reply).map(Some),
+            None => Err(ProtocolError::Timeout(command.id())),
+        }
+    }
+
+    // the teensy has no command for the brightness of the oled display, its frames are
+    // dimmed in the gray scale conversion instead
+    pub fn has_brightness_command(&self) -> bool {
+        self.config.use_dada_packet && self.supports(FEATURE_BRIGHTNESS, true)
     }
 
-    // will be ignored from teensy for now until we find out, how the brightness for the
-    // oled display can be set oO
     pub fn set_brightness(&self, brightness: u8) -> bool {
-        if self.use_dada_packet {
+        if self.has_brightness_command() {
             self.brightness.store(brightness, Ordering::Release);
-            return self.send_command(20)
-                && write_screen_buffer(
-                    &mut self.port.lock().unwrap(),
-                    &DadaPacket::new(brightness.to_le_bytes().to_vec()).as_bytes(),
-                );
+            return self.request(Command::Brightness(brightness)).is_ok();
         }
-        false // not implemented for teensy
+        false // dimmed in the frame
     }
 
     pub fn stand_by(&self) {
         if *self.awake.lock().unwrap() {
-            if !self.send_command(18) {
+            if self.request(Command::StandBy).is_err() {
                 self.disconnect()
             } else {
                 *self.awake.lock().unwrap() = false;
@@ -133,7 +538,7 @@ impl Device {
     pub fn wake_up(&self) {
         if !*self.awake.lock().unwrap() {
             thread::sleep(std::time::Duration::from_millis(200));
-            if !self.send_command(19) {
+            if self.request(Command::WakeUp).is_err() {
                 self.disconnect()
             } else {
                 *self.awake.lock().unwrap() = true;
@@ -147,71 +552,512 @@ impl Device {
                 .store(true, Ordering::Release);
             self.start_writer();
             self.start_bme_sensor_background_thread();
+            self.start_input_background_thread();
         }
     }
 
     fn start_bme_sensor_background_thread(self: &'static Device) {
-        if self.has_bme_sensor {
-            thread::spawn(move || loop {
-                if self.is_connected() {
-                    let bme_info = self.get_bme_info();
-                    if !bme_info.0.is_empty() && !bme_info.1.is_empty() {
-                        *LAST_BME_INFO.lock().unwrap() = bme_info;
-                    }
+        // the sensor may only be reported by the firmware after connecting
+        thread::spawn(move || loop {
+            if self.is_connected()
+                && self.has_bme_sensor()
+                && !self.updating.load(Ordering::Acquire)
+            {
+                if let Some(reading) = self.read_sensor() {
+                    SENSOR_HISTORY.lock().unwrap().push(reading);
                 }
-                if CLOSE_REQUESTED.load(std::sync::atomic::Ordering::Acquire) {
-                    return;
+            }
+            if CLOSE_REQUESTED.load(std::sync::atomic::Ordering::Acquire) {
+                return;
+            }
+            thread::sleep(std::time::Duration::from_millis(2000));
+        });
+    }
+
+    // buttons and encoders are only known after the handshake
+    fn start_input_background_thread(self: &'static Device) {
+        thread::spawn(move || loop {
+            if self.is_connected()
+                && self.supports(FEATURE_BUTTONS, false)
+                && !self.updating.load(Ordering::Acquire)
+            {
+                if let Ok(Some(Reply::Input(events))) = self.request(Command::ReadInput) {
+                    self.input_events.lock().unwrap().extend(events);
                 }
-                thread::sleep(std::time::Duration::from_millis(2000));
-            });
-        }
+            }
+            if CLOSE_REQUESTED.load(std::sync::atomic::Ordering::Acquire) {
+                return;
+            }
+            thread::sleep(INPUT_POLL_INTERVAL);
+        });
     }
 
     fn start_writer(self: &'static Device) {
         thread::spawn(move || {
-            let mut last_sum = 0;
-            let mut brightness_set = false;
+            let mut writer = Writer::new(&self.config);
             loop {
-                let buf = self.receiver.recv();
-                if self.is_connected() {
-                    if !brightness_set {
-                        self.set_brightness(self.brightness.load(Ordering::Acquire));
-                        brightness_set = true;
-                    }
-                    match buf {
-                        Ok(b) => {
-                            if CLOSE_REQUESTED.load(std::sync::atomic::Ordering::Acquire) {
-                                return;
-                            }
-                            if *HIBERNATING.lock().unwrap() {
-                                last_sum = 0;
-                                self.stand_by();
-                            } else {
-                                let crc_of_buf = crc32fast::hash(&b);
-                                let mut payload = b;
-                                if last_sum != crc_of_buf {
-                                    if self.image_format == ImageFormat::WebP {
-                                        payload = convert_to_webp(&payload, 320, 170);
-                                    }
-                                    if self.write(&payload) {
-                                        last_sum = crc_of_buf;
-                                    } else {
-                                        self.disconnect();
-                                    }
-                                } else if !self.send_command(229) {
-                                    self.disconnect();
-                                }
-                                self.wake_up();
-                            }
-                        }
-                        Err(_) => {}
-                    }
-                } else if self.connect() {
-                    brightness_set = false;
-                    last_sum = 0;
-                    self.reset_display()
+                let frame = self.next_frame(&writer);
+                if !self.write_queued(&mut writer, frame) {
+                    return;
                 }
             }
         });
     }
+
+    // waits for the next frame, newer frames replace it while it is held back
+    fn next_frame(&self, writer: &Writer) -> Option<QueuedFrame> {
+        let frame = self.receiver.recv().ok()?;
+        let wait_time = frame.wait_time(&writer.pacer);
+        if wait_time.is_zero() {
+            return Some(frame);
+        }
+        thread::sleep(wait_time);
+        match self.receiver.try_recv() {
+            Ok(newer) => {
+                self.pacing_stats.dropped.fetch_add(1, Ordering::AcqRel);
+                Some(newer)
+            }
+            Err(_) => Some(frame),
+        }
+    }
+
+    // sends the frame, a keep alive if it did not change, or connects first,
+    // returns false once the application closes
+    fn write_queued(&self, writer: &mut Writer, frame: Option<QueuedFrame>) -> bool {
+        if !self.is_connected() {
+            // a firmware upload opens the link again on its own
+            if !self.is_updating() && self.connect() {
+                *writer = Writer::new(&self.config);
+                self.reset_display()
+            }
+            return true;
+        }
+        if !writer.brightness_set {
+            self.set_brightness(self.brightness.load(Ordering::Acquire));
+            writer.brightness_set = true;
+        }
+        let QueuedFrame {
+            data: b, queued, ..
+        } = match frame {
+            Some(frame) => frame,
+            None => return true,
+        };
+        if CLOSE_REQUESTED.load(std::sync::atomic::Ordering::Acquire) {
+            return false;
+        }
+        if self.updating.load(Ordering::Acquire) {
+            // the frame after the upload is sent in full
+            writer.last_sum = 0;
+            writer.last_frame.clear();
+        } else if *HIBERNATING.lock().unwrap() {
+            writer.last_sum = 0;
+            writer.last_frame.clear();
+            self.stand_by();
+        } else {
+            let crc_of_buf = crc32fast::hash(&b);
+            if writer.last_sum != crc_of_buf {
+                let started = Instant::now();
+                if self.write_frame(&b, &writer.last_frame) {
+                    writer.pacer.frame_written(started, &self.pacing_stats);
+                    writer.pacer.frame_shown(queued, &self.pacing_stats);
+                    self.adapt_jpeg_quality(&writer.pacer);
+                    writer.last_sum = crc_of_buf;
+                    writer.last_frame = b;
+                } else {
+                    writer.last_frame.clear();
+                    self.disconnect();
+                }
+            } else if self.request(Command::KeepAlive).is_err() {
+                self.disconnect();
+            } else {
+                writer.pacer.frame_shown(queued, &self.pacing_stats);
+            }
+            self.wake_up();
+        }
+        true
+    }
+}
+
+// the bit of the format in the capabilities of a device
+fn image_encoding(image_format: DeviceImageFormat) -> u8 {
+    match image_format {
+        DeviceImageFormat::Bmp => ENCODING_GRAY4,
+        DeviceImageFormat::WebP => ENCODING_WEBP,
+        DeviceImageFormat::Rgb565 => ENCODING_RGB565,
+        DeviceImageFormat::Jpeg => ENCODING_JPEG,
+        DeviceImageFormat::Qoi => ENCODING_QOI,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::default_devices;
+    use std::{
+        collections::{HashMap, VecDeque},
+        io::{Read, Write},
+        sync::{Arc, Mutex},
+    };
+
+    // remembers everything written, fails writes on demand and answers a command with
+    // the next reply queued for it, an empty one is no answer at all
+    #[derive(Default)]
+    struct FakeLink {
+        written: Vec<u8>,
+        broken: bool,
+        connects: u32,
+        // single byte writes, the ids of the commands
+        commands: Vec<u8>,
+        replies: HashMap<u8, VecDeque<Vec<u8>>>,
+        unread: Vec<u8>,
+    }
+
+    struct FakeSink(Arc<Mutex<FakeLink>>);
+
+    impl DisplaySink for FakeSink {}
+
+    impl Read for FakeSink {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let mut link = self.0.lock().unwrap();
+            let count = buf.len().min(link.unread.len());
+            buf[..count].copy_from_slice(&link.unread[..count]);
+            link.unread.drain(..count);
+            Ok(count)
+        }
+    }
+
+    impl Write for FakeSink {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            let mut link = self.0.lock().unwrap();
+            if link.broken {
+                return Err(std::io::ErrorKind::BrokenPipe.into());
+            }
+            link.written.extend_from_slice(buf);
+            if let [command] = buf {
+                link.commands.push(*command);
+                if let Some(reply) = link.replies.get_mut(command).and_then(VecDeque::pop_front) {
+                    link.unread = reply;
+                }
+            }
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // the teensy: raw gray scale frames without packets or acknowledgements
+    fn fake_device() -> (Device, Arc<Mutex<FakeLink>>) {
+        fake_device_with_config(&default_devices()[0])
+    }
+
+    // the esp32 with acknowledged frames
+    fn fake_reliable_device() -> (Device, Arc<Mutex<FakeLink>>) {
+        let mut config = default_devices()[1].clone();
+        config.reliable_transfer = true;
+        fake_device_with_config(&config)
+    }
+
+    fn fake_device_with_config(config: &DeviceConfig) -> (Device, Arc<Mutex<FakeLink>>) {
+        let link = Arc::new(Mutex::new(FakeLink::default()));
+        let sink_link = link.clone();
+        let device = Device::with_connector(
+            config,
+            Box::new(move |_: &DeviceConfig| {
+                sink_link.lock().unwrap().connects += 1;
+                if sink_link.lock().unwrap().broken {
+                    return None;
+                }
+                Some(Box::new(FakeSink(sink_link.clone())) as Box<dyn DisplaySink>)
+            }),
+        );
+        (device, link)
+    }
+
+    fn frame(value: u8) -> Option<QueuedFrame> {
+        Some(QueuedFrame {
+            data: vec![value; 16],
+            queued: Instant::now(),
+            paced: true,
+        })
+    }
+
+    #[test]
+    fn transition_frames_pass_the_pacer() {
+        let mut config = default_devices()[0].clone();
+        config.target_fps = 0.1;
+        let (device, _link) = fake_device_with_config(&config);
+        let mut writer = Writer::new(&device.config);
+        writer
+            .pacer
+            .frame_written(Instant::now(), &device.pacing_stats);
+
+        // a screen waits 10 s for the next slot, a transition frame does not wait at all
+        let screen = frame(1).unwrap();
+        assert!(screen.wait_time(&writer.pacer) > Duration::from_secs(5));
+        device.queue_transition_frame(vec![2; 16]);
+        let transition = device.next_frame(&writer).unwrap();
+        assert!(!transition.paced);
+        assert_eq!(transition.wait_time(&writer.pacer), Duration::ZERO);
+        assert_eq!(transition.data, vec![2; 16]);
+    }
+
+    fn take_written(link: &Arc<Mutex<FakeLink>>) -> Vec<u8> {
+        std::mem::take(&mut link.lock().unwrap().written)
+    }
+
+    fn frame_command(value: u8) -> Vec<u8> {
+        let mut bytes = vec![Command::FRAME];
+        bytes.extend(vec![value; 16]);
+        bytes
+    }
+
+    #[test]
+    fn connects_before_the_first_frame() {
+        let (device, link) = fake_device();
+        let mut writer = Writer::new(&device.config);
+
+        assert!(device.write_queued(&mut writer, frame(1)));
+        assert!(device.is_connected());
+        // the handshake is not answered, so the device is driven as configured
+        assert_eq!(
+            take_written(&link),
+            vec![Command::CAPABILITIES, Command::RESET]
+        );
+
+        device.write_queued(&mut writer, frame(1));
+        let mut expected = frame_command(1);
+        expected.push(Command::WAKE_UP);
+        assert_eq!(take_written(&link), expected);
+    }
+
+    #[test]
+    fn reconnects_after_a_write_error() {
+        let (device, link) = fake_device();
+        let mut writer = Writer::new(&device.config);
+        device.write_queued(&mut writer, frame(1));
+        device.write_queued(&mut writer, frame(1));
+        take_written(&link);
+
+        link.lock().unwrap().broken = true;
+        device.write_queued(&mut writer, frame(2));
+        assert!(!device.is_connected());
+
+        // a serial device is probed again right away, but it is still gone
+        device.write_queued(&mut writer, frame(2));
+        assert!(!device.is_connected());
+        assert_eq!(link.lock().unwrap().connects, 2);
+
+        link.lock().unwrap().broken = false;
+        device.write_queued(&mut writer, frame(2));
+        assert!(device.is_connected());
+        assert_eq!(link.lock().unwrap().connects, 3);
+        assert_eq!(
+            take_written(&link),
+            vec![Command::CAPABILITIES, Command::RESET]
+        );
+
+        // the frame is sent in full again, even though it is the one that failed
+        device.write_queued(&mut writer, frame(2));
+        assert_eq!(take_written(&link), frame_command(2));
+    }
+
+    #[test]
+    fn keeps_alive_while_the_frame_does_not_change() {
+        let (device, link) = fake_device();
+        let mut writer = Writer::new(&device.config);
+        device.write_queued(&mut writer, frame(1));
+        device.write_queued(&mut writer, frame(1));
+        take_written(&link);
+
+        for _ in 0..3 {
+            device.write_queued(&mut writer, frame(1));
+            assert_eq!(take_written(&link), vec![Command::KEEP_ALIVE]);
+        }
+
+        device.write_queued(&mut writer, frame(2));
+        assert_eq!(take_written(&link), frame_command(2));
+        device.write_queued(&mut writer, frame(2));
+        assert_eq!(take_written(&link), vec![Command::KEEP_ALIVE]);
+
+        // no frame queued, nothing to keep alive
+        device.write_queued(&mut writer, None);
+        assert_eq!(take_written(&link), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn keep_alive_error_disconnects() {
+        let (device, link) = fake_device();
+        let mut writer = Writer::new(&device.config);
+        device.write_queued(&mut writer, frame(1));
+        device.write_queued(&mut writer, frame(1));
+
+        link.lock().unwrap().broken = true;
+        device.write_queued(&mut writer, frame(1));
+        assert!(!device.is_connected());
+    }
+
+    fn reply(link: &Arc<Mutex<FakeLink>>, command: u8, reply: Vec<u8>) {
+        let mut link = link.lock().unwrap();
+        link.replies.entry(command).or_default().push_back(reply);
+    }
+
+    fn take_commands(link: &Arc<Mutex<FakeLink>>) -> Vec<u8> {
+        std::mem::take(&mut link.lock().unwrap().commands)
+    }
+
+    #[test]
+    fn retransmits_a_frame_without_acknowledgement() {
+        let (device, link) = fake_reliable_device();
+        assert!(device.connect());
+        take_commands(&link);
+        reply(&link, Command::SEQUENCED_FRAME, Vec::new());
+        reply(&link, Command::SEQUENCED_FRAME, Reply::Ack(0).as_bytes());
+
+        assert!(device.write(&[1, 2, 3]));
+        assert_eq!(
+            take_commands(&link),
+            vec![Command::SEQUENCED_FRAME, Command::SEQUENCED_FRAME]
+        );
+        let stats = &device.delivery_stats;
+        assert_eq!(stats.retransmitted.load(Ordering::Acquire), 1);
+        assert_eq!(stats.acknowledged.load(Ordering::Acquire), 1);
+        assert_eq!(stats.failed.load(Ordering::Acquire), 0);
+
+        // the link answered before, so a frame nobody acknowledges is not delivered
+        assert!(!device.write(&[1, 2, 3]));
+        assert_eq!(
+            take_commands(&link),
+            vec![Command::SEQUENCED_FRAME; MAX_RETRANSMISSIONS as usize + 1]
+        );
+        assert_eq!(stats.failed.load(Ordering::Acquire), 1);
+    }
+
+    #[test]
+    fn falls_back_until_the_next_connect() {
+        let (device, link) = fake_reliable_device();
+        assert!(device.connect());
+        take_commands(&link);
+
+        // every attempt is made, before the frame is sent without acknowledgement
+        assert!(device.write(&[1, 2, 3]));
+        let mut expected = vec![Command::SEQUENCED_FRAME; MAX_RETRANSMISSIONS as usize + 1];
+        expected.push(Command::FRAME);
+        assert_eq!(take_commands(&link), expected);
+        assert!(device.write(&[1, 2, 3]));
+        assert_eq!(take_commands(&link), vec![Command::FRAME]);
+
+        // other firmware may have been flashed in the meantime
+        reply(&link, Command::SEQUENCED_FRAME, Reply::Ack(1).as_bytes());
+        assert!(device.connect());
+        take_commands(&link);
+        assert!(device.write(&[1, 2, 3]));
+        assert_eq!(take_commands(&link), vec![Command::SEQUENCED_FRAME]);
+    }
+
+    fn esp32_capabilities() -> Capabilities {
+        Capabilities {
+            version: PROTOCOL_VERSION,
+            width: 320,
+            height: 170,
+            pixel_format: PIXEL_FORMAT_RGB888,
+            encodings: ENCODING_WEBP | ENCODING_JPEG,
+            features: FEATURE_SEQUENCED_FRAMES,
+        }
+    }
+
+    #[test]
+    fn incompatible_capabilities_are_rejected() {
+        let (device, _link) = fake_reliable_device();
+        assert!(device.check_capabilities(&esp32_capabilities()).is_ok());
+
+        let old = Capabilities {
+            version: PROTOCOL_VERSION - 1,
+            ..esp32_capabilities()
+        };
+        assert!(device.check_capabilities(&old).is_err());
+        let smaller = Capabilities {
+            height: 160,
+            ..esp32_capabilities()
+        };
+        assert!(device.check_capabilities(&smaller).is_err());
+        let gray = Capabilities {
+            pixel_format: PIXEL_FORMAT_GRAY4,
+            ..esp32_capabilities()
+        };
+        assert!(device.check_capabilities(&gray).is_err());
+        let without_webp = Capabilities {
+            encodings: ENCODING_JPEG,
+            ..esp32_capabilities()
+        };
+        assert!(device.check_capabilities(&without_webp).is_err());
+    }
+
+    #[test]
+    fn firmware_with_handshake_never_falls_back() {
+        let (device, link) = fake_reliable_device();
+        reply(
+            &link,
+            Command::CAPABILITIES,
+            Reply::Capabilities(esp32_capabilities()).as_bytes(),
+        );
+        assert!(device.connect());
+        assert_eq!(
+            take_commands(&link),
+            vec![Command::CAPABILITIES, Command::SET_ENCODING]
+        );
+
+        // it reported acknowledged frames, so a missing ack is a failed delivery
+        assert!(!device.write(&[1, 2, 3]));
+        assert!(!device.write(&[1, 2, 3]));
+        assert_eq!(
+            take_commands(&link),
+            vec![Command::SEQUENCED_FRAME; 2 * (MAX_RETRANSMISSIONS as usize + 1)]
+        );
+        assert_eq!(device.delivery_stats.failed.load(Ordering::Acquire), 2);
+    }
+
+    #[test]
+    fn firmware_without_acknowledged_frames_gets_plain_ones() {
+        let (device, link) = fake_reliable_device();
+        let capabilities = Capabilities {
+            features: 0,
+            ..esp32_capabilities()
+        };
+        reply(
+            &link,
+            Command::CAPABILITIES,
+            Reply::Capabilities(capabilities).as_bytes(),
+        );
+        assert!(device.connect());
+        take_commands(&link);
+
+        assert!(device.write(&[1, 2, 3]));
+        assert_eq!(take_commands(&link), vec![Command::FRAME]);
+    }
+
+    #[test]
+    fn the_rest_of_a_reply_is_not_taken_for_the_next_one() {
+        let (device, link) = fake_reliable_device();
+        reply(
+            &link,
+            Command::CAPABILITIES,
+            Reply::Capabilities(esp32_capabilities()).as_bytes(),
+        );
+        assert!(device.connect());
+
+        let mut replies = Reply::Ack(0).as_bytes();
+        replies.extend(Reply::Ack(1).as_bytes());
+        reply(&link, Command::SEQUENCED_FRAME, replies);
+        assert!(device.write(&[1, 2, 3]));
+
+        // the ack for the next frame came before it was sent, so it is sent again
+        reply(&link, Command::SEQUENCED_FRAME, Vec::new());
+        reply(&link, Command::SEQUENCED_FRAME, Reply::Ack(1).as_bytes());
+        assert!(device.write(&[1, 2, 3]));
+        let stats = &device.delivery_stats;
+        assert_eq!(stats.retransmitted.load(Ordering::Acquire), 1);
+        assert_eq!(stats.acknowledged.load(Ordering::Acquire), 2);
+    }
 }
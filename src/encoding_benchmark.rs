@@ -0,0 +1,132 @@
+// Compares the companion encodings on the frames of the built-in screens: encode time
+// and payload size per format. Every screen is shown for a few seconds and each frame it
+// draws in that time is encoded. Frames recorded with the file sink or the device
+// emulator can be added, use a lossless encoding (qoi or rgb565) for recording them.
+//
+// usage: awesome_info_display --benchmark-encodings [jpeg quality] [directory with png frames]
+use crate::{
+    helpers::convert_image::{convert_to_jpeg, convert_to_qoi, convert_to_rgb565, convert_to_webp},
+    screens::{BasicScreen, Screenable},
+};
+use image::RgbImage;
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+const DEFAULT_JPEG_QUALITY: u8 = 85;
+const FORMATS: [&str; 5] = ["rgb888", "rgb565", "webp", "jpeg", "qoi"];
+// long enough for the screens, which fetch their data first
+const RENDER_TIME: Duration = Duration::from_secs(5);
+const UPDATE_INTERVAL: Duration = Duration::from_millis(100);
+
+fn encode(format: &str, bytes: &[u8], width: u32, height: u32, quality: u8) -> Vec<u8> {
+    match format {
+        "rgb565" => convert_to_rgb565(bytes),
+        "webp" => convert_to_webp(bytes, width, height),
+        "jpeg" => convert_to_jpeg(bytes, width, height, quality),
+        "qoi" => convert_to_qoi(bytes, width, height),
+        // raw, as reference
+        _ => bytes.to_vec(),
+    }
+}
+
+fn usage() {
+    eprintln!(
+        "usage: awesome_info_display --benchmark-encodings [jpeg quality] [directory with png frames]"
+    );
+}
+
+pub fn run(args: &[String], screens: Vec<Box<dyn BasicScreen>>) {
+    let quality = match args.first().map(|quality| quality.parse::<u8>()) {
+        Some(Ok(quality)) if (1..=100).contains(&quality) => quality,
+        Some(_) => return usage(),
+        None => DEFAULT_JPEG_QUALITY,
+    };
+
+    let mut frames = Vec::new();
+    for mut screen in screens {
+        let rendered = render_frames(&mut screen);
+        println!("{}: {} frames", screen.description(), rendered.len());
+        frames.extend(rendered);
+    }
+    if let Some(directory) = args.get(1) {
+        let recorded = load_frames(directory);
+        println!("{}: {} frames", directory, recorded.len());
+        frames.extend(recorded);
+    }
+    if frames.is_empty() {
+        eprintln!("No frames to encode");
+        return;
+    }
+
+    println!("{} frames, jpeg quality {}", frames.len(), quality);
+    println!(
+        "{:<8} {:>12} {:>14} {:>8}",
+        "format", "encode (ms)", "size (bytes)", "ratio"
+    );
+    let raw_bytes: usize = frames.iter().map(|frame| frame.as_raw().len()).sum();
+    for format in FORMATS {
+        let mut duration = Duration::ZERO;
+        let mut bytes = 0;
+        for frame in &frames {
+            let start = Instant::now();
+            let encoded = encode(
+                format,
+                frame.as_raw(),
+                frame.width(),
+                frame.height(),
+                quality,
+            );
+            duration += start.elapsed();
+            bytes += encoded.len();
+        }
+        println!(
+            "{:<8} {:>12.2} {:>14} {:>7.1}%",
+            format,
+            duration.as_secs_f64() * 1000.0 / frames.len() as f64,
+            bytes / frames.len(),
+            bytes as f64 * 100.0 / raw_bytes as f64
+        );
+    }
+}
+
+// every companion frame the screen draws while it is shown, without repeated ones
+fn render_frames(screen: &mut Box<dyn BasicScreen>) -> Vec<RgbImage> {
+    let canvas = screen.get_screen().companion_canvas;
+    let mut frames: Vec<RgbImage> = Vec::new();
+    screen.start();
+    let started = Instant::now();
+    while started.elapsed() < RENDER_TIME {
+        screen.update();
+        let bytes = screen.current_image_for_companion();
+        // screens without a companion frame draw nothing for it
+        if bytes.len() == canvas.buffer_len()
+            && frames.last().map_or(true, |frame| frame.as_raw() != bytes)
+        {
+            frames.extend(RgbImage::from_raw(
+                canvas.width,
+                canvas.height,
+                bytes.clone(),
+            ));
+        }
+        thread::sleep(UPDATE_INTERVAL);
+    }
+    screen.stop();
+    frames
+}
+
+fn load_frames(directory: &str) -> Vec<RgbImage> {
+    let pattern = format!("{}/*.png", directory.trim_end_matches('/'));
+    let mut frames = Vec::new();
+    for path in glob::glob(&pattern)
+        .expect("INVALID FRAME DIRECTORY")
+        .flatten()
+    {
+        match image::open(&path) {
+            Ok(image) => frames.push(image.to_rgb8()),
+            Err(e) => eprintln!("Could not read {:?}: {:?}", path, e),
+        }
+    }
+    frames
+}
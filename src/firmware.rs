@@ -0,0 +1,282 @@
+// Uploads a firmware image to a device over its normal link. The image is sent in
+// chunks with a crc32 each, the device answers with the offset it expects next, so
+// a broken chunk is sent again and an interrupted upload continues where it stopped.
+// Once the whole image is verified by the device, it is rebooted into it.
+use crate::{
+    capabilities::FEATURE_FIRMWARE_UPDATE,
+    device::Device,
+    protocol::{Command, ProtocolError, Reply},
+};
+use std::{thread, time::Duration};
+
+// fits into the receive buffer of both the teensy and the esp32
+const CHUNK_SIZE: usize = 1024;
+const MAX_CHUNK_RETRIES: u32 = 3;
+// how often an interrupted upload is continued
+const MAX_RESUMES: u32 = 5;
+// a device, which lost its link, gets some time to show up again
+const MAX_RECONNECTS: u32 = 10;
+const RECONNECT_DELAY: Duration = Duration::from_millis(500);
+
+// called with the bytes the device confirmed and the size of the image
+pub fn upload(
+    device: &Device,
+    image: &[u8],
+    progress: &dyn Fn(usize, usize),
+) -> Result<(), String> {
+    if !device.is_connected() {
+        return Err(String::from("device is not connected"));
+    }
+    if !device.supports(FEATURE_FIRMWARE_UPDATE, false) {
+        return Err(String::from("firmware does not support updates"));
+    }
+    if image.is_empty() {
+        return Err(String::from("firmware image is empty"));
+    }
+
+    device.set_updating(true);
+    let result = transfer_and_verify(device, image, progress);
+    device.set_updating(false);
+    if result.is_ok() {
+        device.request(Command::Reboot).ok();
+        // the rebooted firmware is asked for its capabilities again
+        device.disconnect();
+    }
+    result
+}
+
+fn transfer_and_verify(
+    device: &Device,
+    image: &[u8],
+    progress: &dyn Fn(usize, usize),
+) -> Result<(), String> {
+    let crc = crc32fast::hash(image);
+    let mut resumes = 0;
+    while let Err(reason) = transfer(device, image, crc, progress) {
+        if resumes == MAX_RESUMES {
+            return Err(reason);
+        }
+        resumes += 1;
+        eprintln!(
+            "Firmware upload to {} interrupted ({}), resuming",
+            device.config.name, reason
+        );
+        if !device.is_connected() {
+            reconnect(device)?;
+        }
+    }
+
+    match device.request(Command::FirmwareVerify) {
+        Ok(Some(Reply::FirmwareVerified(true))) => Ok(()),
+        Ok(_) => Err(String::from("verification of the image failed")),
+        Err(e) => Err(failure(device, e)),
+    }
+}
+
+// the device keeps what it received so far, the upload continues after connecting
+fn reconnect(device: &Device) -> Result<(), String> {
+    for _ in 0..MAX_RECONNECTS {
+        thread::sleep(RECONNECT_DELAY);
+        if device.connect() {
+            return Ok(());
+        }
+    }
+    Err(String::from("device did not come back"))
+}
+
+// a write, which failed, means the link is gone and has to be opened again
+fn failure(device: &Device, error: ProtocolError) -> String {
+    if let ProtocolError::Disconnected = error {
+        device.disconnect();
+    }
+    error.to_string()
+}
+
+// starts at the offset the device reports, 0 for a new image
+fn transfer(
+    device: &Device,
+    image: &[u8],
+    crc: u32,
+    progress: &dyn Fn(usize, usize),
+) -> Result<(), String> {
+    let mut offset = match device.request(Command::FirmwareBegin {
+        size: image.len() as u32,
+        crc,
+    }) {
+        Ok(Some(Reply::FirmwareOffset(offset))) => offset as usize,
+        Ok(_) => return Err(String::from("unexpected answer to upload start")),
+        Err(e) => return Err(failure(device, e)),
+    };
+
+    let mut retries = 0;
+    while offset < image.len() {
+        progress(offset, image.len());
+        let end = (offset + CHUNK_SIZE).min(image.len());
+        let next = match device.request(Command::FirmwareChunk {
+            offset: offset as u32,
+            data: &image[offset..end],
+        }) {
+            Ok(Some(Reply::FirmwareOffset(next))) => next as usize,
+            Ok(_) => return Err(String::from("unexpected answer to chunk")),
+            Err(e) => return Err(failure(device, e)),
+        };
+        if next == end {
+            offset = next;
+            retries = 0;
+        } else if next == offset && retries < MAX_CHUNK_RETRIES {
+            // checksum mismatch, the device keeps waiting for the same chunk
+            retries += 1;
+        } else {
+            return Err(format!("chunk at {} was not accepted", offset));
+        }
+    }
+    progress(offset, image.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::{default_devices, DeviceConfig, TransportConfig},
+        sinks::{file_sink::FileSink, DisplaySink},
+    };
+    use std::{
+        io::{Read, Write},
+        path::{Path, PathBuf},
+        sync::{Arc, Mutex},
+    };
+
+    const IMAGE_SIZE: usize = 5000;
+
+    fn directory(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!(
+            "awesome_info_display_{}_{}",
+            name,
+            std::process::id()
+        ));
+        std::fs::remove_dir_all(&directory).ok();
+        directory
+    }
+
+    fn esp32(directory: &Path) -> DeviceConfig {
+        let mut config = default_devices()[1].clone();
+        config.transport = TransportConfig::File {
+            path: directory.to_string_lossy().into_owned(),
+        };
+        config
+    }
+
+    fn image() -> Vec<u8> {
+        (0..IMAGE_SIZE)
+            .map(|index| (index * 31 % 251) as u8)
+            .collect()
+    }
+
+    // what the file sink wrote once the image was verified
+    fn uploaded(directory: &Path) -> Option<Vec<u8>> {
+        std::fs::read(directory.join("esp32_firmware.bin")).ok()
+    }
+
+    // shares the file sink over reconnects and loses the link once, when a number of
+    // writes went through
+    struct FlakyLink {
+        sink: Arc<Mutex<FileSink>>,
+        writes_until_loss: Arc<Mutex<Option<u32>>>,
+    }
+
+    impl DisplaySink for FlakyLink {}
+
+    impl Read for FlakyLink {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.sink.lock().unwrap().read(buf)
+        }
+    }
+
+    impl Write for FlakyLink {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            let mut writes_until_loss = self.writes_until_loss.lock().unwrap();
+            match *writes_until_loss {
+                Some(0) => {
+                    *writes_until_loss = None;
+                    return Err(std::io::ErrorKind::BrokenPipe.into());
+                }
+                Some(writes) => *writes_until_loss = Some(writes - 1),
+                None => {}
+            }
+            self.sink.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn uploads_to_file_sink() {
+        let directory = directory("firmware_upload");
+        let device = Device::new(&esp32(&directory));
+        assert!(device.connect());
+
+        let progress = Mutex::new(Vec::new());
+        let result = upload(&device, &image(), &|uploaded, size| {
+            assert_eq!(size, IMAGE_SIZE);
+            progress.lock().unwrap().push(uploaded)
+        });
+        assert_eq!(result, Ok(()));
+        assert_eq!(uploaded(&directory), Some(image()));
+        assert_eq!(
+            progress.into_inner().unwrap(),
+            vec![0, 1024, 2048, 3072, 4096, IMAGE_SIZE]
+        );
+        // rebooted, the new firmware is asked for its capabilities again
+        assert!(!device.is_connected());
+    }
+
+    #[test]
+    fn resumes_after_lost_link() {
+        let directory = directory("firmware_resume");
+        let config = esp32(&directory);
+        let sink = Arc::new(Mutex::new(
+            FileSink::new(&directory.to_string_lossy(), &config).unwrap(),
+        ));
+        // handshake (3 writes), upload start (2) and two chunks (2 each), the third one is lost
+        let writes_until_loss = Arc::new(Mutex::new(Some(9)));
+        let connects = Arc::new(Mutex::new(0));
+
+        let link_connects = connects.clone();
+        let device = Device::with_connector(
+            &config,
+            Box::new(move |_: &DeviceConfig| {
+                *link_connects.lock().unwrap() += 1;
+                Some(Box::new(FlakyLink {
+                    sink: sink.clone(),
+                    writes_until_loss: writes_until_loss.clone(),
+                }) as Box<dyn DisplaySink>)
+            }),
+        );
+        assert!(device.connect());
+
+        let progress = Mutex::new(Vec::new());
+        let result = upload(&device, &image(), &|uploaded, _| {
+            progress.lock().unwrap().push(uploaded)
+        });
+        assert_eq!(result, Ok(()));
+        assert_eq!(*connects.lock().unwrap(), 2);
+        assert_eq!(uploaded(&directory), Some(image()));
+        // continued with the chunk, which was lost
+        assert_eq!(
+            progress.into_inner().unwrap(),
+            vec![0, 1024, 2048, 2048, 3072, 4096, IMAGE_SIZE]
+        );
+    }
+
+    #[test]
+    fn refuses_empty_image() {
+        let directory = directory("firmware_empty");
+        let device = Device::new(&esp32(&directory));
+        assert!(device.connect());
+        assert!(upload(&device, &[], &|_, _| {}).is_err());
+        assert_eq!(uploaded(&directory), None);
+    }
+}
@@ -0,0 +1,207 @@
+// Adapts the frame rate of a device to what its link can carry. The writer measures
+// how long every frame takes and waits between frames, so the link is only busy for
+// the configured share of the time. Frames arriving in the meantime replace each
+// other, only the newest one is written.
+use std::{
+    sync::atomic::{AtomicU32, Ordering},
+    time::{Duration, Instant},
+};
+
+// weight of the newest write in the average write time
+const SMOOTHING: f32 = 0.2;
+const FPS_WINDOW: Duration = Duration::from_secs(2);
+const MIN_JPEG_QUALITY: u8 = 40;
+const JPEG_QUALITY_STEP: u8 = 5;
+
+// read by the ui, written by the writer of the device
+#[derive(Default)]
+pub struct PacingStats {
+    // frames per second, times 100
+    pub fps: AtomicU32,
+    // replaced by a newer frame before the writer picked them up
+    pub dropped: AtomicU32,
+    // how long the last frame waited for the writer
+    pub queue_age_ms: AtomicU32,
+    // average time it takes to write a frame
+    pub write_ms: AtomicU32,
+}
+
+impl PacingStats {
+    pub fn fps(&self) -> f32 {
+        self.fps.load(Ordering::Acquire) as f32 / 100.0
+    }
+}
+
+pub struct FramePacer {
+    target_interval: Duration,
+    // share of the time the link may be busy, 0 - 1
+    link_budget: f32,
+    write_ms: Option<f32>,
+    last_write: Option<Instant>,
+    window_start: Instant,
+    window_frames: u32,
+}
+
+impl FramePacer {
+    pub fn new(target_fps: f32, link_budget: u8) -> FramePacer {
+        FramePacer {
+            target_interval: Duration::from_secs_f32(1.0 / target_fps.max(0.1)),
+            link_budget: link_budget.clamp(1, 100) as f32 / 100.0,
+            write_ms: None,
+            last_write: None,
+            window_start: Instant::now(),
+            window_frames: 0,
+        }
+    }
+
+    // a slow link stretches the interval beyond the target
+    fn frame_interval(&self) -> Duration {
+        let needed = match self.write_ms {
+            Some(write_ms) => Duration::from_secs_f32(write_ms / 1000.0 / self.link_budget),
+            None => Duration::ZERO,
+        };
+        self.target_interval.max(needed)
+    }
+
+    // how long the writer waits before it takes the next frame
+    pub fn wait_time(&self) -> Duration {
+        match self.last_write {
+            Some(last_write) => self.frame_interval().saturating_sub(last_write.elapsed()),
+            None => Duration::ZERO,
+        }
+    }
+
+    // for every frame the device shows, unchanged frames only keep the device alive
+    pub fn frame_shown(&mut self, queued: Instant, stats: &PacingStats) {
+        stats
+            .queue_age_ms
+            .store(queued.elapsed().as_millis() as u32, Ordering::Release);
+        self.window_frames += 1;
+        let elapsed = self.window_start.elapsed();
+        if elapsed >= FPS_WINDOW {
+            let fps = self.window_frames as f32 / elapsed.as_secs_f32();
+            stats.fps.store((fps * 100.0) as u32, Ordering::Release);
+            self.window_start = Instant::now();
+            self.window_frames = 0;
+        }
+    }
+
+    pub fn frame_written(&mut self, started: Instant, stats: &PacingStats) {
+        let write_ms = started.elapsed().as_secs_f32() * 1000.0;
+        let average = match self.write_ms {
+            Some(average) => average + (write_ms - average) * SMOOTHING,
+            None => write_ms,
+        };
+        self.write_ms = Some(average);
+        self.last_write = Some(started);
+        stats.write_ms.store(average as u32, Ordering::Release);
+    }
+
+    // lowers the quality, while the target rate does not fit into the budget, and
+    // raises it again up to the configured one, once there is room
+    pub fn adapt_jpeg_quality(&self, quality: u8, max_quality: u8) -> u8 {
+        let write_ms = match self.write_ms {
+            Some(write_ms) => write_ms,
+            None => return quality,
+        };
+        let budget_ms = self.target_interval.as_secs_f32() * 1000.0 * self.link_budget;
+        if write_ms > budget_ms {
+            quality
+                .saturating_sub(JPEG_QUALITY_STEP)
+                .max(MIN_JPEG_QUALITY.min(max_quality))
+        } else if write_ms < budget_ms / 2.0 {
+            quality.saturating_add(JPEG_QUALITY_STEP).min(max_quality)
+        } else {
+            quality
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a write that started `ms` ago and just finished
+    fn written(pacer: &mut FramePacer, ms: u64, stats: &PacingStats) {
+        pacer.frame_written(Instant::now() - Duration::from_millis(ms), stats);
+    }
+
+    #[test]
+    fn interval_follows_the_target_fps() {
+        let pacer = FramePacer::new(4.0, 50);
+        assert_eq!(pacer.frame_interval(), Duration::from_millis(250));
+        // nothing written yet, nothing to wait for
+        assert_eq!(pacer.wait_time(), Duration::ZERO);
+
+        // 100 ms writes fit into half of 250 ms
+        let mut pacer = FramePacer::new(4.0, 50);
+        written(&mut pacer, 100, &PacingStats::default());
+        assert_eq!(pacer.frame_interval(), Duration::from_millis(250));
+    }
+
+    #[test]
+    fn slow_link_stretches_the_interval() {
+        let mut pacer = FramePacer::new(4.0, 20);
+        written(&mut pacer, 100, &PacingStats::default());
+        let interval = pacer.frame_interval();
+        assert!(interval >= Duration::from_millis(500), "{:?}", interval);
+        assert!(interval < Duration::from_millis(550), "{:?}", interval);
+    }
+
+    #[test]
+    fn write_time_is_smoothed() {
+        let stats = PacingStats::default();
+        let mut pacer = FramePacer::new(4.0, 50);
+        written(&mut pacer, 100, &stats);
+        assert_eq!(stats.write_ms.load(Ordering::Acquire), 100);
+        written(&mut pacer, 0, &stats);
+        assert_eq!(stats.write_ms.load(Ordering::Acquire), 80);
+    }
+
+    #[test]
+    fn waits_for_the_rest_of_the_interval() {
+        let mut pacer = FramePacer::new(4.0, 50);
+        written(&mut pacer, 100, &PacingStats::default());
+        let wait_time = pacer.wait_time();
+        assert!(wait_time <= Duration::from_millis(150), "{:?}", wait_time);
+        assert!(wait_time > Duration::from_millis(50), "{:?}", wait_time);
+
+        // the write itself took longer than the interval
+        let mut pacer = FramePacer::new(4.0, 100);
+        written(&mut pacer, 300, &PacingStats::default());
+        assert_eq!(pacer.wait_time(), Duration::ZERO);
+    }
+
+    #[test]
+    fn jpeg_quality_follows_the_budget() {
+        // nothing measured yet
+        let pacer = FramePacer::new(4.0, 50);
+        assert_eq!(pacer.adapt_jpeg_quality(80, 80), 80);
+
+        // 125 ms of every 250 ms may be spent writing
+        let mut pacer = FramePacer::new(4.0, 50);
+        written(&mut pacer, 200, &PacingStats::default());
+        assert_eq!(pacer.adapt_jpeg_quality(80, 80), 75);
+        assert_eq!(pacer.adapt_jpeg_quality(42, 80), MIN_JPEG_QUALITY);
+        // never above the configured quality
+        assert_eq!(pacer.adapt_jpeg_quality(30, 30), 30);
+
+        let mut pacer = FramePacer::new(4.0, 50);
+        written(&mut pacer, 100, &PacingStats::default());
+        assert_eq!(pacer.adapt_jpeg_quality(60, 80), 60);
+
+        let mut pacer = FramePacer::new(4.0, 50);
+        written(&mut pacer, 10, &PacingStats::default());
+        assert_eq!(pacer.adapt_jpeg_quality(60, 80), 65);
+        assert_eq!(pacer.adapt_jpeg_quality(78, 80), 80);
+    }
+
+    #[test]
+    fn frame_shown_reports_the_queue_age() {
+        let stats = PacingStats::default();
+        let mut pacer = FramePacer::new(4.0, 50);
+        pacer.frame_shown(Instant::now() - Duration::from_millis(40), &stats);
+        assert!(stats.queue_age_ms.load(Ordering::Acquire) >= 40);
+        assert_eq!(stats.fps(), 0.0);
+    }
+}
@@ -1,39 +1,206 @@
 use image::{
-    codecs::webp::{WebPEncoder, WebPQuality},
+    codecs::{
+        jpeg::JpegEncoder,
+        qoi::QoiEncoder,
+        webp::{WebPEncoder, WebPQuality},
+    },
     ImageEncoder,
 };
+use serde::{Deserialize, Serialize};
 
-pub fn convert_to_gray_scale(bytes: &Vec<u8>) -> Vec<u8> {
-    let mut buffer = Vec::new();
-    for chunk in bytes.chunks(6) {
-        let gray = (0.299 * i32::pow(chunk[0] as i32, 2) as f32
-            + 0.587 * i32::pow(chunk[1] as i32, 2) as f32
-            + 0.114 * i32::pow(chunk[2] as i32, 2) as f32)
-            .sqrt();
-        let gray2 = (0.299 * i32::pow(chunk[3] as i32, 2) as f32
-            + 0.587 * i32::pow(chunk[4] as i32, 2) as f32
-            + 0.114 * i32::pow(chunk[5] as i32, 2) as f32)
-            .sqrt();
-        buffer.push(((gray / 16.0) as u8) << 4 | ((gray2 / 16.0) as u8));
+// how the 4 bit gray levels of the oled are approximated
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Dithering {
+    #[default]
+    None,
+    // 4x4 bayer matrix, stable between frames, good for text and icons
+    Ordered,
+    // error diffusion, best for photos like album covers
+    FloydSteinberg,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct GrayScaleOptions {
+    pub dithering: Dithering,
+    // gamma of the display, the rgb input is expected to be srgb
+    pub gamma: f32,
+    // in percent, applied in linear light
+    pub brightness: f32,
+}
+
+const BAYER_MATRIX: [[f32; 4]; 4] = [
+    [0.0, 8.0, 2.0, 10.0],
+    [12.0, 4.0, 14.0, 6.0],
+    [3.0, 11.0, 1.0, 9.0],
+    [15.0, 7.0, 13.0, 5.0],
+];
+const GRAY_LEVELS: f32 = 15.0;
+// linear light needs more steps in the dark parts than 8 bits
+const TABLE_STEPS: usize = 4096;
+
+// rgb to 4 bit gray scale, two pixels per byte with the high nibble first
+pub fn convert_to_gray_scale(bytes: &[u8], width: u32, options: &GrayScaleOptions) -> Vec<u8> {
+    let width = width as usize;
+    let table = gray_table(options);
+    let levels: Vec<f32> = bytes
+        .chunks(3)
+        .map(|chunk| {
+            // rec. 709 luminance in linear light
+            let luminance = 0.2126 * srgb_to_linear(chunk[0])
+                + 0.7152 * srgb_to_linear(chunk[1])
+                + 0.0722 * srgb_to_linear(chunk[2]);
+            table[(luminance * (TABLE_STEPS - 1) as f32).round() as usize]
+        })
+        .collect();
+
+    let gray = match options.dithering {
+        Dithering::None => levels.iter().map(|level| level.round() as u8).collect(),
+        Dithering::Ordered => levels
+            .iter()
+            .enumerate()
+            .map(|(index, level)| {
+                let threshold = BAYER_MATRIX[(index / width) % 4][index % width % 4];
+                (level + (threshold + 0.5) / 16.0 - 0.5).round() as u8
+            })
+            .collect(),
+        Dithering::FloydSteinberg => diffuse_error(levels, width),
+    };
+
+    gray.chunks(2)
+        .map(|pair| pair[0].min(15) << 4 | pair.get(1).copied().unwrap_or(0).min(15))
+        .collect()
+}
+
+fn srgb_to_linear(value: u8) -> f32 {
+    let value = value as f32 / 255.0;
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
     }
-    buffer
 }
 
-pub fn adjust_brightness_rgb(bytes: &Vec<u8>, brightness: f32) -> Vec<u8> {
-    let mut converted_sb_rgb = Vec::with_capacity(49152);
-    let set_brightness = |chunk_param: u8| (chunk_param as f32 * brightness / 100.0) as u8;
+// linear luminance to gray level, brightness and gamma baked in
+fn gray_table(options: &GrayScaleOptions) -> Vec<f32> {
+    let brightness = options.brightness.clamp(0.0, 100.0) / 100.0;
+    let gamma = if options.gamma > 0.0 {
+        options.gamma
+    } else {
+        1.0
+    };
+    (0..TABLE_STEPS)
+        .map(|step| {
+            (step as f32 / (TABLE_STEPS - 1) as f32 * brightness).powf(1.0 / gamma) * GRAY_LEVELS
+        })
+        .collect()
+}
 
-    for chunk in bytes.chunks(3) {
-        let chunk_two = set_brightness(chunk[2]);
-        let chunk_one = set_brightness(chunk[1]);
-        let chunk_zero = set_brightness(chunk[0]);
-        // for display
-        converted_sb_rgb.push(chunk_two);
-        converted_sb_rgb.push(chunk_one);
-        converted_sb_rgb.push(chunk_zero);
+fn diffuse_error(mut levels: Vec<f32>, width: usize) -> Vec<u8> {
+    let mut gray = Vec::with_capacity(levels.len());
+    for index in 0..levels.len() {
+        let level = levels[index].clamp(0.0, GRAY_LEVELS);
+        let quantized = level.round();
+        let error = level - quantized;
+        gray.push(quantized as u8);
+
+        let x = index % width;
+        let mut spread = |offset: usize, weight: f32| {
+            if let Some(next) = levels.get_mut(index + offset) {
+                *next += error * weight;
+            }
+        };
+        if x + 1 < width {
+            spread(1, 7.0 / 16.0);
+            spread(width + 1, 1.0 / 16.0);
+        }
+        if x > 0 {
+            spread(width - 1, 3.0 / 16.0);
+        }
+        spread(width, 5.0 / 16.0);
+    }
+    gray
+}
+
+// counterpart of `convert_to_gray_scale`, two pixels per byte with the high nibble first
+pub fn convert_from_gray_scale(bytes: &[u8], width: u32, height: u32) -> image::GrayImage {
+    image::GrayImage::from_fn(width, height, |x, y| {
+        let index = (y * width + x) as usize;
+        let byte = bytes.get(index / 2).copied().unwrap_or_default();
+        let gray = if index % 2 == 1 {
+            byte & 0x0f
+        } else {
+            byte >> 4
+        };
+        image::Luma([gray * 17])
+    })
+}
+
+// packbits like run length encoding of the gray scale frames: a control byte below 128
+// is followed by that many + 1 literal bytes, otherwise the next byte is repeated
+// (control - 126) times
+const MAX_LITERALS: usize = 128;
+const MIN_REPEAT: usize = 2;
+const MAX_REPEAT: usize = 129;
+
+pub fn encode_rle(bytes: &[u8]) -> Vec<u8> {
+    let mut encoded = Vec::with_capacity(bytes.len() / 2);
+    let mut literals_start = 0;
+    let mut index = 0;
+    while index < bytes.len() {
+        let mut repeat = 1;
+        while index + repeat < bytes.len()
+            && repeat < MAX_REPEAT
+            && bytes[index + repeat] == bytes[index]
+        {
+            repeat += 1;
+        }
+
+        // runs of two in between literals are cheaper as literals
+        if repeat > MIN_REPEAT || (repeat == MIN_REPEAT && literals_start == index) {
+            push_literals(&mut encoded, &bytes[literals_start..index]);
+            encoded.push((repeat + 126) as u8);
+            encoded.push(bytes[index]);
+            index += repeat;
+            literals_start = index;
+        } else {
+            index += repeat;
+        }
     }
+    push_literals(&mut encoded, &bytes[literals_start..]);
+    encoded
+}
 
-    converted_sb_rgb
+fn push_literals(encoded: &mut Vec<u8>, literals: &[u8]) {
+    for chunk in literals.chunks(MAX_LITERALS) {
+        encoded.push((chunk.len() - 1) as u8);
+        encoded.extend_from_slice(chunk);
+    }
+}
+
+// reference decoder, returns none for broken data or a different length
+pub fn decode_rle(encoded: &[u8], expected_len: usize) -> Option<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(expected_len);
+    let mut index = 0;
+    while index < encoded.len() {
+        let control = encoded[index] as usize;
+        if control < MAX_LITERALS {
+            decoded.extend_from_slice(encoded.get(index + 1..index + 2 + control)?);
+            index += 2 + control;
+        } else {
+            let value = *encoded.get(index + 1)?;
+            decoded.resize(decoded.len() + control - 126, value);
+            index += 2;
+        }
+        if decoded.len() > expected_len {
+            return None;
+        }
+    }
+    if decoded.len() == expected_len {
+        Some(decoded)
+    } else {
+        None
+    }
 }
 
 pub fn rgb_bytes_to_rgba_image(
@@ -69,10 +236,253 @@ pub fn swap_rgb(bytes: &Vec<u8>, width: u32, height: u32) -> Vec<u8> {
     swapped
 }
 
-pub fn convert_to_webp(bytes: &Vec<u8>, width: u32, height: u32) -> Vec<u8> {
+pub fn convert_to_webp(bytes: &[u8], width: u32, height: u32) -> Vec<u8> {
     let mut writer = Vec::new();
     WebPEncoder::new_with_quality(&mut writer, WebPQuality::lossy(100))
         .write_image(bytes, width, height, image::ColorType::Rgb8)
         .expect("FAILED TO ENCODE WEBP");
     writer
 }
+
+pub fn convert_to_jpeg(bytes: &[u8], width: u32, height: u32, quality: u8) -> Vec<u8> {
+    let mut writer = Vec::new();
+    JpegEncoder::new_with_quality(&mut writer, quality.clamp(1, 100))
+        .write_image(bytes, width, height, image::ColorType::Rgb8)
+        .expect("FAILED TO ENCODE JPEG");
+    writer
+}
+
+pub fn convert_to_qoi(bytes: &[u8], width: u32, height: u32) -> Vec<u8> {
+    let mut writer = Vec::new();
+    QoiEncoder::new(&mut writer)
+        .write_image(bytes, width, height, image::ColorType::Rgb8)
+        .expect("FAILED TO ENCODE QOI");
+    writer
+}
+
+pub fn convert_to_rgb565(bytes: &[u8]) -> Vec<u8> {
+    let mut buffer = Vec::with_capacity(bytes.len() / 3 * 2);
+    for chunk in bytes.chunks(3) {
+        let pixel =
+            (chunk[0] as u16 >> 3) << 11 | (chunk[1] as u16 >> 2) << 5 | chunk[2] as u16 >> 3;
+        buffer.extend_from_slice(&pixel.to_le_bytes());
+    }
+    buffer
+}
+
+// counterpart of `convert_to_rgb565`
+pub fn convert_from_rgb565(bytes: &[u8], width: u32, height: u32) -> image::RgbImage {
+    image::RgbImage::from_fn(width, height, |x, y| {
+        let index = ((y * width + x) * 2) as usize;
+        let pixel = match bytes.get(index..index + 2) {
+            Some(pixel) => u16::from_le_bytes([pixel[0], pixel[1]]),
+            None => 0,
+        };
+        // scale up, so white stays white
+        let red = (pixel >> 11) as u8;
+        let green = (pixel >> 5 & 0x3f) as u8;
+        let blue = (pixel & 0x1f) as u8;
+        image::Rgb([
+            red << 3 | red >> 2,
+            green << 2 | green >> 4,
+            blue << 3 | blue >> 2,
+        ])
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // two rows of 16 gray steps from black to white
+    fn gradient() -> Vec<u8> {
+        (0..32)
+            .flat_map(|index| {
+                let value = (index % 16 * 17) as u8;
+                [value, value, value]
+            })
+            .collect()
+    }
+
+    // 4x4 pixels of srgb 128, in between gray levels 7 and 8 of the oled
+    fn flat_gray() -> Vec<u8> {
+        vec![128; 4 * 4 * 3]
+    }
+
+    fn options(dithering: Dithering, gamma: f32, brightness: f32) -> GrayScaleOptions {
+        GrayScaleOptions {
+            dithering,
+            gamma,
+            brightness,
+        }
+    }
+
+    #[test]
+    fn gradient_without_dithering() {
+        // srgb is close to a gamma of 2.2, so every step gets its own level
+        assert_eq!(
+            convert_to_gray_scale(&gradient(), 16, &options(Dithering::None, 2.2, 100.0)),
+            [
+                0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef, 0x01, 0x23, 0x45, 0x67, 0x89, 0xab,
+                0xcd, 0xef
+            ]
+        );
+    }
+
+    #[test]
+    fn gradient_with_gamma() {
+        // a linear display spends its levels on the bright steps
+        assert_eq!(
+            convert_to_gray_scale(&gradient(), 16, &options(Dithering::None, 1.0, 100.0)),
+            [
+                0x00, 0x00, 0x11, 0x23, 0x45, 0x67, 0x9b, 0xdf, 0x00, 0x00, 0x11, 0x23, 0x45, 0x67,
+                0x9b, 0xdf
+            ]
+        );
+    }
+
+    #[test]
+    fn gradient_with_brightness() {
+        // half the light, not half the level
+        assert_eq!(
+            convert_to_gray_scale(&gradient(), 16, &options(Dithering::None, 2.2, 50.0)),
+            [
+                0x01, 0x22, 0x34, 0x45, 0x67, 0x78, 0x99, 0xab, 0x01, 0x22, 0x34, 0x45, 0x67, 0x78,
+                0x99, 0xab
+            ]
+        );
+    }
+
+    #[test]
+    fn gradient_with_ordered_dithering() {
+        assert_eq!(
+            convert_to_gray_scale(&gradient(), 16, &options(Dithering::Ordered, 2.2, 100.0)),
+            [
+                0x01, 0x23, 0x45, 0x67, 0x79, 0xab, 0xbd, 0xef, 0x01, 0x33, 0x45, 0x67, 0x89, 0xab,
+                0xcd, 0xef
+            ]
+        );
+    }
+
+    #[test]
+    fn gradient_with_error_diffusion() {
+        assert_eq!(
+            convert_to_gray_scale(
+                &gradient(),
+                16,
+                &options(Dithering::FloydSteinberg, 2.2, 100.0)
+            ),
+            [
+                0x01, 0x23, 0x45, 0x67, 0x89, 0xab, 0xcd, 0xef, 0x02, 0x24, 0x45, 0x67, 0x89, 0xab,
+                0xcd, 0xef
+            ]
+        );
+    }
+
+    #[test]
+    fn flat_gray_without_dithering() {
+        assert_eq!(
+            convert_to_gray_scale(&flat_gray(), 4, &options(Dithering::None, 2.2, 100.0)),
+            [0x77; 8]
+        );
+        assert_eq!(
+            convert_to_gray_scale(&flat_gray(), 4, &options(Dithering::None, 1.0, 100.0)),
+            [0x33; 8]
+        );
+        assert_eq!(
+            convert_to_gray_scale(&flat_gray(), 4, &options(Dithering::None, 2.2, 50.0)),
+            [0x55; 8]
+        );
+    }
+
+    #[test]
+    fn flat_gray_with_ordered_dithering() {
+        // a stable checkerboard of the two closest levels
+        assert_eq!(
+            convert_to_gray_scale(&flat_gray(), 4, &options(Dithering::Ordered, 2.2, 100.0)),
+            [0x78, 0x78, 0x87, 0x87, 0x78, 0x78, 0x87, 0x87]
+        );
+        assert_eq!(
+            convert_to_gray_scale(&flat_gray(), 4, &options(Dithering::Ordered, 2.2, 50.0)),
+            [0x55, 0x56, 0x65, 0x65, 0x56, 0x56, 0x65, 0x65]
+        );
+    }
+
+    #[test]
+    fn flat_gray_with_error_diffusion() {
+        assert_eq!(
+            convert_to_gray_scale(
+                &flat_gray(),
+                4,
+                &options(Dithering::FloydSteinberg, 2.2, 100.0)
+            ),
+            [0x78, 0x78, 0x87, 0x87, 0x78, 0x78, 0x87, 0x87]
+        );
+        assert_eq!(
+            convert_to_gray_scale(
+                &flat_gray(),
+                4,
+                &options(Dithering::FloydSteinberg, 1.0, 100.0)
+            ),
+            [0x33, 0x33, 0x34, 0x34, 0x33, 0x33, 0x34, 0x33]
+        );
+    }
+
+    fn rle_round_trip(bytes: &[u8]) -> Vec<u8> {
+        let encoded = encode_rle(bytes);
+        assert_eq!(decode_rle(&encoded, bytes.len()).as_deref(), Some(bytes));
+        encoded
+    }
+
+    #[test]
+    fn rle_round_trip_of_black_frame() {
+        // 8192 bytes of the oled shrink to 64 runs of a control and a value byte
+        let encoded = rle_round_trip(&[0; 8192]);
+        assert_eq!(encoded.len(), 64 * 2);
+    }
+
+    #[test]
+    fn rle_round_trip_of_literals_and_runs() {
+        rle_round_trip(&[]);
+        rle_round_trip(&[7]);
+        rle_round_trip(&[1, 1]);
+        rle_round_trip(&[1, 2, 2, 3, 3, 3, 4]);
+        rle_round_trip(&(0..=255).collect::<Vec<u8>>());
+        rle_round_trip(&[5; MAX_REPEAT + 1]);
+
+        let mut mixed = vec![0; 300];
+        mixed.extend((0..200).map(|index| (index * 7 % 251) as u8));
+        mixed.extend([0x77; 2]);
+        mixed.extend((0..200).map(|index| (index % 3) as u8));
+        mixed.extend([0xff; 1000]);
+        rle_round_trip(&mixed);
+    }
+
+    #[test]
+    fn rle_round_trip_of_gray_scale_frame() {
+        let packed = convert_to_gray_scale(
+            &gradient(),
+            16,
+            &options(Dithering::FloydSteinberg, 2.2, 100.0),
+        );
+        rle_round_trip(&packed);
+    }
+
+    #[test]
+    fn rle_decoder_rejects_broken_data() {
+        let encoded = encode_rle(&[1, 2, 3, 4, 4, 4, 4]);
+        assert_eq!(decode_rle(&encoded, 6), None);
+        assert_eq!(decode_rle(&encoded, 8), None);
+        assert_eq!(decode_rle(&encoded[..encoded.len() - 1], 7), None);
+        assert_eq!(decode_rle(&encoded[..3], 7), None);
+    }
+
+    #[test]
+    fn gray_scale_round_trip() {
+        let packed = convert_to_gray_scale(&gradient(), 16, &options(Dithering::None, 2.2, 100.0));
+        let image = convert_from_gray_scale(&packed, 16, 2);
+        for (x, y, pixel) in image.enumerate_pixels() {
+            assert_eq!(pixel.0[0], gradient()[((y * 16 + x) * 3) as usize]);
+        }
+    }
+}
@@ -0,0 +1,267 @@
+// Finds the parts of a rgb frame that changed since the last sent one, so the
+// companion display only gets the regions that are actually new (e.g. a clock tick).
+
+const TILE_SIZE: u32 = 16;
+// above this share of the screen (in percent) a full frame is cheaper than the regions
+const MAX_DIRTY_AREA: u32 = 50;
+const MAX_REGIONS: usize = 8;
+const REGION_HEADER_LENGTH: usize = 8;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Rect {
+    pub fn area(&self) -> u32 {
+        self.width * self.height
+    }
+}
+
+// returns none, if the whole frame should be sent instead
+pub fn dirty_rects(previous: &[u8], current: &[u8], width: u32, height: u32) -> Option<Vec<Rect>> {
+    if previous.len() != current.len() || current.len() != (width * height * 3) as usize {
+        return None;
+    }
+
+    let mut finished: Vec<Rect> = Vec::new();
+    // regions of the tile row above, which can still grow downwards
+    let mut open: Vec<Rect> = Vec::new();
+    for tile_y in (0..height).step_by(TILE_SIZE as usize) {
+        let tile_height = TILE_SIZE.min(height - tile_y);
+        let mut row: Vec<Rect> = Vec::new();
+        for tile_x in (0..width).step_by(TILE_SIZE as usize) {
+            let tile_width = TILE_SIZE.min(width - tile_x);
+            let tile = Rect {
+                x: tile_x,
+                y: tile_y,
+                width: tile_width,
+                height: tile_height,
+            };
+            if !tile_changed(previous, current, width, &tile) {
+                continue;
+            }
+            match row.last_mut() {
+                Some(last) if last.x + last.width == tile_x => last.width += tile_width,
+                _ => row.push(tile),
+            }
+        }
+
+        let mut next_open = Vec::new();
+        for mut rect in row {
+            if let Some(index) = open
+                .iter()
+                .position(|above| above.x == rect.x && above.width == rect.width)
+            {
+                let above = open.remove(index);
+                rect.y = above.y;
+                rect.height += above.height;
+            }
+            next_open.push(rect);
+        }
+        finished.append(&mut open);
+        open = next_open;
+    }
+    finished.append(&mut open);
+
+    let dirty_area: u32 = finished.iter().map(|rect| rect.area()).sum();
+    if finished.len() > MAX_REGIONS || dirty_area * 100 > width * height * MAX_DIRTY_AREA {
+        return None;
+    }
+    Some(finished)
+}
+
+fn tile_changed(previous: &[u8], current: &[u8], width: u32, tile: &Rect) -> bool {
+    (tile.y..tile.y + tile.height).any(|y| {
+        let start = ((y * width + tile.x) * 3) as usize;
+        let end = start + (tile.width * 3) as usize;
+        previous[start..end] != current[start..end]
+    })
+}
+
+pub fn crop_rgb(bytes: &[u8], width: u32, rect: &Rect) -> Vec<u8> {
+    let mut region = Vec::with_capacity((rect.area() * 3) as usize);
+    for y in rect.y..rect.y + rect.height {
+        let start = ((y * width + rect.x) * 3) as usize;
+        region.extend_from_slice(&bytes[start..start + (rect.width * 3) as usize]);
+    }
+    region
+}
+
+// x, y, width and height as u16 le, followed by the encoded region
+pub fn region_payload(rect: &Rect, encoded: &[u8]) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(REGION_HEADER_LENGTH + encoded.len());
+    for value in [rect.x, rect.y, rect.width, rect.height] {
+        payload.extend_from_slice(&(value as u16).to_le_bytes());
+    }
+    payload.extend_from_slice(encoded);
+    payload
+}
+
+pub fn split_region_payload(payload: &[u8]) -> Option<(Rect, &[u8])> {
+    if payload.len() < REGION_HEADER_LENGTH {
+        return None;
+    }
+    let value = |index: usize| u16::from_le_bytes([payload[index], payload[index + 1]]) as u32;
+    Some((
+        Rect {
+            x: value(0),
+            y: value(2),
+            width: value(4),
+            height: value(6),
+        },
+        &payload[REGION_HEADER_LENGTH..],
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDTH: u32 = 320;
+    const HEIGHT: u32 = 170;
+
+    fn frame() -> Vec<u8> {
+        vec![0; (WIDTH * HEIGHT * 3) as usize]
+    }
+
+    fn paint(frame: &mut [u8], x: u32, y: u32) {
+        frame[((y * WIDTH + x) * 3) as usize] = 255;
+    }
+
+    fn rect(x: u32, y: u32, width: u32, height: u32) -> Rect {
+        Rect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    #[test]
+    fn unchanged_frame_has_no_regions() {
+        assert_eq!(dirty_rects(&frame(), &frame(), WIDTH, HEIGHT), Some(vec![]));
+    }
+
+    #[test]
+    fn frames_of_another_size_are_sent_in_full() {
+        assert_eq!(dirty_rects(&frame(), &frame()[3..], WIDTH, HEIGHT), None);
+        assert_eq!(dirty_rects(&frame(), &frame(), WIDTH, HEIGHT + 1), None);
+    }
+
+    #[test]
+    fn changed_pixel_dirties_its_tile() {
+        let mut current = frame();
+        paint(&mut current, 17, 40);
+        assert_eq!(
+            dirty_rects(&frame(), &current, WIDTH, HEIGHT),
+            Some(vec![rect(16, 32, 16, 16)])
+        );
+
+        // the tiles at the bottom are cut off by the screen
+        let mut current = frame();
+        paint(&mut current, 319, 169);
+        assert_eq!(
+            dirty_rects(&frame(), &current, WIDTH, HEIGHT),
+            Some(vec![rect(304, 160, 16, 10)])
+        );
+    }
+
+    #[test]
+    fn neighbouring_tiles_are_merged() {
+        let mut current = frame();
+        // a block of 3x2 tiles and a single tile further right
+        for (x, y) in [
+            (0, 0),
+            (20, 5),
+            (40, 10),
+            (0, 20),
+            (20, 25),
+            (47, 31),
+            (100, 0),
+        ] {
+            paint(&mut current, x, y);
+        }
+        assert_eq!(
+            dirty_rects(&frame(), &current, WIDTH, HEIGHT),
+            Some(vec![rect(96, 0, 16, 16), rect(0, 0, 48, 32)])
+        );
+
+        // rows of different width stay apart
+        let mut current = frame();
+        for (x, y) in [(0, 0), (20, 0), (0, 20)] {
+            paint(&mut current, x, y);
+        }
+        assert_eq!(
+            dirty_rects(&frame(), &current, WIDTH, HEIGHT),
+            Some(vec![rect(0, 0, 32, 16), rect(0, 16, 16, 16)])
+        );
+    }
+
+    #[test]
+    fn too_many_regions_are_sent_as_full_frame() {
+        let mut current = frame();
+        for index in 0..MAX_REGIONS as u32 {
+            paint(&mut current, index * 32, 0);
+        }
+        assert_eq!(
+            dirty_rects(&frame(), &current, WIDTH, HEIGHT).map(|rects| rects.len()),
+            Some(MAX_REGIONS)
+        );
+
+        paint(&mut current, 0, 100);
+        assert_eq!(dirty_rects(&frame(), &current, WIDTH, HEIGHT), None);
+    }
+
+    #[test]
+    fn large_changes_are_sent_as_full_frame() {
+        // 80 of 170 rows are less than half of the screen
+        let mut current = frame();
+        for y in (0..80).step_by(TILE_SIZE as usize) {
+            for x in (0..WIDTH).step_by(TILE_SIZE as usize) {
+                paint(&mut current, x, y);
+            }
+        }
+        assert_eq!(
+            dirty_rects(&frame(), &current, WIDTH, HEIGHT),
+            Some(vec![rect(0, 0, WIDTH, 80)])
+        );
+
+        // 96 are more
+        for x in (0..WIDTH).step_by(TILE_SIZE as usize) {
+            paint(&mut current, x, 80);
+        }
+        assert_eq!(dirty_rects(&frame(), &current, WIDTH, HEIGHT), None);
+    }
+
+    #[test]
+    fn crops_the_region_out_of_the_frame() {
+        let frame: Vec<u8> = (0..4 * 3 * 3).map(|value| value as u8).collect();
+        assert_eq!(
+            crop_rgb(&frame, 4, &rect(1, 1, 2, 2)),
+            vec![15, 16, 17, 18, 19, 20, 27, 28, 29, 30, 31, 32]
+        );
+    }
+
+    #[test]
+    fn region_payload_round_trip() {
+        let region = rect(304, 160, 16, 10);
+        let payload = region_payload(&region, &[1, 2, 3]);
+        assert_eq!(payload.len(), REGION_HEADER_LENGTH + 3);
+        assert_eq!(
+            split_region_payload(&payload),
+            Some((region, &[1u8, 2, 3][..]))
+        );
+        assert_eq!(
+            split_region_payload(&payload[..REGION_HEADER_LENGTH]),
+            Some((region, &[][..]))
+        );
+        assert_eq!(
+            split_region_payload(&payload[..REGION_HEADER_LENGTH - 1]),
+            None
+        );
+    }
+}
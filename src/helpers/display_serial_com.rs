@@ -1,39 +1,105 @@
 use serialport;
 
-use std::time::Duration;
+use lazy_static::lazy_static;
+use std::{
+    collections::HashSet,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
 
+use crate::{dada_packet::DadaPacketDecoder, sinks::DisplaySink};
+
+// writes of a whole frame may take a while, reads are shortened to the reply timeout
+pub const PORT_TIMEOUT: Duration = Duration::from_millis(1000);
+
+lazy_static! {
+    // ports in use by a device, so identical devices do not grab the same port
+    static ref CLAIMED_PORTS: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
+}
+
+// returns the name of the opened port as well, it stays claimed until `release_port`
 pub fn init_serial(
-    device_string: &String,
+    identifier: &str,
+    serial_number: Option<&str>,
     baud: u32,
-) -> Option<std::boxed::Box<dyn serialport::SerialPort>> {
-    let ports = serialport::available_ports().expect("No ports found!");
+) -> Option<(String, std::boxed::Box<dyn serialport::SerialPort>)> {
+    let mut claimed_ports = CLAIMED_PORTS.lock().unwrap();
 
-    if ports.is_empty() {
-        return None;
+    // pseudo terminals (e.g. of the device emulator) are not listed as usb ports,
+    // so a port name can be given instead of vid and pid
+    if std::path::Path::new(identifier).exists() {
+        return claim_port(&mut claimed_ports, &[identifier], |name| {
+            open_port(name, baud)
+        });
     }
-    for p in ports {
-        match p.port_type {
+
+    let ports = serialport::available_ports().expect("No ports found!");
+    let candidates = matching_ports(identifier, serial_number, &ports);
+    claim_port(&mut claimed_ports, &candidates, |name| {
+        open_port(name, baud)
+    })
+}
+
+// usb ports with the vid and pid (or the name) of the identifier and the serial number,
+// if one is given, other ports only by name
+fn matching_ports<'a>(
+    identifier: &str,
+    serial_number: Option<&str>,
+    ports: &'a [serialport::SerialPortInfo],
+) -> Vec<&'a str> {
+    ports
+        .iter()
+        .filter(|p| match &p.port_type {
             serialport::SerialPortType::UsbPort(info) => {
                 let comp = format!("{:04x}{:04x}", info.vid, info.pid);
-                if device_string.eq(&comp) {
-                    let port = match serialport::new(p.port_name, baud)
-                        .timeout(Duration::from_millis(1000))
-                        .open()
-                    {
-                        Ok(port) => Some(port),
-                        Err(_) => continue,
-                    };
-                    return port;
-                }
+                (identifier.eq(&comp) || identifier.eq(&p.port_name))
+                    && (serial_number.is_none() || info.serial_number.as_deref() == serial_number)
             }
-            _ => {}
+            _ => identifier.eq(&p.port_name),
+        })
+        .map(|p| p.port_name.as_str())
+        .collect()
+}
+
+// opens the first candidate, that no other device claimed yet, and claims it
+fn claim_port<T>(
+    claimed_ports: &mut HashSet<String>,
+    candidates: &[&str],
+    mut open: impl FnMut(&str) -> Option<T>,
+) -> Option<(String, T)> {
+    for name in candidates {
+        if claimed_ports.contains(*name) {
+            continue;
+        }
+        if let Some(port) = open(name) {
+            claimed_ports.insert(name.to_string());
+            return Some((name.to_string(), port));
         }
     }
     None
 }
 
+pub fn release_port(port_name: &str) {
+    CLAIMED_PORTS.lock().unwrap().remove(port_name);
+}
+
+fn open_port(port_name: &str, baud: u32) -> Option<std::boxed::Box<dyn serialport::SerialPort>> {
+    serialport::new(port_name, baud)
+        .timeout(PORT_TIMEOUT)
+        .open()
+        .ok()
+}
+
+// serial ports time out, sockets with a read timeout would block on unix
+fn is_timeout(error: &std::io::Error) -> bool {
+    matches!(
+        error.kind(),
+        std::io::ErrorKind::TimedOut | std::io::ErrorKind::WouldBlock
+    )
+}
+
 pub fn write_screen_buffer(
-    port: &mut Option<std::boxed::Box<dyn serialport::SerialPort>>,
+    port: &mut Option<std::boxed::Box<dyn DisplaySink>>,
     screen_buf: &[u8],
 ) -> bool {
     match port.as_deref_mut() {
@@ -42,26 +108,63 @@ pub fn write_screen_buffer(
     }
 }
 
-pub fn read_bme_sensor(port: &mut Option<std::boxed::Box<dyn serialport::SerialPort>>) -> String {
-    let mut data: [u8; 14] = [0; 14];
-    if port.as_deref_mut().is_some() {
-        match port.as_deref_mut().unwrap().read_exact(&mut data) {
-            Ok(_) => {
-                // everything alright,
-                return std::str::from_utf8(&data).unwrap().to_string();
-            }
-            Err(_) => {
-                return String::new();
+// reads exactly `length` bytes, e.g. the answer to a bme request
+pub fn read_bytes(
+    port: &mut Option<std::boxed::Box<dyn DisplaySink>>,
+    length: usize,
+    timeout: Duration,
+) -> Option<Vec<u8>> {
+    let deadline = Instant::now() + timeout;
+    let mut data = vec![0u8; length];
+    let mut received = 0;
+    while received < length {
+        let now = Instant::now();
+        if now >= deadline {
+            return None;
+        }
+        let port = port.as_deref_mut()?;
+        port.set_reply_timeout(deadline - now);
+        match port.read(&mut data[received..]) {
+            Ok(0) => return None,
+            Ok(count) => received += count,
+            Err(ref e) if is_timeout(e) => (),
+            Err(_) => return None,
+        }
+    }
+    Some(data)
+}
+
+pub fn read_packet(
+    port: &mut Option<std::boxed::Box<dyn DisplaySink>>,
+    decoder: &mut DadaPacketDecoder,
+    timeout: Duration,
+) -> Option<Vec<u8>> {
+    let deadline = Instant::now() + timeout;
+    let mut data: [u8; 64] = [0; 64];
+    loop {
+        while let Some(packet) = decoder.next_packet() {
+            match packet {
+                Ok(packet) => return Some(packet),
+                Err(e) => eprintln!("Invalid packet from device: {}", e),
             }
         }
+        let now = Instant::now();
+        if now >= deadline {
+            return None;
+        }
+        let port = port.as_deref_mut()?;
+        port.set_reply_timeout(deadline - now);
+        match port.read(&mut data) {
+            // sinks without a back channel
+            Ok(0) => return None,
+            Ok(count) => decoder.push(&data[..count]),
+            Err(ref e) if is_timeout(e) => (),
+            Err(_) => return None,
+        }
     }
-    String::new()
 }
 
-pub fn send_command(
-    port: &mut Option<std::boxed::Box<dyn serialport::SerialPort>>,
-    command: &[u8],
-) -> bool {
+pub fn send_command(port: &mut Option<std::boxed::Box<dyn DisplaySink>>, command: &[u8]) -> bool {
     if port.as_deref_mut().is_some() {
         match port.as_deref_mut().unwrap().write(command) {
             Ok(_) => match port.as_deref_mut().unwrap().flush() {
@@ -70,10 +173,107 @@ pub fn send_command(
                 }
                 Err(_) => {}
             },
-            Err(ref e) if e.kind() == std::io::ErrorKind::TimedOut => (),
+            Err(ref e) if is_timeout(e) => (),
             Err(e) => eprintln!("{:?}", e),
         }
     }
 
     false
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serialport::{SerialPortInfo, SerialPortType, UsbPortInfo};
+
+    fn usb_port(name: &str, vid: u16, pid: u16, serial_number: Option<&str>) -> SerialPortInfo {
+        SerialPortInfo {
+            port_name: name.to_string(),
+            port_type: SerialPortType::UsbPort(UsbPortInfo {
+                vid,
+                pid,
+                serial_number: serial_number.map(str::to_string),
+                manufacturer: None,
+                product: None,
+            }),
+        }
+    }
+
+    fn ports() -> Vec<SerialPortInfo> {
+        vec![
+            SerialPortInfo {
+                port_name: "COM1".to_string(),
+                port_type: SerialPortType::Unknown,
+            },
+            usb_port("COM3", 0x16c0, 0x0483, Some("1234")),
+            usb_port("COM4", 0x303a, 0x1001, None),
+            usb_port("COM5", 0x16c0, 0x0483, Some("5678")),
+        ]
+    }
+
+    #[test]
+    fn ports_are_matched_by_vid_and_pid() {
+        let ports = ports();
+        assert_eq!(
+            matching_ports("16c00483", None, &ports),
+            vec!["COM3", "COM5"]
+        );
+        assert_eq!(matching_ports("303a1001", None, &ports), vec!["COM4"]);
+        assert!(matching_ports("12345678", None, &ports).is_empty());
+    }
+
+    #[test]
+    fn identical_devices_are_told_apart_by_serial_number() {
+        let ports = ports();
+        assert_eq!(
+            matching_ports("16c00483", Some("5678"), &ports),
+            vec!["COM5"]
+        );
+        assert!(matching_ports("16c00483", Some("9999"), &ports).is_empty());
+    }
+
+    #[test]
+    fn ports_are_matched_by_name() {
+        let ports = ports();
+        assert_eq!(matching_ports("COM1", None, &ports), vec!["COM1"]);
+        assert_eq!(matching_ports("COM5", None, &ports), vec!["COM5"]);
+        assert_eq!(matching_ports("COM5", Some("5678"), &ports), vec!["COM5"]);
+    }
+
+    #[test]
+    fn claimed_ports_are_skipped() {
+        let mut claimed_ports = HashSet::new();
+        let candidates = ["COM3", "COM5"];
+        let open = |name: &str| Some(name.to_lowercase());
+
+        assert_eq!(
+            claim_port(&mut claimed_ports, &candidates, open),
+            Some(("COM3".to_string(), "com3".to_string()))
+        );
+        assert_eq!(
+            claim_port(&mut claimed_ports, &candidates, open),
+            Some(("COM5".to_string(), "com5".to_string()))
+        );
+        assert_eq!(claim_port(&mut claimed_ports, &candidates, open), None);
+
+        claimed_ports.remove("COM3");
+        assert_eq!(
+            claim_port(&mut claimed_ports, &candidates, open),
+            Some(("COM3".to_string(), "com3".to_string()))
+        );
+    }
+
+    #[test]
+    fn ports_that_do_not_open_stay_unclaimed() {
+        let mut claimed_ports = HashSet::new();
+        let busy = |name: &str| (name != "COM3").then_some(());
+
+        assert_eq!(
+            claim_port(&mut claimed_ports, &["COM3", "COM5"], busy),
+            Some(("COM5".to_string(), ()))
+        );
+        assert_eq!(claimed_ports, HashSet::from(["COM5".to_string()]));
+        assert_eq!(claim_port(&mut claimed_ports, &["COM3"], busy), None);
+        assert_eq!(claimed_ports.len(), 1);
+    }
+}
@@ -1,8 +1,10 @@
 pub mod convert;
 pub mod convert_image;
 pub mod current_cover;
+pub mod dirty_rect;
 pub mod display_serial_com;
 pub mod keyboard;
 pub mod master_volume;
 pub mod power;
-pub mod text_manipulation;
+pub mod transform;
+pub mod transition;
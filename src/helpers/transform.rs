@@ -0,0 +1,211 @@
+// Orientation of a frame on the physical panel, for displays which are mounted upside
+// down in a case or mirrored behind glass. Applied to the rendered rgb frame, before
+// it is converted for the device.
+use image::{imageops, RgbImage};
+use serde::{Deserialize, Serialize};
+
+// clockwise, given in degrees in the config, other angles are rejected when it's loaded
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+#[serde(try_from = "u16", into = "u16")]
+pub enum Rotation {
+    #[default]
+    Degrees0,
+    Degrees90,
+    Degrees180,
+    Degrees270,
+}
+
+impl Rotation {
+    // portrait frames for a landscape panel and the other way round
+    pub fn swaps_sides(&self) -> bool {
+        matches!(self, Rotation::Degrees90 | Rotation::Degrees270)
+    }
+}
+
+impl TryFrom<u16> for Rotation {
+    type Error = String;
+
+    fn try_from(degrees: u16) -> Result<Rotation, String> {
+        match degrees {
+            0 => Ok(Rotation::Degrees0),
+            90 => Ok(Rotation::Degrees90),
+            180 => Ok(Rotation::Degrees180),
+            270 => Ok(Rotation::Degrees270),
+            _ => Err(format!("rotation {} is not 0, 90, 180 or 270", degrees)),
+        }
+    }
+}
+
+impl From<Rotation> for u16 {
+    fn from(rotation: Rotation) -> u16 {
+        match rotation {
+            Rotation::Degrees0 => 0,
+            Rotation::Degrees90 => 90,
+            Rotation::Degrees180 => 180,
+            Rotation::Degrees270 => 270,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug, Default)]
+pub struct Transform {
+    #[serde(default)]
+    pub rotation: Rotation,
+    // mirrored after the rotation, so in the orientation of the panel
+    #[serde(default)]
+    pub flip_horizontal: bool,
+    #[serde(default)]
+    pub flip_vertical: bool,
+    // position of the rotated frame on the panel, everything outside is cropped
+    #[serde(default)]
+    pub offset_x: i32,
+    #[serde(default)]
+    pub offset_y: i32,
+}
+
+impl Transform {
+    pub fn is_identity(&self) -> bool {
+        *self == Transform::default()
+    }
+
+    // returns the frame in the size of the panel, uncovered parts stay black
+    pub fn apply(
+        &self,
+        bytes: &[u8],
+        width: u32,
+        height: u32,
+        panel_width: u32,
+        panel_height: u32,
+    ) -> Vec<u8> {
+        if self.is_identity() && width == panel_width && height == panel_height {
+            return bytes.to_vec();
+        }
+        let frame = match RgbImage::from_raw(width, height, bytes.to_vec()) {
+            Some(frame) => frame,
+            None => return vec![0; (panel_width * panel_height * 3) as usize],
+        };
+
+        let mut frame = match self.rotation {
+            Rotation::Degrees0 => frame,
+            Rotation::Degrees90 => imageops::rotate90(&frame),
+            Rotation::Degrees180 => imageops::rotate180(&frame),
+            Rotation::Degrees270 => imageops::rotate270(&frame),
+        };
+        if self.flip_horizontal {
+            imageops::flip_horizontal_in_place(&mut frame);
+        }
+        if self.flip_vertical {
+            imageops::flip_vertical_in_place(&mut frame);
+        }
+
+        let mut panel = RgbImage::new(panel_width, panel_height);
+        imageops::replace(
+            &mut panel,
+            &frame,
+            self.offset_x as i64,
+            self.offset_y as i64,
+        );
+        panel.into_raw()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 3x2 frame, every pixel holds its index
+    fn frame() -> Vec<u8> {
+        (0..6u8).flat_map(|index| [index, index, index]).collect()
+    }
+
+    fn pixels(bytes: &[u8]) -> Vec<u8> {
+        bytes.chunks(3).map(|pixel| pixel[0]).collect()
+    }
+
+    fn rotation(degrees: u16) -> Transform {
+        Transform {
+            rotation: Rotation::try_from(degrees).unwrap(),
+            ..Transform::default()
+        }
+    }
+
+    #[test]
+    fn identity_keeps_the_frame() {
+        assert_eq!(Transform::default().apply(&frame(), 3, 2, 3, 2), frame());
+    }
+
+    #[test]
+    fn rotates_into_the_panel_size() {
+        assert_eq!(
+            pixels(&rotation(90).apply(&frame(), 3, 2, 2, 3)),
+            [3, 0, 4, 1, 5, 2]
+        );
+        assert_eq!(
+            pixels(&rotation(180).apply(&frame(), 3, 2, 3, 2)),
+            [5, 4, 3, 2, 1, 0]
+        );
+        assert_eq!(
+            pixels(&rotation(270).apply(&frame(), 3, 2, 2, 3)),
+            [2, 5, 1, 4, 0, 3]
+        );
+    }
+
+    #[test]
+    fn mirrors_after_the_rotation() {
+        let transform = Transform {
+            rotation: Rotation::Degrees90,
+            flip_horizontal: true,
+            ..Transform::default()
+        };
+        assert_eq!(
+            pixels(&transform.apply(&frame(), 3, 2, 2, 3)),
+            [0, 3, 1, 4, 2, 5]
+        );
+        let transform = Transform {
+            flip_vertical: true,
+            ..Transform::default()
+        };
+        assert_eq!(
+            pixels(&transform.apply(&frame(), 3, 2, 3, 2)),
+            [3, 4, 5, 0, 1, 2]
+        );
+    }
+
+    #[test]
+    fn offset_crops_and_leaves_black() {
+        let transform = Transform {
+            offset_x: 1,
+            offset_y: -1,
+            ..Transform::default()
+        };
+        assert_eq!(
+            pixels(&transform.apply(&frame(), 3, 2, 3, 2)),
+            [0, 3, 4, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn wrong_source_size_gives_black_panel() {
+        assert_eq!(rotation(90).apply(&frame(), 3, 3, 2, 3), vec![0; 2 * 3 * 3]);
+    }
+
+    #[test]
+    fn rotation_is_read_in_degrees() {
+        let transform: Transform = serde_json::from_str(r#"{"rotation": 270}"#).unwrap();
+        assert_eq!(transform, rotation(270));
+        assert_eq!(
+            serde_json::to_string(&rotation(90)).unwrap(),
+            r#"{"rotation":90,"flip_horizontal":false,"flip_vertical":false,"offset_x":0,"offset_y":0}"#
+        );
+        assert_eq!(
+            serde_json::from_str::<Transform>("{}").unwrap(),
+            Transform::default()
+        );
+    }
+
+    #[test]
+    fn other_angles_are_rejected() {
+        assert!(serde_json::from_str::<Transform>(r#"{"rotation": 45}"#).is_err());
+        assert!(serde_json::from_str::<Transform>(r#"{"rotation": 360}"#).is_err());
+    }
+}
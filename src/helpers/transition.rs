@@ -0,0 +1,119 @@
+// Intermediate frames between the outgoing and the incoming screen. The progress is
+// taken from the clock, so a transition takes the same time at every frame rate.
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum TransitionKind {
+    None,
+    // the new screen pushes the old one out to the left
+    SlideLeft,
+    SlideRight,
+    // the new screen is revealed from top to bottom
+    Wipe,
+    Crossfade,
+    // pixels switch one by one in random order, no gray levels in between, so it
+    // looks the same on the 4 bit oled
+    Dissolve,
+}
+
+// the rgb frames of the screen, which was shown when the switch started
+pub struct Transition {
+    main: Vec<u8>,
+    companion: Vec<u8>,
+    started: Instant,
+}
+
+impl Transition {
+    pub fn new(main: Vec<u8>, companion: Vec<u8>) -> Transition {
+        Transition {
+            main,
+            companion,
+            started: Instant::now(),
+        }
+    }
+
+    // 0 - 1, none once the transition is over
+    pub fn progress(&self, duration: Duration) -> Option<f32> {
+        let elapsed = self.started.elapsed();
+        if elapsed >= duration {
+            return None;
+        }
+        Some(elapsed.as_secs_f32() / duration.as_secs_f32())
+    }
+
+    pub fn main(&self) -> &[u8] {
+        &self.main
+    }
+
+    pub fn companion(&self) -> &[u8] {
+        &self.companion
+    }
+}
+
+// both frames have to be of the given size
+pub fn blend(
+    kind: TransitionKind,
+    from: &[u8],
+    to: &[u8],
+    width: u32,
+    height: u32,
+    progress: f32,
+) -> Vec<u8> {
+    let progress = progress.clamp(0.0, 1.0);
+    let (width, height) = (width as usize, height as usize);
+    match kind {
+        TransitionKind::None => to.to_vec(),
+        TransitionKind::SlideLeft | TransitionKind::SlideRight => {
+            let offset = (progress * width as f32) as usize;
+            let mut frame = Vec::with_capacity(to.len());
+            for y in 0..height {
+                let row = y * width * 3;
+                let (left, right, split) = if kind == TransitionKind::SlideLeft {
+                    (from, to, width - offset)
+                } else {
+                    (to, from, offset)
+                };
+                // the right end of the left frame (`split` pixels wide), followed by the
+                // start of the right one
+                let left_start = row + (width - split) * 3;
+                frame.extend_from_slice(&left[left_start..row + width * 3]);
+                frame.extend_from_slice(&right[row..row + (width - split) * 3]);
+            }
+            frame
+        }
+        TransitionKind::Wipe => {
+            let split = (progress * height as f32) as usize * width * 3;
+            let mut frame = to[..split].to_vec();
+            frame.extend_from_slice(&from[split..]);
+            frame
+        }
+        TransitionKind::Crossfade => from
+            .iter()
+            .zip(to)
+            .map(|(from, to)| (*from as f32 + (*to as f32 - *from as f32) * progress) as u8)
+            .collect(),
+        TransitionKind::Dissolve => {
+            let mut frame = from.to_vec();
+            for y in 0..height {
+                for x in 0..width {
+                    if threshold(x as u32, y as u32) < progress {
+                        let pixel = (y * width + x) * 3;
+                        frame[pixel..pixel + 3].copy_from_slice(&to[pixel..pixel + 3]);
+                    }
+                }
+            }
+            frame
+        }
+    }
+}
+
+// the same pseudo random order for every frame of a transition
+fn threshold(x: u32, y: u32) -> f32 {
+    let mut hash = x.wrapping_mul(0x9e37_79b1) ^ y.wrapping_mul(0x85eb_ca77);
+    hash ^= hash >> 15;
+    hash = hash.wrapping_mul(0xc2b2_ae35);
+    hash ^= hash >> 13;
+    (hash & 0xffff) as f32 / 65536.0
+}
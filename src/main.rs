@@ -1,16 +1,27 @@
 #![windows_subsystem = "windows"]
 extern crate winapi;
 
+mod capabilities;
 mod config;
 mod config_manager;
 mod dada_packet;
 mod device;
+mod encoding_benchmark;
+mod firmware;
+mod frame_pacer;
 mod helpers;
+mod protocol;
 mod screen_manager;
 mod screens;
+mod sensor;
+mod sensor_log;
+mod sinks;
 mod style;
+mod transition_player;
 mod weather;
 
+use capabilities::FEATURE_FIRMWARE_UPDATE;
+use config::{DeviceImageFormat, DisplayRole, InputAction};
 use debounce::EventDebouncer;
 use device::*;
 use helpers::keyboard::{self, set_last_key, start_global_key_grabber};
@@ -18,20 +29,19 @@ use helpers::power::window_proc;
 use helpers::{convert_image::*, power::register_power_broadcast};
 use iced::widget::Text;
 use iced::{executor, time, window, Application, Command, Element, Length, Settings};
-use image::ImageFormat;
 use lazy_static::lazy_static;
 use named_lock::NamedLock;
 use named_lock::Result;
-use once_cell::sync::Lazy;
+use once_cell::sync::OnceCell;
 
 use rusttype::Font as ft;
 use std::{
-    collections::HashMap,
     error::Error,
     fmt,
     rc::Rc,
     sync::{atomic::AtomicBool, Arc, Mutex, RwLock},
 };
+use transition_player::{Frames, TransitionPlayer};
 
 #[derive(Debug)]
 struct SuperError {
@@ -69,6 +79,7 @@ fn get_super_error() -> SuperError {
 
 struct BrightnessEvent {
     brightness_value: f32,
+    device: usize,
     event_type: String,
 }
 
@@ -76,13 +87,14 @@ impl Default for BrightnessEvent {
     fn default() -> BrightnessEvent {
         BrightnessEvent {
             brightness_value: 0f32,
+            device: 0,
             event_type: "brightness_event".into(),
         }
     }
 }
 impl PartialEq for BrightnessEvent {
     fn eq(&self, other: &Self) -> bool {
-        self.event_type == other.event_type
+        self.event_type == other.event_type && self.device == other.device
     }
 }
 const FONT_BYTES: &[u8] = include_bytes!("Liberation.ttf");
@@ -99,23 +111,30 @@ lazy_static! {
     static ref LAST_KEY_VALUE: Mutex<u32> = Mutex::new(0);
     static ref CLOSE_REQUESTED: Arc<AtomicBool> = Arc::new(AtomicBool::new(false));
     static ref HIBERNATING: Mutex<bool> = Mutex::new(false);
-    static ref LAST_BME_INFO: Mutex<(String, String)> = Mutex::new((String::new(), String::new()));
-}
-const TEENSY: &str = "teensy";
-const ESP32: &str = "esp32";
-
-static DEVICES: Lazy<HashMap<String, Device>> = Lazy::new(|| {
-    let mut m: HashMap<String, Device> = HashMap::new();
-    m.insert(
-        TEENSY.into(),
-        Device::new("16c00483".into(), 4608000, false, ImageFormat::Bmp, true),
-    );
-    m.insert(
-        ESP32.into(),
-        Device::new("303a1001".into(), 921600, true, ImageFormat::WebP, false),
-    );
-    m
-});
+    static ref SENSOR_HISTORY: Mutex<sensor::SensorHistory> =
+        Mutex::new(sensor::SensorHistory::new(sensor::HISTORY_LENGTH));
+}
+// filled from the device configuration, once the application starts
+static DEVICES: OnceCell<Vec<Device>> = OnceCell::new();
+
+fn devices() -> &'static [Device] {
+    match DEVICES.get() {
+        Some(devices) => devices,
+        None => &[],
+    }
+}
+
+// brightness change per encoder step, in percent
+const BRIGHTNESS_STEP: i32 = 5;
+
+// fast enough for the device with the highest target frame rate
+fn render_interval() -> std::time::Duration {
+    let fps = devices()
+        .iter()
+        .map(|device| device.config.target_fps)
+        .fold(config::default_target_fps(), f32::max);
+    std::time::Duration::from_secs_f32(1.0 / fps)
+}
 
 pub fn main() -> iced::Result {
     match signal_hook::flag::register(signal_hook::consts::SIGINT, CLOSE_REQUESTED.clone()) {
@@ -129,6 +148,21 @@ pub fn main() -> iced::Result {
     match lock {
         Ok(l) => match l.try_lock() {
             Ok(_) => {
+                // usage: awesome_info_display --upload-firmware <device name> <firmware file>
+                let args: Vec<String> = std::env::args().collect();
+                if let [_, option, name, path] = args.as_slice() {
+                    if option == "--upload-firmware" {
+                        attach_console();
+                        upload_firmware_cli(name, path);
+                        return Ok(());
+                    }
+                }
+                if args.get(1).map(String::as_str) == Some("--benchmark-encodings") {
+                    attach_console();
+                    benchmark_encodings_cli(&args[2..]);
+                    return Ok(());
+                }
+
                 // register power callback
                 register_power_broadcast(window_proc);
 
@@ -165,6 +199,9 @@ struct AwesomeDisplay {
     screens: Mutex<screen_manager::ScreenManager>,
     config_manager: Arc<RwLock<config_manager::ConfigManager>>,
     companion_brightness_debouncer: Mutex<EventDebouncer<BrightnessEvent>>,
+    device_brightness_debouncer: Mutex<EventDebouncer<BrightnessEvent>>,
+    firmware_path: String,
+    transitions: TransitionPlayer,
 }
 
 #[derive(Debug, Clone)]
@@ -176,10 +213,15 @@ enum Message {
     FontLoaded(Result<(), iced::font::Error>),
     MainScreenBrightnessChanged(f32),
     CompanionScreenBrightnessChanged(f32),
+    DeviceBrightnessChanged(usize, f32),
     ScreenStatusChanged(bool, String),
     KeyboardEventOccurred(iced::keyboard::KeyCode, u32),
     WindowEventOccurred(iced::Event),
     ConfigValueChanged(String, String),
+    FirmwarePathChanged(String),
+    UploadFirmware(usize),
+    ScreenModeChanged(u32),
+    CarouselToggled(bool),
 }
 
 impl Application for AwesomeDisplay {
@@ -192,84 +234,53 @@ impl Application for AwesomeDisplay {
         let symbols = Rc::new(ft::try_from_vec(Vec::from(SYMBOL_BYTES as &[u8])).unwrap());
         let config_manager =
             std::sync::Arc::new(RwLock::new(config_manager::ConfigManager::new(None)));
-        let mut screens: Vec<Box<dyn screens::BasicScreen>> = Vec::new();
-
-        screens.push(Box::new(
-            screens::system_info_screen::SystemInfoScreen::new(
-                String::from("System Info"),
-                String::from("system_info_screen"),
-                font.clone(),
-                config_manager.clone(),
-            ),
-        ));
-        screens.push(Box::new(screens::media_info_screen::MediaInfoScreen::new(
-            String::from("Media Info"),
-            String::from("media_info_screen"),
-            font.clone(),
-            symbols.clone(),
-            config_manager.clone(),
-        )));
-        screens.push(Box::new(screens::bitpanda_screen::BitpandaScreen::new(
-            String::from("Bitpanda Info"),
-            String::from("bitpanda_screen"),
-            font.clone(),
-            symbols.clone(),
-            config_manager.clone(),
-        )));
-        screens.push(Box::new(screens::weather_screen::WeatherScreen::new(
-            String::from("Weather Info"),
-            String::from("weather_screen"),
-            font.clone(),
-            symbols.clone(),
-            config_manager.clone(),
-        )));
-        screens.push(Box::new(
-            screens::current_date_screen::CurrentDateScreen::new(
-                String::from("Time Info"),
-                String::from("current_date_screen"),
-                font.clone(),
-                config_manager.clone(),
-            ),
-        ));
-        screens.push(Box::new(screens::ice_screen::IceScreen::new(
-            String::from("Ice Sorts"),
-            String::from("ice_screen"),
-            font.clone(),
-        )));
+        let screens = create_screens(font, symbols, config_manager.clone());
 
+        let companion_config_manager = config_manager.clone();
         let this = AwesomeDisplay {
             screens: Mutex::new(screen_manager::ScreenManager::new(screens)),
             config_manager,
             companion_brightness_debouncer: Mutex::new(EventDebouncer::new(
+                std::time::Duration::from_millis(500),
+                move |_event: BrightnessEvent| {
+                    // devices with a brightness of their own keep it
+                    let config = companion_config_manager.read().unwrap().config.clone();
+                    for (index, device) in devices().iter().enumerate() {
+                        if device.config.role == DisplayRole::Companion && device.is_connected() {
+                            let brightness = config.device_brightness(index);
+                            device.set_brightness((brightness as f32 * 2.55f32) as u8 - 1);
+                        }
+                    }
+                },
+            )),
+            device_brightness_debouncer: Mutex::new(EventDebouncer::new(
                 std::time::Duration::from_millis(500),
                 move |event: BrightnessEvent| {
-                    if DEVICES.get(ESP32).unwrap().is_connected() {
-                        DEVICES
-                            .get(ESP32)
-                            .unwrap()
-                            .set_brightness((event.brightness_value * 2.55 as f32) as u8 - 1);
+                    if let Some(device) = devices().get(event.device) {
+                        if device.is_connected() {
+                            device.set_brightness((event.brightness_value * 2.55 as f32) as u8 - 1);
+                        }
                     }
                 },
             )),
+            firmware_path: String::new(),
+            transitions: TransitionPlayer::default(),
         };
 
         // global key press listener
         start_global_key_grabber(keyboard::callback);
 
         // init device objects
-        for device in DEVICES.values() {
-            device.set_brightness(
-                (this
-                    .config_manager
-                    .read()
-                    .unwrap()
-                    .config
-                    .companion_brightness as f32
-                    * 2.55f32) as u8
-                    - 1,
-            );
+        let config = this.config_manager.read().unwrap().config.clone();
+        let _ = DEVICES.set(config.devices.iter().map(Device::new).collect());
+        for (index, device) in devices().iter().enumerate() {
+            let brightness = config.device_brightness(index);
+            device.set_brightness((brightness as f32 * 2.55f32) as u8 - 1);
             device.start_background_workers()
         }
+        if let Some(sensor_log) = config.sensor_log.clone() {
+            sensor_log::start_sensor_logger(sensor_log);
+        }
         (
             this,
             iced::font::load(SYMBOL_BYTES).map(Message::FontLoaded),
@@ -321,8 +332,7 @@ impl Application for AwesomeDisplay {
                         _ => None,
                     }
                 }),
-                time::every(std::time::Duration::from_millis(250))
-                    .map(|_| Message::UpdateCurrentScreen),
+                time::every(render_interval()).map(|_| Message::UpdateCurrentScreen),
                 iced::subscription::events().map(Message::WindowEventOccurred),
             ]
             .into_iter(),
@@ -330,16 +340,20 @@ impl Application for AwesomeDisplay {
     }
     fn update(&mut self, message: Message) -> Command<Message> {
         let mut screen_manager = self.screens.lock().unwrap();
+        let mut command = Command::none();
+        let carousel = self.config_manager.read().unwrap().config.carousel.clone();
         match message {
             Message::SaveConfig => {
                 self.config_manager.write().unwrap().save();
             }
             Message::NextScreen => {
+                screen_manager.pause_carousel(&carousel);
                 screen_manager.update_current_screen();
                 screen_manager.next_screen();
                 screen_manager.update_current_screen();
             }
             Message::PreviousScreen => {
+                screen_manager.pause_carousel(&carousel);
                 screen_manager.update_current_screen();
                 screen_manager.previous_screen();
                 screen_manager.update_current_screen();
@@ -347,7 +361,9 @@ impl Application for AwesomeDisplay {
             Message::UpdateCurrentScreen => {
                 if *LAST_KEY.lock().unwrap() {
                     *LAST_KEY.lock().unwrap() = false;
+                    screen_manager.pause_carousel(&carousel);
                     let val = *LAST_KEY_VALUE.lock().unwrap();
+                    screen_manager.current_screen().key_pressed(val);
                     if val == 174 || val == 175 {
                         // 1 is "volume mode"
                         screen_manager.set_screen_for_short("media_info_screen".into(), 1);
@@ -359,6 +375,18 @@ impl Application for AwesomeDisplay {
                     }
                     *LAST_KEY_VALUE.lock().unwrap() = 0;
                 }
+                screen_manager.rotate(&carousel);
+                screen_manager.update_current_screen();
+                // buttons and encoders of the devices are handled like the ui
+                let config = self.config_manager.read().unwrap().config.clone();
+                command =
+                    Command::batch(input_messages(&config).into_iter().map(|message| {
+                        Command::perform(async move { message }, |message| message)
+                    }));
+            }
+            Message::ScreenModeChanged(mode) => {
+                screen_manager.pause_carousel(&carousel);
+                screen_manager.current_screen().set_mode(mode);
                 screen_manager.update_current_screen();
             }
             Message::KeyboardEventOccurred(_event, key_code) => {
@@ -388,6 +416,26 @@ impl Application for AwesomeDisplay {
                         ..Default::default()
                     });
             }
+            Message::DeviceBrightnessChanged(index, slider_value) => {
+                if let Some(device) = self
+                    .config_manager
+                    .write()
+                    .unwrap()
+                    .config
+                    .devices
+                    .get_mut(index)
+                {
+                    device.brightness = Some(slider_value as u16);
+                }
+                self.device_brightness_debouncer
+                    .lock()
+                    .unwrap()
+                    .put(BrightnessEvent {
+                        brightness_value: slider_value,
+                        device: index,
+                        event_type: "device_brightness_event".into(),
+                    });
+            }
             Message::ScreenStatusChanged(status, screen) => {
                 if screen_manager.screen_deactivatable(&screen) {
                     screen_manager.set_status_for_screen(&screen, status);
@@ -396,12 +444,28 @@ impl Application for AwesomeDisplay {
             Message::ConfigValueChanged(key, value) => {
                 self.config_manager.write().unwrap().set_value(key, value);
             }
+            Message::CarouselToggled(enabled) => {
+                self.config_manager.write().unwrap().config.carousel.enabled = enabled;
+            }
+            Message::FirmwarePathChanged(path) => {
+                self.firmware_path = path;
+            }
+            Message::UploadFirmware(index) => {
+                if let Some(device) = devices().get(index) {
+                    let path = self.firmware_path.clone();
+                    std::thread::spawn(move || {
+                        upload_firmware_file(device, &path, &|status| {
+                            *device.firmware_status.lock().unwrap() = Some(status)
+                        })
+                    });
+                }
+            }
             _ => (),
         }
 
         // disconnect all devices, if application will be closed
         if CLOSE_REQUESTED.load(std::sync::atomic::Ordering::Acquire) {
-            for (_, device) in DEVICES.iter() {
+            for device in devices() {
                 if device.is_connected() {
                     device.reset_display();
                     device.disconnect();
@@ -410,7 +474,7 @@ impl Application for AwesomeDisplay {
             self.config_manager.write().unwrap().save();
             return window::close();
         }
-        Command::none()
+        command
     }
 
     fn theme(&self) -> iced::Theme {
@@ -425,37 +489,47 @@ impl Application for AwesomeDisplay {
 
     fn view(&self) -> Element<Message> {
         let mut screen_manager = self.screens.lock().unwrap();
-        let main_screen_bytes = screen_manager.current_screen().current_image().clone();
-        let companion_screen_bytes = screen_manager
-            .current_screen()
-            .current_image_for_companion()
-            .clone();
-
-        // preview image
-        let main_screen_image =
-            rgb_bytes_to_rgba_image(&swap_rgb(&main_screen_bytes, 256, 64), 256, 64);
-        let companion_screen_image =
-            rgb_bytes_to_rgba_image(&swap_rgb(&companion_screen_bytes, 320, 170), 320, 170);
-
-        // convert to gray scale for display
-        let main_screen_bytes = convert_to_gray_scale(&adjust_brightness_rgb(
-            &main_screen_bytes,
-            self.config_manager.read().unwrap().config.brightness as f32,
-        ));
-
-        for (device_name, buffer) in
-            vec![(TEENSY, main_screen_bytes), (ESP32, companion_screen_bytes)]
-        {
-            if !buffer.is_empty() {
-                DEVICES
-                    .get(device_name)
-                    .unwrap()
-                    .sender
-                    .try_send(buffer)
-                    .unwrap_or_default();
+        let config = self.config_manager.read().unwrap().config.clone();
+        let (main, companion) = screen_manager.frames();
+        let frames = Frames { main, companion };
+        let screen_dithering = screen_manager.current_screen().dithering();
+
+        // transitions are sent by the player, the screens as soon as it is done
+        let canvases = screens::Canvas::for_devices(&self.config_manager);
+        let (main_canvas, companion_canvas) = canvases;
+        self.transitions.set_target(frames.clone());
+        if let Some(transition) = screen_manager.take_transition() {
+            let device_config = config.clone();
+            self.transitions.play(
+                transition,
+                config.transition.clone(),
+                canvases,
+                move |frames| {
+                    let (payloads, _, _) =
+                        device_frames(&device_config, &frames, canvases, screen_dithering);
+                    for (device, payload) in devices().iter().zip(payloads) {
+                        device.queue_transition_frame(payload);
+                    }
+                },
+            );
+        }
+
+        let (payloads, main_preview, companion_preview) =
+            device_frames(&config, &frames, canvases, screen_dithering);
+        if !self.transitions.is_playing() {
+            for (device, payload) in devices().iter().zip(payloads) {
+                device.queue_frame(payload);
             }
         }
 
+        let carousel = config.carousel.clone();
+        let main_preview =
+            main_preview.unwrap_or_else(|| canvas_preview(&main_canvas, &frames.main));
+        let companion_preview = companion_preview
+            .unwrap_or_else(|| canvas_preview(&companion_canvas, &frames.companion));
+        let main_screen_image = preview_image(&main_preview);
+        let companion_screen_image = preview_image(&companion_preview);
+
         let mut column_parts = vec![
             iced::widget::button(
                 Text::new("Next screen").horizontal_alignment(iced::alignment::Horizontal::Center),
@@ -470,6 +544,11 @@ impl Application for AwesomeDisplay {
             .on_press(Message::PreviousScreen)
             .width(Length::Fixed(200f32))
             .into(),
+            iced::widget::checkbox("Rotate screens", carousel.enabled, Message::CarouselToggled)
+                .style(iced::theme::Checkbox::Custom(Box::new(style::Checkbox {})))
+                .width(Length::Fixed(200f32))
+                .into(),
+            iced::widget::text(carousel_status(&screen_manager, &carousel)).into(),
             iced::widget::text(format!(
                 "Main Brightness: {:.2}",
                 self.config_manager.read().unwrap().config.brightness
@@ -556,9 +635,34 @@ impl Application for AwesomeDisplay {
             .into(),
             iced::widget::Row::with_children(vec![iced::widget::vertical_space(10).into()]).into(),
             iced::widget::Row::with_children(vec![iced::widget::text("Devices").into()]).into(),
-            iced::widget::Row::with_children(device_status(TEENSY)).into(),
-            iced::widget::Row::with_children(device_status(ESP32)).into(),
         ];
+        for (index, device) in devices().iter().enumerate() {
+            left_column_after_screens
+                .push(iced::widget::Row::with_children(device_status(device)).into());
+            left_column_after_screens.push(pacing_status(device));
+            left_column_after_screens.push(
+                iced::widget::Slider::new(
+                    20.0..=100.0,
+                    config.device_brightness(index) as f32,
+                    move |value| Message::DeviceBrightnessChanged(index, value),
+                )
+                .width(Length::Fixed(190f32))
+                .step(1.0)
+                .into(),
+            );
+            if device.is_connected() && device.supports(FEATURE_FIRMWARE_UPDATE, false) {
+                left_column_after_screens.push(firmware_status(device, index));
+            }
+        }
+        left_column_after_screens.push(
+            iced::widget::text_input("Firmware file", &self.firmware_path)
+                .on_input(Message::FirmwarePathChanged)
+                .style(iced::theme::TextInput::Custom(Box::new(
+                    style::TextInput {},
+                )))
+                .width(Length::Fixed(200f32))
+                .into(),
+        );
 
         column_parts.append(&mut left_column_after_screens);
 
@@ -567,7 +671,7 @@ impl Application for AwesomeDisplay {
             .align_items(iced::Alignment::Center)
             .spacing(10);
 
-        let col2: iced::widget::Column<Message> = iced::widget::Column::new()
+        let mut col2: iced::widget::Column<Message> = iced::widget::Column::new()
             .padding(20)
             .align_items(iced::Alignment::Center)
             .width(Length::Fill)
@@ -575,21 +679,88 @@ impl Application for AwesomeDisplay {
             .push(iced::widget::text(screen_manager.current_screen().description()).size(25))
             .push(
                 main_screen_image
-                    .width(Length::Fixed(256f32))
-                    .height(Length::Fixed(64f32)),
+                    .width(Length::Fixed(main_preview.1 as f32))
+                    .height(Length::Fixed(main_preview.2 as f32)),
             )
             .spacing(10)
             .push(
                 // companion image
                 companion_screen_image
-                    .width(Length::Fixed(320f32))
-                    .height(Length::Fixed(170f32)),
+                    .width(Length::Fixed(companion_preview.1 as f32))
+                    .height(Length::Fixed(companion_preview.2 as f32)),
             );
+        let log = screen_manager.current_screen().log();
+        if !log.is_empty() {
+            col2 = col2.push(iced::widget::text(log.join("\n")).size(14));
+        }
 
         iced::widget::Row::new().push(col1).push(col2).into()
     }
 }
 
+// the built-in screens and the ones of the plugins, in the order they are shown
+fn create_screens(
+    font: Rc<ft<'static>>,
+    symbols: Rc<ft<'static>>,
+    config_manager: Arc<RwLock<config_manager::ConfigManager>>,
+) -> Vec<Box<dyn screens::BasicScreen>> {
+    let mut screens: Vec<Box<dyn screens::BasicScreen>> = Vec::new();
+
+    screens.push(Box::new(
+        screens::system_info_screen::SystemInfoScreen::new(
+            String::from("System Info"),
+            String::from("system_info_screen"),
+            font.clone(),
+            config_manager.clone(),
+        ),
+    ));
+    screens.push(Box::new(screens::media_info_screen::MediaInfoScreen::new(
+        String::from("Media Info"),
+        String::from("media_info_screen"),
+        font.clone(),
+        symbols.clone(),
+        config_manager.clone(),
+    )));
+    screens.push(Box::new(screens::bitpanda_screen::BitpandaScreen::new(
+        String::from("Bitpanda Info"),
+        String::from("bitpanda_screen"),
+        font.clone(),
+        symbols.clone(),
+        config_manager.clone(),
+    )));
+    screens.push(Box::new(screens::weather_screen::WeatherScreen::new(
+        String::from("Weather Info"),
+        String::from("weather_screen"),
+        font.clone(),
+        symbols.clone(),
+        config_manager.clone(),
+    )));
+    screens.push(Box::new(
+        screens::current_date_screen::CurrentDateScreen::new(
+            String::from("Time Info"),
+            String::from("current_date_screen"),
+            font.clone(),
+            config_manager.clone(),
+        ),
+    ));
+    screens.push(Box::new(screens::ice_screen::IceScreen::new(
+        String::from("Ice Sorts"),
+        String::from("ice_screen"),
+        font.clone(),
+        config_manager.clone(),
+    )));
+    let plugins = config_manager.read().unwrap().config.plugins.clone();
+    for plugin in plugins {
+        screens.push(Box::new(screens::plugin_screen::PluginScreen::new(
+            plugin,
+            font.clone(),
+            symbols.clone(),
+            config_manager.clone(),
+        )));
+    }
+    screens
+}
+
 fn special_checkbox<'a>(
     checked: bool,
     key: String,
@@ -614,12 +785,341 @@ fn device_connected_icon<'a>(is_connected: bool) -> iced::Element<'a, Message, i
     .into()
 }
 
-fn device_status<'a>(device: &str) -> Vec<iced::Element<'a, Message, iced::Renderer>> {
+fn benchmark_encodings_cli(args: &[String]) {
+    let font = Rc::new(ft::try_from_vec(Vec::from(FONT_BYTES as &[u8])).unwrap());
+    let symbols = Rc::new(ft::try_from_vec(Vec::from(SYMBOL_BYTES as &[u8])).unwrap());
+    let config_manager = Arc::new(RwLock::new(config_manager::ConfigManager::new(None)));
+    encoding_benchmark::run(args, create_screens(font, symbols, config_manager));
+}
+
+// the frames of all devices, transformed and converted from the screen, and the frames
+// of the first device of a role, which the preview shows as it is mounted
+fn device_frames(
+    config: &config::Config,
+    frames: &Frames,
+    (main_canvas, companion_canvas): (screens::Canvas, screens::Canvas),
+    screen_dithering: Dithering,
+) -> (
+    Vec<Vec<u8>>,
+    Option<(Vec<u8>, u32, u32)>,
+    Option<(Vec<u8>, u32, u32)>,
+) {
+    let mut payloads = Vec::new();
+    let mut main_preview = None;
+    let mut companion_preview = None;
+    for (index, device) in devices().iter().enumerate() {
+        let (buffer, canvas, preview) = match device.config.role {
+            DisplayRole::Main => (&frames.main, main_canvas, &mut main_preview),
+            DisplayRole::Companion => (&frames.companion, companion_canvas, &mut companion_preview),
+        };
+        let (frame_width, frame_height) = device.config.frame_size();
+        let frame = device.config.transform.apply(
+            &canvas.fit(buffer, frame_width, frame_height),
+            frame_width,
+            frame_height,
+            device.config.width,
+            device.config.height,
+        );
+        if preview.is_none() {
+            *preview = Some((frame.clone(), device.config.width, device.config.height));
+        }
+        payloads.push(match device.config.image_format {
+            // convert to gray scale for display
+            DeviceImageFormat::Bmp => convert_to_gray_scale(
+                &swap_rgb(&frame, device.config.width, device.config.height),
+                device.config.width,
+                &GrayScaleOptions {
+                    dithering: device.config.dithering.unwrap_or(screen_dithering),
+                    gamma: device.config.gamma,
+                    // devices without brightness command are dimmed in the frame
+                    brightness: if device.has_brightness_command() {
+                        100.0
+                    } else {
+                        config.device_brightness(index) as f32
+                    },
+                },
+            ),
+            // rgb, encoded by the device
+            _ => frame,
+        });
+    }
+    (payloads, main_preview, companion_preview)
+}
+
+// the rendered screen, for roles without device
+fn canvas_preview(canvas: &screens::Canvas, bytes: &[u8]) -> (Vec<u8>, u32, u32) {
+    (
+        canvas.fit(bytes, canvas.width, canvas.height),
+        canvas.width,
+        canvas.height,
+    )
+}
+
+// rgb bytes with their width and height
+fn preview_image(
+    preview: &(Vec<u8>, u32, u32),
+) -> iced::widget::Image<iced::widget::image::Handle> {
+    let (bytes, width, height) = preview;
+    rgb_bytes_to_rgba_image(&swap_rgb(bytes, *width, *height), *width, *height)
+}
+
+// countdown to the next switch of the carousel
+fn carousel_status(
+    screen_manager: &screen_manager::ScreenManager,
+    carousel: &config::CarouselConfig,
+) -> String {
+    if !carousel.enabled {
+        String::new()
+    } else if screen_manager.carousel_paused() {
+        String::from("Paused")
+    } else {
+        match screen_manager.next_switch_in() {
+            Some(next_switch_in) => format!("Next screen in {} s", next_switch_in.as_secs() + 1),
+            None => String::new(),
+        }
+    }
+}
+
+// messages for the input events of all devices, in the order they happened per device
+fn input_messages(config: &config::Config) -> Vec<Message> {
+    devices()
+        .iter()
+        .enumerate()
+        .flat_map(|(index, device)| {
+            device_input_messages(
+                index,
+                &device.config.inputs,
+                device.take_input_events(),
+                config.device_brightness(index),
+            )
+        })
+        .collect()
+}
+
+// the messages of the actions bound to the events of one device
+fn device_input_messages(
+    index: usize,
+    bindings: &[config::InputBinding],
+    events: Vec<protocol::InputEvent>,
+    brightness: u16,
+) -> Vec<Message> {
+    let mut messages = Vec::new();
+    // the steps of all rotations since the last tick add up
+    let mut changed_brightness = None;
+    for event in events {
+        let (action, steps) = match bindings
+            .iter()
+            .find_map(|binding| binding.steps(&event).map(|steps| (binding.action, steps)))
+        {
+            Some(binding) => binding,
+            None => continue,
+        };
+        match action {
+            InputAction::NextScreen | InputAction::PreviousScreen => {
+                let message = if (action == InputAction::NextScreen) == (steps > 0) {
+                    Message::NextScreen
+                } else {
+                    Message::PreviousScreen
+                };
+                messages.extend(std::iter::repeat(message).take(steps.unsigned_abs() as usize));
+            }
+            InputAction::ScreenMode(mode) => messages.push(Message::ScreenModeChanged(mode)),
+            InputAction::Brightness => {
+                let current = changed_brightness.unwrap_or(brightness as i32);
+                changed_brightness = Some((current + steps as i32 * BRIGHTNESS_STEP).clamp(1, 100));
+            }
+        }
+    }
+    if let Some(brightness) = changed_brightness {
+        messages.push(Message::DeviceBrightnessChanged(index, brightness as f32));
+    }
+    messages
+}
+
+// update button and the progress of the last upload
+fn firmware_status<'a>(
+    device: &Device,
+    index: usize,
+) -> iced::Element<'a, Message, iced::Renderer> {
+    let mut button = iced::widget::button(Text::new("Update firmware").size(14));
+    if !device.is_updating() {
+        button = button.on_press(Message::UploadFirmware(index));
+    }
+    let status = device.firmware_status.lock().unwrap().clone();
+    iced::widget::Column::with_children(vec![
+        button.into(),
+        iced::widget::text(status.unwrap_or_default())
+            .size(14)
+            .width(Length::Fixed(200f32))
+            .into(),
+    ])
+    .align_items(iced::Alignment::Center)
+    .into()
+}
+
+fn upload_firmware_file(device: &Device, path: &str, report: &dyn Fn(String)) {
+    let image = match std::fs::read(path) {
+        Ok(image) => image,
+        Err(e) => return report(format!("Could not read {}: {}", path, e)),
+    };
+    let result = firmware::upload(device, &image, &|uploaded, size| {
+        report(format!("Uploading firmware: {} %", uploaded * 100 / size))
+    });
+    match result {
+        Ok(_) => report(String::from("Firmware updated")),
+        Err(reason) => report(format!("Firmware update failed: {}", reason)),
+    }
+}
+
+// the windows subsystem starts without console, the command line options print to the one
+// they were started from
+fn attach_console() {
+    unsafe {
+        winapi::um::wincon::AttachConsole(winapi::um::wincon::ATTACH_PARENT_PROCESS);
+    }
+}
+
+fn upload_firmware_cli(name: &str, path: &str) {
+    let config = config_manager::ConfigManager::new(None).config;
+    let device = match config.devices.iter().find(|device| device.name == name) {
+        Some(device) => Device::new(device),
+        None => return eprintln!("No device {} in settings.json", name),
+    };
+    if !device.connect() {
+        return eprintln!("Could not connect to {}", name);
+    }
+    upload_firmware_file(&device, path, &|status| println!("{}", status));
+}
+
+// achieved frame rate, dropped frames and how long the last frame waited
+fn pacing_status<'a>(device: &Device) -> iced::Element<'a, Message, iced::Renderer> {
+    let stats = &device.pacing_stats;
+    iced::widget::text(format!(
+        "{:.1} fps, {} dropped, {} ms",
+        stats.fps(),
+        stats.dropped.load(std::sync::atomic::Ordering::Acquire),
+        stats
+            .queue_age_ms
+            .load(std::sync::atomic::Ordering::Acquire)
+    ))
+    .size(14)
+    .width(Length::Fixed(200f32))
+    .into()
+}
+
+fn device_status<'a>(device: &Device) -> Vec<iced::Element<'a, Message, iced::Renderer>> {
     vec![
-        iced::widget::Text::new(device.to_uppercase())
+        iced::widget::Text::new(device.config.name.to_uppercase())
             .width(Length::Fixed(146f32))
             .font(iced::Font::MONOSPACE)
             .into(),
-        device_connected_icon(DEVICES.get(device).unwrap().is_connected()),
+        device_connected_icon(device.is_connected()),
     ]
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::{default_input_bindings, InputBinding, InputTrigger},
+        protocol::InputEvent,
+    };
+
+    // messages hold iced types, which can't be compared
+    fn debug(messages: Vec<Message>) -> String {
+        format!("{:?}", messages)
+    }
+
+    fn rotate(steps: i16) -> InputEvent {
+        InputEvent::Rotate { encoder: 0, steps }
+    }
+
+    #[test]
+    fn buttons_switch_screens() {
+        let events = vec![
+            InputEvent::Press(0),
+            InputEvent::LongPress(0),
+            InputEvent::Press(0),
+            // not bound
+            InputEvent::Press(1),
+        ];
+        assert_eq!(
+            debug(device_input_messages(
+                0,
+                &default_input_bindings(),
+                events,
+                50
+            )),
+            "[NextScreen, PreviousScreen, NextScreen]"
+        );
+    }
+
+    #[test]
+    fn encoder_steps_repeat_the_action() {
+        let bindings = vec![
+            InputBinding {
+                trigger: InputTrigger::Rotate,
+                index: 1,
+                action: InputAction::NextScreen,
+            },
+            InputBinding {
+                trigger: InputTrigger::Press,
+                index: 1,
+                action: InputAction::ScreenMode(1),
+            },
+        ];
+        let events = vec![
+            InputEvent::Rotate {
+                encoder: 1,
+                steps: 2,
+            },
+            InputEvent::Rotate {
+                encoder: 1,
+                steps: -1,
+            },
+            InputEvent::Press(1),
+        ];
+        assert_eq!(
+            debug(device_input_messages(0, &bindings, events, 50)),
+            "[NextScreen, NextScreen, PreviousScreen, ScreenModeChanged(1)]"
+        );
+    }
+
+    #[test]
+    fn brightness_steps_add_up_to_one_change() {
+        let events = vec![rotate(3), InputEvent::Press(0), rotate(-1)];
+        assert_eq!(
+            debug(device_input_messages(
+                1,
+                &default_input_bindings(),
+                events,
+                50
+            )),
+            "[NextScreen, DeviceBrightnessChanged(1, 60.0)]"
+        );
+    }
+
+    #[test]
+    fn brightness_stays_in_range() {
+        let bindings = default_input_bindings();
+        assert_eq!(
+            debug(device_input_messages(0, &bindings, vec![rotate(5)], 90)),
+            "[DeviceBrightnessChanged(0, 100.0)]"
+        );
+        assert_eq!(
+            debug(device_input_messages(0, &bindings, vec![rotate(-30)], 50)),
+            "[DeviceBrightnessChanged(0, 1.0)]"
+        );
+        // clamped on every event, not only at the end
+        assert_eq!(
+            debug(device_input_messages(
+                0,
+                &bindings,
+                vec![rotate(5), rotate(-2)],
+                95
+            )),
+            "[DeviceBrightnessChanged(0, 90.0)]"
+        );
+        assert_eq!(debug(device_input_messages(0, &bindings, vec![], 50)), "[]");
+    }
+}
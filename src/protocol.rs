@@ -0,0 +1,543 @@
+// The commands a device understands and the replies it sends back. Adding a command
+// means adding a variant with its id, payload and reply here, `Device` and the
+// device emulator only work with these types.
+use std::time::Duration;
+
+use crate::{
+    capabilities::Capabilities,
+    dada_packet::{DadaPacket, ACK, NACK},
+};
+
+pub const BME_REPLY_LENGTH: usize = 14;
+const ENVIRONMENT_REPLY_LENGTH: usize = 8;
+const BME_TIMEOUT: Duration = Duration::from_millis(1000);
+const ACK_TIMEOUT: Duration = Duration::from_millis(1000);
+const CAPABILITIES_TIMEOUT: Duration = Duration::from_millis(500);
+// the length prefix of compressed frames is a u16
+pub const MAX_COMPRESSED_FRAME_LEN: usize = u16::MAX as usize;
+// erasing the flash before the first chunk takes a while
+const FIRMWARE_BEGIN_TIMEOUT: Duration = Duration::from_millis(5000);
+const FIRMWARE_CHUNK_TIMEOUT: Duration = Duration::from_millis(2000);
+// polled often, a device without an answer should not hold the port for long
+const INPUT_TIMEOUT: Duration = Duration::from_millis(200);
+
+// kinds of input events, each event is sent as kind, index and value (i16 le)
+const INPUT_PRESS: u8 = 1;
+const INPUT_LONG_PRESS: u8 = 2;
+const INPUT_ROTATE: u8 = 3;
+const INPUT_EVENT_LENGTH: usize = 4;
+
+pub enum Command<'a> {
+    Reset,
+    StandBy,
+    WakeUp,
+    Brightness(u8),
+    ReadBme,
+    // raw 4 bit gray scale or an encoded image in a dada packet
+    Frame {
+        data: &'a [u8],
+        use_dada_packet: bool,
+    },
+    KeepAlive,
+    // has to be acknowledged with the same sequence number
+    SequencedFrame {
+        sequence: u16,
+        data: &'a [u8],
+    },
+    // position, size and the encoded region, see `dirty_rect::region_payload`
+    Region(&'a [u8]),
+    Capabilities,
+    // run length encoded gray scale frame, prefixed with its length (u16 le)
+    CompressedFrame(&'a [u8]),
+    // encoding of the following frames and regions, see `capabilities`
+    SetEncoding(u8),
+    // all values of the bme280 including the pressure, answered in binary
+    ReadEnvironment,
+    // starts (or resumes) the upload of a firmware image with the given size and crc32,
+    // answered with the offset the device expects next
+    FirmwareBegin {
+        size: u32,
+        crc: u32,
+    },
+    // part of the image with its own crc32, answered with the offset expected next
+    FirmwareChunk {
+        offset: u32,
+        data: &'a [u8],
+    },
+    // checks the crc32 of the whole image
+    FirmwareVerify,
+    // starts the verified image
+    Reboot,
+    // button presses and encoder steps since the last read, answered with the events
+    ReadInput,
+}
+
+// how the answer to a command looks on the wire
+pub enum ReplyFormat {
+    None,
+    // fixed number of bytes, without packet
+    Raw(usize),
+    DadaPacket,
+}
+
+impl Command<'_> {
+    pub const RESET: u8 = 17;
+    pub const STAND_BY: u8 = 18;
+    pub const WAKE_UP: u8 = 19;
+    pub const BRIGHTNESS: u8 = 20;
+    pub const READ_BME: u8 = 205;
+    pub const FRAME: u8 = 228;
+    pub const KEEP_ALIVE: u8 = 229;
+    pub const SEQUENCED_FRAME: u8 = 230;
+    pub const REGION: u8 = 231;
+    pub const CAPABILITIES: u8 = 232;
+    pub const COMPRESSED_FRAME: u8 = 233;
+    pub const SET_ENCODING: u8 = 234;
+    pub const READ_ENVIRONMENT: u8 = 235;
+    pub const FIRMWARE_BEGIN: u8 = 236;
+    pub const FIRMWARE_CHUNK: u8 = 237;
+    pub const FIRMWARE_VERIFY: u8 = 238;
+    pub const REBOOT: u8 = 239;
+    pub const READ_INPUT: u8 = 240;
+
+    pub fn id(&self) -> u8 {
+        match self {
+            Command::Reset => Command::RESET,
+            Command::StandBy => Command::STAND_BY,
+            Command::WakeUp => Command::WAKE_UP,
+            Command::Brightness(_) => Command::BRIGHTNESS,
+            Command::ReadBme => Command::READ_BME,
+            Command::Frame { .. } => Command::FRAME,
+            Command::KeepAlive => Command::KEEP_ALIVE,
+            Command::SequencedFrame { .. } => Command::SEQUENCED_FRAME,
+            Command::Region(_) => Command::REGION,
+            Command::Capabilities => Command::CAPABILITIES,
+            Command::CompressedFrame(_) => Command::COMPRESSED_FRAME,
+            Command::SetEncoding(_) => Command::SET_ENCODING,
+            Command::ReadEnvironment => Command::READ_ENVIRONMENT,
+            Command::FirmwareBegin { .. } => Command::FIRMWARE_BEGIN,
+            Command::FirmwareChunk { .. } => Command::FIRMWARE_CHUNK,
+            Command::FirmwareVerify => Command::FIRMWARE_VERIFY,
+            Command::Reboot => Command::REBOOT,
+            Command::ReadInput => Command::READ_INPUT,
+        }
+    }
+
+    // bytes sent right after the command id
+    pub fn payload(&self) -> Option<Vec<u8>> {
+        match self {
+            Command::Brightness(brightness) => {
+                Some(DadaPacket::new(brightness.to_le_bytes().to_vec()).as_bytes())
+            }
+            Command::SetEncoding(encoding) => Some(DadaPacket::new(vec![*encoding]).as_bytes()),
+            Command::Frame {
+                data,
+                use_dada_packet: true,
+            } => Some(DadaPacket::new(data.to_vec()).as_bytes()),
+            Command::Frame {
+                data,
+                use_dada_packet: false,
+            } => Some(data.to_vec()),
+            Command::SequencedFrame { sequence, data } => {
+                Some(DadaPacket::with_sequence(*sequence, data.to_vec()).as_bytes())
+            }
+            Command::Region(data) => Some(DadaPacket::new(data.to_vec()).as_bytes()),
+            Command::CompressedFrame(data) => {
+                let mut payload = (data.len() as u16).to_le_bytes().to_vec();
+                payload.extend_from_slice(data);
+                Some(payload)
+            }
+            Command::FirmwareBegin { size, crc } => {
+                let mut payload = size.to_le_bytes().to_vec();
+                payload.extend_from_slice(&crc.to_le_bytes());
+                Some(DadaPacket::new(payload).as_bytes())
+            }
+            Command::FirmwareChunk { offset, data } => {
+                let mut payload = offset.to_le_bytes().to_vec();
+                payload.extend_from_slice(&crc32fast::hash(data).to_le_bytes());
+                payload.extend_from_slice(data);
+                Some(DadaPacket::new(payload).as_bytes())
+            }
+            _ => None,
+        }
+    }
+
+    pub fn reply_format(&self) -> ReplyFormat {
+        match self {
+            Command::ReadBme => ReplyFormat::Raw(BME_REPLY_LENGTH),
+            Command::SequencedFrame { .. }
+            | Command::Capabilities
+            | Command::ReadEnvironment
+            | Command::FirmwareBegin { .. }
+            | Command::FirmwareChunk { .. }
+            | Command::FirmwareVerify
+            | Command::ReadInput => ReplyFormat::DadaPacket,
+            _ => ReplyFormat::None,
+        }
+    }
+
+    pub fn reply_timeout(&self) -> Duration {
+        match self {
+            Command::ReadBme | Command::ReadEnvironment => BME_TIMEOUT,
+            Command::SequencedFrame { .. } => ACK_TIMEOUT,
+            Command::Capabilities => CAPABILITIES_TIMEOUT,
+            Command::FirmwareBegin { .. } => FIRMWARE_BEGIN_TIMEOUT,
+            Command::FirmwareChunk { .. } | Command::FirmwareVerify => FIRMWARE_CHUNK_TIMEOUT,
+            Command::ReadInput => INPUT_TIMEOUT,
+            _ => Duration::ZERO,
+        }
+    }
+
+    pub fn parse_reply(&self, bytes: &[u8]) -> Result<Reply, ProtocolError> {
+        match self {
+            Command::ReadBme => {
+                let text = std::str::from_utf8(bytes)
+                    .map_err(|_| ProtocolError::Malformed(self.id()))?
+                    .trim_end_matches('\0');
+                let mut parts = text.split(' ').map(|part| part.parse::<f32>());
+                match (parts.next(), parts.next()) {
+                    (Some(Ok(temperature)), Some(Ok(humidity))) => Ok(Reply::Bme {
+                        temperature,
+                        humidity,
+                        pressure: None,
+                    }),
+                    _ => Err(ProtocolError::Malformed(self.id())),
+                }
+            }
+            // temperature in 1/100 °C (i16), humidity in 1/100 % (u16), pressure in Pa (u32)
+            Command::ReadEnvironment => match bytes.get(..ENVIRONMENT_REPLY_LENGTH) {
+                Some(values) => Ok(Reply::Bme {
+                    temperature: i16::from_le_bytes([values[0], values[1]]) as f32 / 100.0,
+                    humidity: u16::from_le_bytes([values[2], values[3]]) as f32 / 100.0,
+                    pressure: Some(
+                        u32::from_le_bytes([values[4], values[5], values[6], values[7]]) as f32
+                            / 100.0,
+                    ),
+                }),
+                None => Err(ProtocolError::Malformed(self.id())),
+            },
+            Command::SequencedFrame { .. } => match bytes {
+                [ACK, low, high] => Ok(Reply::Ack(u16::from_le_bytes([*low, *high]))),
+                [NACK, low, high] => Ok(Reply::Nack(u16::from_le_bytes([*low, *high]))),
+                _ => Err(ProtocolError::Malformed(self.id())),
+            },
+            Command::Capabilities => Capabilities::parse(bytes)
+                .map(Reply::Capabilities)
+                .ok_or(ProtocolError::Malformed(self.id())),
+            Command::FirmwareBegin { .. } | Command::FirmwareChunk { .. } => match bytes {
+                [a, b, c, d] => Ok(Reply::FirmwareOffset(u32::from_le_bytes([*a, *b, *c, *d]))),
+                _ => Err(ProtocolError::Malformed(self.id())),
+            },
+            Command::FirmwareVerify => match bytes {
+                [verified] => Ok(Reply::FirmwareVerified(*verified == 1)),
+                _ => Err(ProtocolError::Malformed(self.id())),
+            },
+            // no events is an empty packet, unknown kinds of newer firmware are skipped
+            Command::ReadInput if bytes.len() % INPUT_EVENT_LENGTH == 0 => Ok(Reply::Input(
+                bytes
+                    .chunks(INPUT_EVENT_LENGTH)
+                    .filter_map(InputEvent::parse)
+                    .collect(),
+            )),
+            Command::ReadInput => Err(ProtocolError::Malformed(self.id())),
+            _ => Err(ProtocolError::UnexpectedReply(self.id())),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Reply {
+    // in °C, % and hPa, the pressure is only read with `Command::ReadEnvironment`
+    Bme {
+        temperature: f32,
+        humidity: f32,
+        pressure: Option<f32>,
+    },
+    Ack(u16),
+    Nack(u16),
+    Capabilities(Capabilities),
+    FirmwareOffset(u32),
+    FirmwareVerified(bool),
+    Input(Vec<InputEvent>),
+}
+
+// buttons and rotary encoders wired to a device, by their index on the device
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum InputEvent {
+    Press(u8),
+    LongPress(u8),
+    // steps since the last read, positive is clockwise
+    Rotate { encoder: u8, steps: i16 },
+}
+
+impl InputEvent {
+    fn parse(bytes: &[u8]) -> Option<InputEvent> {
+        match bytes {
+            [INPUT_PRESS, button, _, _] => Some(InputEvent::Press(*button)),
+            [INPUT_LONG_PRESS, button, _, _] => Some(InputEvent::LongPress(*button)),
+            [INPUT_ROTATE, encoder, low, high] => Some(InputEvent::Rotate {
+                encoder: *encoder,
+                steps: i16::from_le_bytes([*low, *high]),
+            }),
+            _ => None,
+        }
+    }
+
+    fn as_bytes(&self) -> [u8; INPUT_EVENT_LENGTH] {
+        match self {
+            InputEvent::Press(button) => [INPUT_PRESS, *button, 0, 0],
+            InputEvent::LongPress(button) => [INPUT_LONG_PRESS, *button, 0, 0],
+            InputEvent::Rotate { encoder, steps } => {
+                let steps = steps.to_le_bytes();
+                [INPUT_ROTATE, *encoder, steps[0], steps[1]]
+            }
+        }
+    }
+}
+
+impl Reply {
+    // what the firmware sends, used by the emulator and the file sink
+    pub fn as_bytes(&self) -> Vec<u8> {
+        match self {
+            Reply::Bme {
+                temperature,
+                humidity,
+                pressure: None,
+            } => {
+                let mut bytes = format!("{:.2} {:.2}", temperature, humidity).into_bytes();
+                bytes.resize(BME_REPLY_LENGTH, 0);
+                bytes
+            }
+            Reply::Bme {
+                temperature,
+                humidity,
+                pressure: Some(pressure),
+            } => {
+                let mut bytes = ((temperature * 100.0).round() as i16)
+                    .to_le_bytes()
+                    .to_vec();
+                bytes.extend_from_slice(&((humidity * 100.0).round() as u16).to_le_bytes());
+                bytes.extend_from_slice(&((pressure * 100.0).round() as u32).to_le_bytes());
+                DadaPacket::new(bytes).as_bytes()
+            }
+            Reply::Ack(sequence) => sequence_reply(ACK, *sequence),
+            Reply::Nack(sequence) => sequence_reply(NACK, *sequence),
+            Reply::Capabilities(capabilities) => {
+                DadaPacket::new(capabilities.as_bytes()).as_bytes()
+            }
+            Reply::FirmwareOffset(offset) => {
+                DadaPacket::new(offset.to_le_bytes().to_vec()).as_bytes()
+            }
+            Reply::FirmwareVerified(verified) => DadaPacket::new(vec![*verified as u8]).as_bytes(),
+            Reply::Input(events) => {
+                DadaPacket::new(events.iter().flat_map(InputEvent::as_bytes).collect()).as_bytes()
+            }
+        }
+    }
+}
+
+fn sequence_reply(kind: u8, sequence: u16) -> Vec<u8> {
+    let sequence = sequence.to_le_bytes();
+    DadaPacket::new(vec![kind, sequence[0], sequence[1]]).as_bytes()
+}
+
+#[derive(Debug)]
+pub enum ProtocolError {
+    // command or payload could not be written
+    Disconnected,
+    // no (complete) reply within the timeout of the command
+    Timeout(u8),
+    Malformed(u8),
+    UnexpectedReply(u8),
+}
+
+impl std::fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Disconnected => write!(f, "device disconnected"),
+            Self::Timeout(command) => write!(f, "no reply to command {}", command),
+            Self::Malformed(command) => write!(f, "malformed reply to command {}", command),
+            Self::UnexpectedReply(command) => write!(f, "command {} has no reply", command),
+        }
+    }
+}
+
+impl std::error::Error for ProtocolError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        capabilities::*,
+        dada_packet::{split_sequenced_payload, DadaPacketDecoder},
+    };
+
+    fn unpack(bytes: &[u8]) -> Vec<u8> {
+        let mut packets = DadaPacketDecoder::default().decode(bytes);
+        assert_eq!(packets.len(), 1);
+        packets.remove(0).unwrap()
+    }
+
+    // what the host makes of the bytes the firmware sends
+    fn round_trip(command: Command, reply: Reply) {
+        let bytes = reply.as_bytes();
+        let payload = match command.reply_format() {
+            ReplyFormat::Raw(length) => {
+                assert_eq!(bytes.len(), length);
+                bytes
+            }
+            ReplyFormat::DadaPacket => unpack(&bytes),
+            ReplyFormat::None => panic!("command {} has no reply", command.id()),
+        };
+        assert_eq!(command.parse_reply(&payload).unwrap(), reply);
+    }
+
+    #[test]
+    fn replies_survive_the_round_trip() {
+        round_trip(
+            Command::ReadBme,
+            Reply::Bme {
+                temperature: 21.5,
+                humidity: 45.25,
+                pressure: None,
+            },
+        );
+        round_trip(
+            Command::ReadEnvironment,
+            Reply::Bme {
+                temperature: -3.5,
+                humidity: 80.0,
+                pressure: Some(1013.25),
+            },
+        );
+        let frame = Command::SequencedFrame {
+            sequence: 0,
+            data: &[],
+        };
+        round_trip(frame, Reply::Ack(0x4144));
+        let frame = Command::SequencedFrame {
+            sequence: 0,
+            data: &[],
+        };
+        round_trip(frame, Reply::Nack(7));
+        round_trip(
+            Command::Capabilities,
+            Reply::Capabilities(Capabilities {
+                version: PROTOCOL_VERSION,
+                width: 320,
+                height: 170,
+                pixel_format: PIXEL_FORMAT_RGB888,
+                encodings: ENCODING_WEBP | ENCODING_JPEG,
+                features: FEATURE_SEQUENCED_FRAMES | FEATURE_FIRMWARE_UPDATE,
+            }),
+        );
+        round_trip(
+            Command::FirmwareBegin { size: 10, crc: 1 },
+            Reply::FirmwareOffset(0x41_44_41_44),
+        );
+        round_trip(Command::FirmwareVerify, Reply::FirmwareVerified(true));
+        round_trip(
+            Command::ReadInput,
+            Reply::Input(vec![
+                InputEvent::Press(1),
+                InputEvent::LongPress(2),
+                InputEvent::Rotate {
+                    encoder: 0,
+                    steps: -3,
+                },
+            ]),
+        );
+        round_trip(Command::ReadInput, Reply::Input(Vec::new()));
+    }
+
+    #[test]
+    fn malformed_replies_are_rejected() {
+        assert!(matches!(
+            Command::ReadBme.parse_reply(b"21.50\0\0\0\0\0\0\0\0\0"),
+            Err(ProtocolError::Malformed(Command::READ_BME))
+        ));
+        assert!(matches!(
+            Command::ReadEnvironment.parse_reply(&[0; 7]),
+            Err(ProtocolError::Malformed(Command::READ_ENVIRONMENT))
+        ));
+        assert!(matches!(
+            Command::Capabilities.parse_reply(&[PROTOCOL_VERSION]),
+            Err(ProtocolError::Malformed(Command::CAPABILITIES))
+        ));
+        assert!(matches!(
+            Command::FirmwareVerify.parse_reply(&[1, 1]),
+            Err(ProtocolError::Malformed(Command::FIRMWARE_VERIFY))
+        ));
+        assert!(matches!(
+            Command::ReadInput.parse_reply(&[INPUT_PRESS, 1, 0]),
+            Err(ProtocolError::Malformed(Command::READ_INPUT))
+        ));
+        assert!(matches!(
+            Command::KeepAlive.parse_reply(&[]),
+            Err(ProtocolError::UnexpectedReply(Command::KEEP_ALIVE))
+        ));
+    }
+
+    #[test]
+    fn unknown_input_events_are_skipped() {
+        let reply = Command::ReadInput.parse_reply(&[9, 0, 0, 0, INPUT_PRESS, 4, 0, 0]);
+        assert_eq!(reply.unwrap(), Reply::Input(vec![InputEvent::Press(4)]));
+    }
+
+    #[test]
+    fn payloads_are_encoded_for_the_firmware() {
+        assert_eq!(Command::Reset.payload(), None);
+        assert_eq!(
+            unpack(&Command::Brightness(60).payload().unwrap()),
+            vec![60]
+        );
+        assert_eq!(
+            Command::Frame {
+                data: &[1, 2],
+                use_dada_packet: false
+            }
+            .payload(),
+            Some(vec![1, 2])
+        );
+        assert_eq!(
+            unpack(
+                &Command::Frame {
+                    data: b"DADA",
+                    use_dada_packet: true
+                }
+                .payload()
+                .unwrap()
+            ),
+            b"DADA".to_vec()
+        );
+
+        let packet = Command::SequencedFrame {
+            sequence: 513,
+            data: &[1, 2, 3],
+        }
+        .payload()
+        .unwrap();
+        let payload = unpack(&packet);
+        assert_eq!(
+            split_sequenced_payload(&payload),
+            Some((513, &[1u8, 2, 3][..]))
+        );
+
+        assert_eq!(
+            Command::CompressedFrame(&[5; 300]).payload().unwrap()[..2],
+            300u16.to_le_bytes()
+        );
+
+        let chunk = unpack(
+            &Command::FirmwareChunk {
+                offset: 256,
+                data: &[7; 4],
+            }
+            .payload()
+            .unwrap(),
+        );
+        assert_eq!(chunk[..4], 256u32.to_le_bytes());
+        assert_eq!(chunk[4..8], crc32fast::hash(&[7; 4]).to_le_bytes());
+        assert_eq!(chunk[8..], [7; 4]);
+    }
+}
@@ -1,3 +1,5 @@
+use crate::config::CarouselConfig;
+use crate::helpers::transition::Transition;
 use std::time::Duration;
 use std::time::Instant;
 
@@ -7,6 +9,12 @@ pub struct ScreenManager {
     timeout: Option<std::time::Instant>,
     last_screen: usize,
     switch_in_progress: bool,
+    // when the carousel shows the next screen, none while it is off or paused
+    next_switch: Option<Instant>,
+    // switching by hand pauses the carousel until then
+    paused_until: Option<Instant>,
+    // frames of the screen shown before the last switch
+    transition: Option<Transition>,
 }
 
 impl ScreenManager {
@@ -17,6 +25,9 @@ impl ScreenManager {
             timeout: Some(Instant::now()),
             last_screen: 0,
             switch_in_progress: false,
+            next_switch: None,
+            paused_until: None,
+            transition: None,
         };
 
         if !this.screens[this.current].enabled() {
@@ -38,6 +49,7 @@ impl ScreenManager {
                 && self.timeout.unwrap_or(Instant::now()).elapsed() >= seconds
             {
                 self.screens[self.current].update();
+                self.begin_transition();
                 self.current = self.last_screen;
                 self.switch_in_progress = false;
             } else {
@@ -51,6 +63,7 @@ impl ScreenManager {
 
     pub fn next_screen(&mut self) {
         self.current_screen().stop();
+        self.begin_transition();
         self.switch_in_progress = false;
         self.find_next_enabled_screen();
         self.current_screen().start();
@@ -58,17 +71,82 @@ impl ScreenManager {
 
     pub fn previous_screen(&mut self) {
         self.current_screen().stop();
+        self.begin_transition();
         self.switch_in_progress = false;
         self.find_previous_enabled_screen();
         self.current_screen().start();
     }
 
+    // called with every update, switches screens once the dwell time is over
+    pub fn rotate(&mut self, carousel: &CarouselConfig) {
+        if !carousel.enabled {
+            self.next_switch = None;
+            self.paused_until = None;
+            return;
+        }
+        let now = Instant::now();
+        match self.paused_until {
+            Some(paused_until) if now < paused_until => return,
+            Some(_) => self.paused_until = None,
+            None => {}
+        }
+        if self.switch_in_progress {
+            return;
+        }
+
+        match self.next_switch {
+            Some(next_switch) if now < next_switch => {}
+            Some(_) => {
+                self.next_screen_with_content();
+                self.next_switch = Some(now + carousel.dwell_time(&self.current_screen().key()));
+            }
+            None => {
+                self.next_switch = Some(now + carousel.dwell_time(&self.current_screen().key()));
+            }
+        }
+    }
+
+    pub fn pause_carousel(&mut self, carousel: &CarouselConfig) {
+        if carousel.enabled {
+            self.next_switch = None;
+            self.paused_until = Some(Instant::now() + carousel.resume_after());
+        }
+    }
+
+    pub fn carousel_paused(&self) -> bool {
+        self.paused_until.is_some()
+    }
+
+    // time left until the carousel switches
+    pub fn next_switch_in(&self) -> Option<Duration> {
+        self.next_switch
+            .map(|next_switch| next_switch.saturating_duration_since(Instant::now()))
+    }
+
+    // stays on the current screen, if no other one has something to show
+    fn next_screen_with_content(&mut self) {
+        let start = self.current;
+        let mut next = start;
+        for _ in 0..self.screens.len() {
+            next = self.next_enabled_screen(next);
+            if next == start || self.screens[next].has_content() {
+                break;
+            }
+        }
+        if next == start {
+            return;
+        }
+        self.current_screen().stop();
+        self.begin_transition();
+        self.current = next;
+        self.current_screen().start();
+    }
+
     pub fn update_current_screen(&mut self) {
         self.current_screen().update();
     }
 
     pub fn set_screen_for_short(&mut self, key: String, mode: u32) {
-        
         let index: usize = match self.screens.iter_mut().position(|r| *r.key() == key) {
             Some(idx) => idx,
             None => return,
@@ -80,6 +158,7 @@ impl ScreenManager {
         self.timeout = Some(Instant::now());
         if !self.switch_in_progress {
             self.current_screen().stop();
+            self.begin_transition();
             self.last_screen = self.current;
         }
         self.current = index;
@@ -88,6 +167,28 @@ impl ScreenManager {
         self.switch_in_progress = true;
     }
 
+    // the frames of the main and the companion display
+    pub fn frames(&mut self) -> (Vec<u8>, Vec<u8>) {
+        let screen = self.current_screen();
+        (
+            screen.current_image().clone(),
+            screen.current_image_for_companion().clone(),
+        )
+    }
+
+    // a transition started since the last call, played apart from the screens
+    pub fn take_transition(&mut self) -> Option<Transition> {
+        self.transition.take()
+    }
+
+    // keeps what the outgoing screen shows right now, a running transition is cut short
+    fn begin_transition(&mut self) {
+        let screen = &mut self.screens[self.current];
+        let main = screen.current_image().clone();
+        let companion = screen.current_image_for_companion().clone();
+        self.transition = Some(Transition::new(main, companion));
+    }
+
     fn find_previous_enabled_screen(&mut self) {
         loop {
             self.current = if self.current == 0 {
@@ -102,10 +203,14 @@ impl ScreenManager {
     }
 
     fn find_next_enabled_screen(&mut self) {
+        self.current = self.next_enabled_screen(self.current);
+    }
+
+    fn next_enabled_screen(&mut self, mut index: usize) -> usize {
         loop {
-            self.current = (self.current + 1) % self.screens.len();
-            if self.screens[self.current].enabled() {
-                break;
+            index = (index + 1) % self.screens.len();
+            if self.screens[index].enabled() {
+                return index;
             }
         }
     }
@@ -145,3 +250,170 @@ impl ScreenManager {
         result
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::screens::{BasicScreen, Screen, Screenable};
+    use std::{cell::RefCell, collections::HashMap, rc::Rc};
+
+    struct FakeScreen {
+        screen: Screen,
+        has_content: bool,
+        // keys of the stopped screens, shared by all of them
+        stopped: Rc<RefCell<Vec<String>>>,
+    }
+
+    impl Screenable for FakeScreen {
+        fn get_screen(&mut self) -> &mut Screen {
+            &mut self.screen
+        }
+    }
+
+    impl BasicScreen for FakeScreen {
+        fn update(&mut self) {}
+
+        fn has_content(&mut self) -> bool {
+            self.has_content
+        }
+
+        fn enabled(&mut self) -> bool {
+            true
+        }
+
+        fn stop(&mut self) {
+            self.stopped.borrow_mut().push(self.screen.key.clone());
+        }
+    }
+
+    // screens by key and whether they have content
+    fn manager(screens: &[(&str, bool)]) -> (ScreenManager, Rc<RefCell<Vec<String>>>) {
+        let stopped = Rc::new(RefCell::new(Vec::new()));
+        let screens = screens
+            .iter()
+            .map(|(key, has_content)| {
+                Box::new(FakeScreen {
+                    screen: Screen {
+                        key: key.to_string(),
+                        ..Default::default()
+                    },
+                    has_content: *has_content,
+                    stopped: stopped.clone(),
+                }) as Box<dyn BasicScreen>
+            })
+            .collect();
+        (ScreenManager::new(screens), stopped)
+    }
+
+    fn carousel() -> CarouselConfig {
+        CarouselConfig {
+            enabled: true,
+            dwell_seconds: 10,
+            screen_dwell_seconds: HashMap::from([(String::from("b"), 30)]),
+            resume_after_seconds: 60,
+        }
+    }
+
+    fn current_key(manager: &mut ScreenManager) -> String {
+        manager.current_screen().key()
+    }
+
+    // as if the dwell time of the current screen was over
+    fn dwell_time_over(manager: &mut ScreenManager) {
+        manager.next_switch = Some(Instant::now() - Duration::from_millis(1));
+    }
+
+    fn is_about(left: Option<Duration>, seconds: u64) -> bool {
+        matches!(left, Some(left) if left <= Duration::from_secs(seconds)
+            && left > Duration::from_secs(seconds - 1))
+    }
+
+    #[test]
+    fn carousel_switches_after_the_dwell_time() {
+        let (mut manager, _) = manager(&[("a", true), ("b", true), ("c", true)]);
+        let carousel = carousel();
+
+        manager.rotate(&carousel);
+        assert!(is_about(manager.next_switch_in(), 10));
+        manager.rotate(&carousel);
+        assert_eq!(current_key(&mut manager), "a");
+        assert!(manager.take_transition().is_none());
+
+        dwell_time_over(&mut manager);
+        manager.rotate(&carousel);
+        assert_eq!(current_key(&mut manager), "b");
+        assert!(manager.take_transition().is_some());
+        // the dwell time of the screen, which is shown now
+        assert!(is_about(manager.next_switch_in(), 30));
+
+        dwell_time_over(&mut manager);
+        manager.rotate(&carousel);
+        dwell_time_over(&mut manager);
+        manager.rotate(&carousel);
+        assert_eq!(current_key(&mut manager), "a");
+    }
+
+    #[test]
+    fn carousel_skips_screens_without_content() {
+        let (mut manager, stopped) = manager(&[("a", true), ("b", false), ("c", true)]);
+        let carousel = carousel();
+        manager.rotate(&carousel);
+
+        dwell_time_over(&mut manager);
+        manager.rotate(&carousel);
+        assert_eq!(current_key(&mut manager), "c");
+        assert_eq!(*stopped.borrow(), vec!["a"]);
+    }
+
+    #[test]
+    fn carousel_stays_without_other_content() {
+        let (mut manager, stopped) = manager(&[("a", true), ("b", false), ("c", false)]);
+        let carousel = carousel();
+        manager.rotate(&carousel);
+
+        dwell_time_over(&mut manager);
+        manager.rotate(&carousel);
+        assert_eq!(current_key(&mut manager), "a");
+        // neither stopped nor faded into itself
+        assert!(stopped.borrow().is_empty());
+        assert!(manager.take_transition().is_none());
+        assert!(is_about(manager.next_switch_in(), 10));
+    }
+
+    #[test]
+    fn switching_by_hand_pauses_the_carousel() {
+        let (mut manager, _) = manager(&[("a", true), ("b", true)]);
+        let carousel = carousel();
+        manager.rotate(&carousel);
+
+        manager.pause_carousel(&carousel);
+        assert!(manager.carousel_paused());
+        assert_eq!(manager.next_switch_in(), None);
+        manager.rotate(&carousel);
+        assert_eq!(manager.next_switch_in(), None);
+
+        // it goes on with a full dwell time, once the pause is over
+        manager.paused_until = Some(Instant::now() - Duration::from_millis(1));
+        manager.rotate(&carousel);
+        assert!(!manager.carousel_paused());
+        assert_eq!(current_key(&mut manager), "a");
+        assert!(is_about(manager.next_switch_in(), 10));
+    }
+
+    #[test]
+    fn disabled_carousel_does_not_switch() {
+        let (mut manager, _) = manager(&[("a", true), ("b", true)]);
+        let mut carousel = carousel();
+        manager.rotate(&carousel);
+        carousel.enabled = false;
+
+        dwell_time_over(&mut manager);
+        manager.rotate(&carousel);
+        assert_eq!(current_key(&mut manager), "a");
+        assert_eq!(manager.next_switch_in(), None);
+
+        // nothing to pause either
+        manager.pause_carousel(&carousel);
+        assert!(!manager.carousel_paused());
+    }
+}
@@ -1,13 +1,13 @@
 use crate::{
     config_manager::ConfigManager,
-    screens::{BasicScreen, Screen, Screenable},
+    screens::widgets::{Align, Size, Widget},
+    screens::{BasicScreen, Canvas, Screen, Screenable},
 };
 use chrono::{DateTime, Local};
 use crossbeam_channel::{bounded, Receiver, Sender};
 use error_chain::error_chain;
-use image::{ImageBuffer, Rgb, RgbImage};
-use imageproc::drawing::draw_text_mut;
-use rusttype::{Font, Scale};
+use image::Rgb;
+use rusttype::Font;
 
 use std::{
     rc::Rc,
@@ -65,88 +65,34 @@ impl BasicScreen for BitpandaScreen {
 impl BitpandaScreen {
     fn draw_screen(&mut self, wallet_info: WalletInfo) {
         // draw initial image
-        let mut image = RgbImage::new(256, 64);
-        let mut companion_image = RgbImage::new(320, 170);
-        let scale = Scale { x: 16.0, y: 16.0 };
-        let companion_scale = Scale { x: 64.0, y: 64.0 };
+        let canvas = self.screen.main_canvas;
+        let companion_canvas = self.screen.companion_canvas;
+        let mut image = canvas.image();
+        let mut companion_image = companion_canvas.image();
+        let font = &self.screen.font;
+        let last_update: DateTime<Local> = wallet_info.last_update.into();
+
+        Widget::column(vec![
+            Widget::row(vec![
+                Widget::label(font, "Bitpanda", 16.0),
+                Widget::label(font, &format!("{:.2}€", wallet_info.wallet_value), 16.0)
+                    .align(Align::End),
+            ])
+            .height(Size::Fixed(16)),
+            Widget::label(font, "Last update", 16.0).align(Align::Center),
+            Widget::label(font, &last_update.format("%d.%m.%Y %T").to_string(), 16.0)
+                .align(Align::Center),
+        ])
+        .spacing(4)
+        .render(&canvas, &mut image);
+        Widget::icon(&self.screen.symbols, "\u{f555}", 64.0)
+            .color(Rgb([128u8, 64u8, 0u8]))
+            .render(&companion_canvas, &mut companion_image);
 
-        self.draw_wallet_value(wallet_info.wallet_value, &mut image, scale);
-        self.draw_updated_at(wallet_info.last_update, &mut image, scale);
-        self.draw_companion_screen(&mut companion_image, companion_scale);
         self.screen.main_screen_bytes = image.into_vec();
         self.screen.companion_screen_bytes = companion_image.into_vec();
     }
 
-    pub fn draw_companion_screen(
-        &mut self,
-        image: &mut ImageBuffer<Rgb<u8>, Vec<u8>>,
-        scale: Scale,
-    ) {
-        draw_text_mut(
-            image,
-            Rgb([128u8, 64u8, 0u8]),
-            ((320f32 - scale.x) / 2f32) as i32,
-            ((170f32 - scale.y) / 2f32) as i32,
-            scale,
-            &self.screen.symbols,
-            &String::from("\u{f555}"),
-        );
-    }
-
-    pub fn draw_wallet_value(
-        &mut self,
-        wallet_value: f64,
-        image: &mut ImageBuffer<Rgb<u8>, Vec<u8>>,
-        scale: Scale,
-    ) {
-        draw_text_mut(
-            image,
-            Rgb([255u8, 255u8, 255u8]),
-            0,
-            0,
-            scale,
-            &self.screen.font,
-            "Bitpanda",
-        );
-        draw_text_mut(
-            image,
-            Rgb([255u8, 255u8, 255u8]),
-            112,
-            0,
-            scale,
-            &self.screen.font,
-            &format!("{: >16.2}€", wallet_value),
-        );
-    }
-
-    pub fn draw_updated_at(
-        &mut self,
-        last_update: SystemTime,
-        image: &mut ImageBuffer<Rgb<u8>, Vec<u8>>,
-        scale: Scale,
-    ) {
-        let date_value: DateTime<Local> = last_update.into();
-        draw_text_mut(
-            image,
-            Rgb([255u8, 255u8, 255u8]),
-            84,
-            20,
-            scale,
-            &self.screen.font,
-            "Last update",
-        );
-
-        draw_text_mut(
-            image,
-            Rgb([255u8, 255u8, 255u8]),
-            52,
-            40,
-            scale,
-            &self.screen.font,
-            &date_value.format("%d.%m.%Y %T").to_string(),
-        );
-    }
-
     pub fn new(
         description: String,
         key: String,
@@ -156,6 +102,7 @@ impl BitpandaScreen {
     ) -> BitpandaScreen {
         let (tx, rx): (Sender<WalletInfo>, Receiver<WalletInfo>) = bounded(1);
         let active = Arc::new(AtomicBool::new(false));
+        let (main_canvas, companion_canvas) = Canvas::for_devices(&config_manager);
         let mut this = BitpandaScreen {
             screen: Screen {
                 description,
@@ -199,6 +146,8 @@ impl BitpandaScreen {
                         thread::sleep(Duration::from_millis(1000));
                     }
                 })),
+                main_canvas,
+                companion_canvas,
                 ..Default::default()
             },
             receiver: rx,
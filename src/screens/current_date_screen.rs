@@ -1,11 +1,10 @@
 use crate::config_manager::ConfigManager;
-use crate::screens::{BasicScreen, Screen, Screenable};
+use crate::screens::widgets::{Align, Size, Widget};
+use crate::screens::{BasicScreen, Canvas, Screen, Screenable};
 use chrono::{DateTime, Local};
 use crossbeam_channel::bounded;
 use crossbeam_channel::{Receiver, Sender};
-use image::{ImageBuffer, Rgb, RgbImage};
-use imageproc::drawing::draw_text_mut;
-use rusttype::{Font, Scale};
+use rusttype::Font;
 use std::{
     rc::Rc,
     sync::{atomic::AtomicBool, atomic::Ordering, Arc, RwLock},
@@ -51,29 +50,15 @@ impl BasicScreen for CurrentDateScreen {
 impl CurrentDateScreen {
     fn draw_screen(&mut self, local: DateTime<Local>) {
         // draw initial image
-        let mut image = RgbImage::new(256, 64);
-        let scale = Scale { x: 16.0, y: 16.0 };
-
-        self.draw_clock(&mut image, local, scale);
-        self.screen.main_screen_bytes = image.into_vec();
-    }
-
-    pub fn draw_clock(
-        &mut self,
-        image: &mut ImageBuffer<Rgb<u8>, Vec<u8>>,
-        local: DateTime<Local>,
-        scale: Scale,
-    ) {
+        let canvas = self.screen.main_canvas;
+        let mut image = canvas.image();
         let date_time = local.format("%d.%m.%Y %H:%M:%S").to_string();
-        draw_text_mut(
-            image,
-            Rgb([255u8, 255u8, 255u8]),
-            46,
-            24,
-            scale,
-            &self.screen.font,
-            &date_time,
-        );
+        Widget::label(&self.screen.font, &date_time, 16.0)
+            .height(Size::Fill)
+            .align(Align::Center)
+            .vertical_align(Align::Center)
+            .render(&canvas, &mut image);
+        self.screen.main_screen_bytes = image.into_vec();
     }
 
     pub fn new(
@@ -84,6 +69,7 @@ impl CurrentDateScreen {
     ) -> CurrentDateScreen {
         let (tx, rx): (Sender<ClockInfo>, Receiver<ClockInfo>) = bounded(1);
         let active = Arc::new(AtomicBool::new(false));
+        let (main_canvas, companion_canvas) = Canvas::for_devices(&config_manager);
         let mut this = CurrentDateScreen {
             screen: Screen {
                 description,
@@ -103,6 +89,8 @@ impl CurrentDateScreen {
                     }
                 })),
                 config_manager,
+                main_canvas,
+                companion_canvas,
                 ..Default::default()
             },
             receiver: rx,
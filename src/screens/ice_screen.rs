@@ -1,22 +1,26 @@
 extern crate encoding;
-use crate::screens::{BasicScreen, Screen, Screenable};
+use crate::config_manager::ConfigManager;
+use crate::screens::marquee::Marquee;
+use crate::screens::widgets::{Align, Fit, Size, Widget};
+use crate::screens::{BasicScreen, Canvas, Screen, Screenable};
 use crossbeam_channel::{bounded, Receiver, Sender};
-use image::{ImageBuffer, Rgb, RgbImage};
-use imageproc::drawing::draw_text_mut;
-use rusttype::{Font, Scale};
+use image::Rgb;
+use rusttype::Font;
 use scraper::{Html, Selector};
 use std::{
     rc::Rc,
-    sync::{atomic::AtomicBool, atomic::Ordering, Arc},
+    sync::{atomic::AtomicBool, atomic::Ordering, Arc, RwLock},
     thread,
     time::{Duration, SystemTime},
 };
-use unicode_segmentation::UnicodeSegmentation;
+
+// the companion shows the images of the sorts in a grid
+const IMAGES_PER_ROW: usize = 5;
 
 pub struct IceScreen {
     screen: Screen,
     receiver: Receiver<IceInfo>,
-    sort_x: u32,
+    sorts: Marquee,
     last_ice_info: IceInfo,
 }
 
@@ -49,69 +53,54 @@ impl BasicScreen for IceScreen {
 impl IceScreen {
     fn draw_screen(&mut self, ice_info: IceInfo) {
         // draw initial image
-        let mut image = RgbImage::new(256, 64);
-        self.draw_ice_info(ice_info, &mut image);
+        let canvas = self.screen.main_canvas;
+        let mut image = canvas.image();
+        let marquee = self.screen.config_manager.read().unwrap().config.marquee;
+        self.sorts.set_text(&ice_info.sorts.join(" · "));
+        self.sorts
+            .widget(&self.screen.font, 16.0, &marquee)
+            .height(Size::Fill)
+            .align(Align::Center)
+            .vertical_align(Align::Center)
+            .render(&canvas, &mut image);
         self.screen.main_screen_bytes = image.into_vec();
     }
 
-    fn calc_next_image_x(&mut self, current_x: &mut i64, current_y: &mut i64) {
-        if *current_x + 56 * 2 > 320 {
-            *current_x = 5;
-            *current_y += 56;
-        } else {
-            *current_x += 61;
-        }
-    }
-
     fn draw_companion_screen(&mut self, ice_info: IceInfo) {
         // draw initial image
-        let mut image = image::DynamicImage::new_rgb8(320, 170);
-        imageproc::drawing::draw_filled_rect_mut(
-            &mut image,
-            imageproc::rect::Rect::at(0, 0).of_size(320, 170),
-            image::Rgba([255u8, 255u8, 255u8, 255]),
-        );
-        let mut x = -53;
-        let mut y = 0;
-        for ice_image in ice_info.images {
-            self.calc_next_image_x(&mut x, &mut y);
-
-            image::imageops::overlay(&mut image, &ice_image, x, y);
-        }
-        self.screen.companion_screen_bytes = image.as_bytes().to_vec();
+        let canvas = self.screen.companion_canvas;
+        let mut image = canvas.image();
+        let rows = ice_info
+            .images
+            .chunks(IMAGES_PER_ROW)
+            .map(|images| {
+                let row = images
+                    .iter()
+                    .map(|ice_image| {
+                        Widget::image(ice_image.to_rgb8())
+                            .fit(Fit::Cover)
+                            .width(Size::Fixed(56))
+                    })
+                    .collect();
+                Widget::row(row).spacing(5).height(Size::Fixed(56))
+            })
+            .collect();
+        Widget::column(rows)
+            .padding(5)
+            .background(Rgb([255u8, 255u8, 255u8]))
+            .render(&canvas, &mut image);
+        self.screen.companion_screen_bytes = image.into_vec();
     }
 
-    fn draw_ice_info(&mut self, ice_info: IceInfo, image: &mut ImageBuffer<Rgb<u8>, Vec<u8>>) {
-        let sorts = ice_info.sorts.join(" · ");
-        let title_len = sorts.graphemes(true).count();
-        let mut position_title = 0;
-        let mut start = 0;
-        if title_len * 17 < 480 {
-            position_title = (1 + ((256 - (title_len as u32 * 17) / 2) / 2)) - 2;
-        } else {
-            start = self.sort_x as usize;
-
-            if self.sort_x == title_len as u32 + 2u32 {
-                self.sort_x = 0;
-            } else {
-                self.sort_x += 1;
-            }
-        }
-
-        draw_text_mut(
-            image,
-            Rgb([255u8, 255u8, 255u8]),
-            position_title as i32,
-            24,
-            Scale { x: 16.0, y: 16.0 },
-            &self.screen.font,
-            &rotate(&[&sorts, "   "].join(""), start),
-        );
-    }
-
-    pub fn new(description: String, key: String, font: Rc<Font<'static>>) -> IceScreen {
+    pub fn new(
+        description: String,
+        key: String,
+        font: Rc<Font<'static>>,
+        config_manager: Arc<RwLock<ConfigManager>>,
+    ) -> IceScreen {
         let (tx, rx): (Sender<IceInfo>, Receiver<IceInfo>) = bounded(1);
         let active = Arc::new(AtomicBool::new(false));
+        let (main_canvas, companion_canvas) = Canvas::for_devices(&config_manager);
         let mut this = IceScreen {
             screen: Screen {
                 description,
@@ -171,9 +160,12 @@ impl IceScreen {
                         thread::sleep(Duration::from_millis(1000));
                     }
                 })),
+                config_manager,
+                main_canvas,
+                companion_canvas,
                 ..Default::default()
             },
-            sort_x: 0,
+            sorts: Default::default(),
             receiver: rx,
             last_ice_info: IceInfo {
                 sorts: vec![String::from("Loading")],
@@ -185,8 +177,3 @@ impl IceScreen {
         this
     }
 }
-pub fn rotate(str: &str, count: usize) -> String {
-    let mut str_vec: Vec<char> = str.chars().collect();
-    str_vec.rotate_left(count);
-    str_vec.iter().collect()
-}
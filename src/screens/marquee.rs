@@ -0,0 +1,188 @@
+// Text, which is wider than its place on the display, scrolls through it pixel by pixel.
+// The offset is taken from the clock, so the speed does not depend on how often a
+// screen is drawn. Screens keep a marquee per line, it starts over when the text changes.
+use super::widgets::Widget;
+use super::Canvas;
+use crate::config::{MarqueeConfig, MarqueeMode};
+use rusttype::Font;
+use std::{
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+pub struct Marquee {
+    text: String,
+    started: Instant,
+}
+
+impl Default for Marquee {
+    fn default() -> Marquee {
+        Marquee {
+            text: String::new(),
+            started: Instant::now(),
+        }
+    }
+}
+
+impl Marquee {
+    pub fn set_text(&mut self, text: &str) {
+        if self.text != text {
+            self.text = String::from(text);
+            self.restart();
+        }
+    }
+
+    // back to the start, including the pause
+    pub fn restart(&mut self) {
+        self.started = Instant::now();
+    }
+
+    // a label, which scrolls once its text does not fit
+    pub fn widget(&self, font: &Rc<Font<'static>>, size: f32, config: &MarqueeConfig) -> Widget {
+        Widget::label(font, &self.text, size).scroll(Scroll {
+            elapsed: self.started.elapsed(),
+            config: *config,
+        })
+    }
+}
+
+// the state of a marquee at the time its widget was created
+#[derive(Clone, Copy)]
+pub struct Scroll {
+    elapsed: Duration,
+    config: MarqueeConfig,
+}
+
+impl Scroll {
+    // where the copies of the text start, relative to the left of their box
+    pub fn positions(&self, canvas: &Canvas, text_width: u32, box_width: u32) -> Vec<i32> {
+        let speed = canvas.x(self.config.speed as i32) as f32;
+        if speed <= 0.0 || text_width <= box_width {
+            return vec![0];
+        }
+        let pause = self.config.pause().as_secs_f32();
+        let elapsed = self.elapsed.as_secs_f32();
+        match self.config.mode {
+            MarqueeMode::Loop => {
+                let distance = (text_width as i32 + canvas.x(self.config.gap as i32)) as f32;
+                let time = elapsed % (pause + distance / speed);
+                let offset = ((time - pause).max(0.0) * speed) as i32;
+                vec![-offset, distance as i32 - offset]
+            }
+            MarqueeMode::PingPong => {
+                let distance = (text_width - box_width) as f32;
+                let travel = distance / speed;
+                let time = elapsed % (2.0 * (pause + travel));
+                let offset = if time < pause {
+                    0.0
+                } else if time < pause + travel {
+                    (time - pause) * speed
+                } else if time < 2.0 * pause + travel {
+                    distance
+                } else {
+                    distance - (time - 2.0 * pause - travel) * speed
+                };
+                vec![-(offset as i32)]
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::DisplayRole;
+    use crate::screens::widgets::Size;
+    use image::RgbImage;
+
+    // 16 layout pixels per second after a second of pause
+    fn scroll(mode: MarqueeMode, elapsed_ms: u64) -> Scroll {
+        Scroll {
+            elapsed: Duration::from_millis(elapsed_ms),
+            config: MarqueeConfig {
+                speed: 16,
+                pause_ms: 1000,
+                mode,
+                gap: 10,
+            },
+        }
+    }
+
+    fn canvas() -> Canvas {
+        Canvas::new(DisplayRole::Main, 256, 64)
+    }
+
+    #[test]
+    fn text_that_fits_stays() {
+        for elapsed in [0, 3000, 10000] {
+            for mode in [MarqueeMode::Loop, MarqueeMode::PingPong] {
+                assert_eq!(scroll(mode, elapsed).positions(&canvas(), 50, 50), vec![0]);
+            }
+        }
+        let mut still = scroll(MarqueeMode::Loop, 3000);
+        still.config.speed = 0;
+        assert_eq!(still.positions(&canvas(), 90, 50), vec![0]);
+    }
+
+    #[test]
+    fn loop_wraps_after_the_text_and_the_gap() {
+        let positions = |elapsed| scroll(MarqueeMode::Loop, elapsed).positions(&canvas(), 90, 50);
+        // the copy starts 100 pixels behind, a pass takes 1 + 100 / 16 seconds
+        assert_eq!(positions(500), vec![0, 100]);
+        assert_eq!(positions(1000), vec![0, 100]);
+        assert_eq!(positions(2000), vec![-16, 84]);
+        assert_eq!(positions(7000), vec![-96, 4]);
+        // the copy took the place of the text and pauses
+        assert_eq!(positions(7500), vec![0, 100]);
+        assert_eq!(positions(9250), vec![-16, 84]);
+    }
+
+    #[test]
+    fn ping_pong_turns_at_the_end() {
+        let positions =
+            |elapsed| scroll(MarqueeMode::PingPong, elapsed).positions(&canvas(), 90, 50);
+        // 40 pixels to travel take 2.5 seconds
+        assert_eq!(positions(500), vec![0]);
+        assert_eq!(positions(2000), vec![-16]);
+        assert_eq!(positions(3500), vec![-40]);
+        assert_eq!(positions(4000), vec![-40]);
+        assert_eq!(positions(5500), vec![-24]);
+        assert_eq!(positions(7000), vec![0]);
+        assert_eq!(positions(7500), vec![0]);
+        assert_eq!(positions(9000), vec![-16]);
+    }
+
+    #[test]
+    fn graphemes_wrap_as_one_glyph() {
+        let font = Rc::new(
+            Font::try_from_vec(Vec::from(include_bytes!("../Liberation.ttf") as &[u8])).unwrap(),
+        );
+        // in a box of 40 pixels, after 6 seconds both copies of a text of 12 graphemes
+        let render_at = |text: &str, elapsed: u64| {
+            let label = Widget::label(&font, text, 16.0)
+                .scroll(scroll(MarqueeMode::Loop, elapsed))
+                .width(Size::Fixed(40));
+            let mut image = RgbImage::new(256, 64);
+            Widget::row(vec![label, Widget::space()]).render(&canvas(), &mut image);
+            image
+        };
+        let render = |text: &str| render_at(text, 6000);
+        // a skin tone or a family take the place of the emoji on its own
+        assert!(render("a \u{1f44d}\u{1f3fd} b c d ef") == render("a \u{1f44d} b c d ef"));
+        assert!(
+            render("a \u{1f468}\u{200d}\u{1f469}\u{200d}\u{1f467} b c d ef")
+                == render("a \u{1f468} b c d ef")
+        );
+        // an accent of its own is drawn onto the letter, the text wraps at the same place
+        let columns = |image: RgbImage| {
+            (0..40)
+                .map(|x| (0..64).any(|y| image.get_pixel(x, y).0 != [0, 0, 0]))
+                .collect::<Vec<bool>>()
+        };
+        assert!(render_at("cafe\u{301} au lait", 2000) != render_at("cafe au lait", 2000));
+        assert_eq!(
+            columns(render("cafe\u{301} au lait")),
+            columns(render("cafe au lait"))
+        );
+    }
+}
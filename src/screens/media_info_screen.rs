@@ -1,18 +1,16 @@
 extern crate winapi;
 use crate::{
     config_manager::ConfigManager,
+    helpers::convert_image::Dithering,
     helpers::current_cover::{extract_cover_image, extract_current_cover_path},
-    helpers::text_manipulation::rotate,
-    screens::{BasicScreen, Screen, Screenable},
+    screens::marquee::Marquee,
+    screens::widgets::{Align, BarStyle, Fit, Size, Widget},
+    screens::{BasicScreen, Canvas, Screen, Screenable},
 };
 use crossbeam_channel::{bounded, Receiver, Sender};
-use image::{EncodableLayout, ImageBuffer, Rgb, RgbImage};
-use imageproc::drawing::{
-    draw_filled_rect_mut, draw_hollow_rect_mut, draw_line_segment_mut, draw_text_mut,
-};
-use imageproc::rect::Rect;
+use image::{EncodableLayout, Rgb, RgbImage};
 use regex;
-use rusttype::{Font, Scale};
+use rusttype::Font;
 use std::path::Path;
 use std::ptr::null_mut;
 use std::{
@@ -21,7 +19,6 @@ use std::{
     thread,
     time::{Duration, Instant},
 };
-use unicode_segmentation::UnicodeSegmentation;
 use winapi::{
     shared::minwindef::LPARAM,
     um::{handleapi::CloseHandle, winnt::HANDLE},
@@ -31,8 +28,8 @@ pub struct MediaInfoScreen {
     screen: Screen,
     receiver: Receiver<MusicPlayerInfo>,
     symbols: Rc<Font<'static>>,
-    title_x: u32,
-    artist_x: u32,
+    title: Marquee,
+    artist: Marquee,
     music_player_info: MusicPlayerInfo,
 }
 
@@ -62,6 +59,19 @@ impl Screenable for MediaInfoScreen {
 }
 
 impl BasicScreen for MediaInfoScreen {
+    // album covers band badly without dithering
+    fn dithering(&mut self) -> Dithering {
+        Dithering::FloydSteinberg
+    }
+
+    // the player info is only updated while the screen is shown, so look for the window
+    fn has_content(&mut self) -> bool {
+        matches!(
+            winsafe::HWND::FindWindow(Some(winsafe::AtomStr::from_str("Winamp v1.x")), None),
+            Ok(Some(_))
+        )
+    }
+
     fn update(&mut self) {
         let music_player_info = self.receiver.try_recv();
         match music_player_info {
@@ -70,287 +80,118 @@ impl BasicScreen for MediaInfoScreen {
                 self.draw_companion_screen(&music_player_info);
                 self.music_player_info = music_player_info;
             }
-            Err(_) => {}
+            // keeps long titles scrolling between the updates of the player info
+            Err(_) => {
+                let music_player_info = self.music_player_info.clone();
+                self.draw_screen(&music_player_info);
+            }
         }
     }
 }
 
 impl MediaInfoScreen {
-    fn draw_intro(&mut self, image: &mut ImageBuffer<Rgb<u8>, Vec<u8>>, scale: Scale) {
-        draw_text_mut(
-            image,
-            Rgb([255u8, 255u8, 255u8]),
-            77,
-            4,
-            scale,
-            &self.screen.font,
-            "Media Screen",
-        );
-        draw_text_mut(
-            image,
-            Rgb([255u8, 255u8, 255u8]),
-            65,
-            32,
-            scale,
-            &self.screen.font,
-            "Winamp inactive",
-        );
+    fn intro(&self) -> Widget {
+        let font = &self.screen.font;
+        Widget::column(vec![
+            Widget::label(font, "Media Screen", 16.0).align(Align::Center),
+            Widget::label(font, "Winamp inactive", 16.0).align(Align::Center),
+        ])
+        .padding(4)
+        .spacing(12)
     }
-    fn draw_artist(
-        &mut self,
-        artist: &String,
-        image: &mut ImageBuffer<Rgb<u8>, Vec<u8>>,
-        scale: Scale,
-    ) {
-        let mut position_artist = 0;
-        let artist_len = artist.graphemes(true).count();
-        let mut start = 0usize;
-        if artist_len * 17 < 480 {
-            position_artist = (1 + ((256 - (artist_len as u32 * 17) / 2) / 2)) - 2;
-        } else {
-            start = self.artist_x as usize;
-            if self.artist_x == artist_len as u32 + 2u32 {
-                self.artist_x = 0;
-            } else {
-                self.artist_x += 1;
-            }
-        }
 
-        draw_text_mut(
-            image,
-            Rgb([255u8, 255u8, 255u8]),
-            position_artist as i32,
-            0,
-            scale,
-            &self.screen.font,
-            &rotate(
-                &[artist, "   "].join(""),
-                crate::helpers::text_manipulation::Direction::Left,
-                start,
-            ),
-        );
+    fn play_button(&self, playback_status: isize) -> Widget {
+        // 1 == playing, 3 == paused, anything else == stopped
+        let button = match playback_status {
+            1 => "\u{f04B}",
+            3 => "\u{f04C}",
+            _ => "\u{f04D}",
+        };
+        Widget::icon(&self.symbols, button, 10.0).width(Size::Fixed(16))
     }
 
-    fn draw_title(
-        &mut self,
-        title: &String,
-        image: &mut ImageBuffer<Rgb<u8>, Vec<u8>>,
-        scale: Scale,
-    ) {
-        let title_len = title.graphemes(true).count();
-        let mut position_title = 0;
-        let mut start = 0;
-        if title_len * 17 < 480 {
-            position_title = (1 + ((256 - (title_len as u32 * 17) / 2) / 2)) - 2;
+    fn mute_speaker(&self, mute: i32) -> Widget {
+        let speaker = if mute == 1 {
+            Widget::icon(&self.symbols, "\u{f6a9}", 10.0)
         } else {
-            start = self.title_x as usize;
-
-            if self.title_x == title_len as u32 + 2u32 {
-                self.title_x = 0;
-            } else {
-                self.title_x += 1;
-            }
-        }
-
-        draw_text_mut(
-            image,
-            Rgb([255u8, 255u8, 255u8]),
-            position_title as i32,
-            16,
-            scale,
-            &self.screen.font,
-            &rotate(
-                &[title, "   "].join(""),
-                crate::helpers::text_manipulation::Direction::Left,
-                start,
-            ),
-        );
-    }
-
-    fn draw_play_button(
-        &mut self,
-        playback_status: isize,
-        image: &mut ImageBuffer<Rgb<u8>, Vec<u8>>,
-    ) {
-        let play_button = &String::from("\u{f04B}");
-        let pause_button = &String::from("\u{f04C}");
-        let stop_button = &String::from("\u{f04D}");
-
-        let mut button = stop_button;
-        if playback_status == 1 {
-            button = play_button;
-        }
-
-        if playback_status == 3 {
-            button = pause_button;
-        }
-        draw_text_mut(
-            image,
-            Rgb([255u8, 255u8, 255u8]),
-            4,
-            37,
-            Scale { x: 10.0, y: 10.0 },
-            self.symbols.as_ref(),
-            button,
-        );
-    }
-
-    fn draw_elapsed(&mut self, length: isize, image: &mut ImageBuffer<Rgb<u8>, Vec<u8>>) {
-        let length = length / 1000;
-        let seconds = length % 60;
-        let minutes = (length / 60) % 60;
-        let hours = (length / 60) / 60;
-        let elapsed = format!("{:0>2}:{:0>2}:{:0>2}", hours, minutes, seconds);
-        let elapsed = format!("{: <12}", elapsed);
-        draw_text_mut(
-            image,
-            Rgb([255u8, 255u8, 255u8]),
-            16,
-            36,
-            Scale { x: 14.0, y: 14.0 },
-            &self.screen.font,
-            &elapsed,
-        );
-    }
-
-    fn draw_total(&mut self, length: isize, image: &mut ImageBuffer<Rgb<u8>, Vec<u8>>) {
-        let seconds = length % 60;
-        let minutes = (length / 60) % 60;
-        let hours = (length / 60) / 60;
-        let total = format!("{:0>2}:{:0>2}:{:0>2}", hours, minutes, seconds);
-        let total = format!("{: >12}", total);
-        draw_text_mut(
-            image,
-            Rgb([255u8, 255u8, 255u8]),
-            166,
-            36,
-            Scale { x: 14.0, y: 14.0 },
-            &self.screen.font,
-            &total,
-        );
+            Widget::space()
+        };
+        speaker.width(Size::Fixed(16))
     }
 
-    fn draw_elapsed_bar(
-        &mut self,
-        current_track_position: isize,
-        track_length: isize,
-        image: &mut ImageBuffer<Rgb<u8>, Vec<u8>>,
-    ) {
-        let indicator_position_x_min = 16.0;
-        let indicator_position_x_max = 232.0;
-
-        let progress = (current_track_position as f64 / 1000.0) / (track_length as f64);
-        let position = indicator_position_x_min + (progress * indicator_position_x_max);
-        draw_hollow_rect_mut(
-            image,
-            Rect::at(16, 50).of_size(238, 7),
-            Rgb([255u8, 255u8, 255u8]),
-        );
-
-        draw_filled_rect_mut(
-            image,
-            Rect::at(position as i32, 50).of_size(6, 7),
-            Rgb([255u8, 255u8, 255u8]),
-        );
-
-        let start = 16;
-        let end = position as i32;
-        let segment_length = 6;
-        let line_length = (end - start) + segment_length;
-        let segments = line_length / segment_length;
-
-        for n in 0..segments {
-            let formula = start as f32 + (n as f32 * segment_length as f32);
-            draw_line_segment_mut(
-                image,
-                (formula, 53.0),
-                (formula + (segment_length / 2) as f32, 53.0),
-                Rgb([255u8, 255u8, 255u8]),
-            );
-        }
+    // track position and length in the first line, the position as a bar below
+    fn elapsed(&self, music_player_info: &MusicPlayerInfo) -> Vec<Widget> {
+        let time = |length: isize| {
+            let seconds = length % 60;
+            let minutes = (length / 60) % 60;
+            let hours = (length / 60) / 60;
+            format!("{:0>2}:{:0>2}:{:0>2}", hours, minutes, seconds)
+        };
+        let font = &self.screen.font;
+        let progress = (music_player_info.current_track_position as f32 / 1000.0)
+            / music_player_info.track_length as f32;
+        vec![
+            Widget::row(vec![
+                self.play_button(music_player_info.playback_status),
+                Widget::label(
+                    font,
+                    &time(music_player_info.current_track_position / 1000),
+                    14.0,
+                ),
+                self.mute_speaker(music_player_info.mute),
+                Widget::label(font, &time(music_player_info.track_length), 14.0).align(Align::End),
+            ])
+            .height(Size::Fixed(14)),
+            Widget::row(vec![
+                Widget::space().width(Size::Fixed(16)),
+                Widget::progress_bar(if progress.is_finite() { progress } else { 0.0 })
+                    .bar_style(BarStyle::Marker),
+                Widget::space().width(Size::Fixed(2)),
+            ])
+            .height(Size::Fixed(7)),
+        ]
     }
 
-    fn draw_mute_speaker(&mut self, mute: i32, image: &mut ImageBuffer<Rgb<u8>, Vec<u8>>) {
-        let mute_speaker = &String::from("\u{f6a9}");
-        if mute == 1 {
-            draw_text_mut(
-                image,
-                Rgb([255u8, 255u8, 255u8]),
-                118,
-                38,
-                Scale { x: 10.0, y: 10.0 },
-                self.symbols.as_ref(),
-                mute_speaker,
-            );
-        }
+    // the speakers in the first line, the volume as a bar below
+    fn volume(&self, music_player_info: &MusicPlayerInfo) -> Vec<Widget> {
+        vec![
+            Widget::row(vec![
+                self.play_button(music_player_info.playback_status),
+                Widget::icon(&self.symbols, "\u{f027}", 10.0).width(Size::Fixed(16)),
+                Widget::space(),
+                self.mute_speaker(music_player_info.mute),
+                Widget::space(),
+                Widget::icon(&self.symbols, "\u{f028}", 10.0).width(Size::Fixed(16)),
+            ])
+            .height(Size::Fixed(14)),
+            Widget::row(vec![
+                Widget::space().width(Size::Fixed(16)),
+                Widget::progress_bar(music_player_info.system_volume),
+                Widget::space().width(Size::Fixed(2)),
+            ])
+            .height(Size::Fixed(6)),
+        ]
     }
 
     fn draw_cover(&mut self, music_player_info: &MusicPlayerInfo) -> Vec<u8> {
+        let canvas = self.screen.companion_canvas;
+        let mut image = canvas.image();
         if !music_player_info.player_active {
-            return vec![0; 320 * 170 * 3];
+            return image.into_vec();
         }
-        let mut dyn_image_base = image::DynamicImage::new_rgb8(320, 170);
         // TODO: replace me with real cover
-        let mut cover = RgbImage::new(170, 170);
-
+        // without cover the placeholder is as gray as the background
+        let mut cover = RgbImage::from_pixel(170, 170, Rgb([211u8, 211u8, 211u8]));
         if music_player_info.cover.len() == cover.len() {
             cover.copy_from_slice(music_player_info.cover.as_bytes());
         }
 
-        let dyn_image_cover = image::DynamicImage::ImageRgb8(cover);
-
-        draw_filled_rect_mut(
-            &mut dyn_image_base,
-            Rect::at(0, 0).of_size(320, 170),
-            image::Rgba([211u8, 211u8, 211u8, 255]),
-        );
-        image::imageops::overlay(&mut dyn_image_base, &dyn_image_cover, 75, 0);
-
-        return dyn_image_base.as_bytes().to_vec();
-    }
-
-    fn draw_volume_bar(
-        &mut self,
-        system_volume: f32,
-        playback_status: isize,
-        image: &mut ImageBuffer<Rgb<u8>, Vec<u8>>,
-    ) {
-        let progress = (1.0 + (238.0 * system_volume)) as u32;
-
-        draw_hollow_rect_mut(
-            image,
-            Rect::at(16, 50).of_size(238, 6),
-            Rgb([255u8, 255u8, 255u8]),
-        );
-        let small_speaker = &String::from("\u{f027}");
-        let big_speaker = &String::from("\u{f028}");
-
-        draw_text_mut(
-            image,
-            Rgb([255u8, 255u8, 255u8]),
-            16,
-            38,
-            Scale { x: 10.0, y: 10.0 },
-            self.symbols.as_ref(),
-            small_speaker,
-        );
-
-        draw_text_mut(
-            image,
-            Rgb([255u8, 255u8, 255u8]),
-            240,
-            37,
-            Scale { x: 10.0, y: 10.0 },
-            self.symbols.as_ref(),
-            big_speaker,
-        );
-        draw_filled_rect_mut(
-            image,
-            Rect::at(16, 50).of_size(progress, 6),
-            Rgb([255u8, 255u8, 255u8]),
-        );
-
-        self.draw_play_button(playback_status, image);
+        // the cover is as high as the display, centered
+        Widget::image(cover)
+            .fit(Fit::Contain)
+            .background(Rgb([211u8, 211u8, 211u8]))
+            .render(&canvas, &mut image);
+        image.into_vec()
     }
 
     fn draw_companion_screen(&mut self, music_player_info: &MusicPlayerInfo) {
@@ -363,48 +204,43 @@ impl MediaInfoScreen {
     }
 
     fn draw_screen(&mut self, music_player_info: &MusicPlayerInfo) {
-        let mut image = RgbImage::new(256, 64);
-        let scale = Scale { x: 16.0, y: 16.0 };
+        let canvas = self.screen.main_canvas;
+        let mut image = canvas.image();
+        let marquee = self.screen.config_manager.read().unwrap().config.marquee;
         let seconds = Duration::from_secs(3);
         if self.screen.mode_timeout.unwrap_or(Instant::now()).elapsed() >= seconds {
             self.screen.mode = 0;
         }
 
-        if (music_player_info.artist != self.music_player_info.artist)
-            || (music_player_info.title != self.music_player_info.title)
-        {
-            self.title_x = 0;
-        }
-
+        // the same title of another artist starts over, too
         if music_player_info.artist != self.music_player_info.artist {
-            self.artist_x = 0;
+            self.title.restart();
         }
-
-        if music_player_info.player_active {
-            self.draw_artist(&music_player_info.artist, &mut image, scale);
-            self.draw_title(&music_player_info.title, &mut image, scale);
-            self.draw_mute_speaker(music_player_info.mute, &mut image);
-
+        self.artist.set_text(&music_player_info.artist);
+        self.title.set_text(&music_player_info.title);
+
+        let screen = if music_player_info.player_active {
+            let mut lines = vec![
+                self.artist
+                    .widget(&self.screen.font, 16.0, &marquee)
+                    .align(Align::Center)
+                    .height(Size::Fixed(16)),
+                self.title
+                    .widget(&self.screen.font, 16.0, &marquee)
+                    .align(Align::Center)
+                    .height(Size::Fixed(16)),
+                Widget::space().height(Size::Fixed(4)),
+            ];
             if self.screen.mode == 0 {
-                self.draw_play_button(music_player_info.playback_status, &mut image);
-                self.draw_elapsed(music_player_info.current_track_position, &mut image);
-                self.draw_total(music_player_info.track_length, &mut image);
-                self.draw_elapsed_bar(
-                    music_player_info.current_track_position,
-                    music_player_info.track_length,
-                    &mut image,
-                );
+                lines.extend(self.elapsed(music_player_info));
             } else {
-                // DRAW VOLUME BAR
-                self.draw_volume_bar(
-                    music_player_info.system_volume,
-                    music_player_info.playback_status,
-                    &mut image,
-                );
+                lines.extend(self.volume(music_player_info));
             }
+            Widget::column(lines)
         } else {
-            self.draw_intro(&mut image, scale);
-        }
+            self.intro()
+        };
+        screen.render(&canvas, &mut image);
         self.screen.main_screen_bytes = image.into_vec();
     }
 
@@ -417,6 +253,7 @@ impl MediaInfoScreen {
     ) -> MediaInfoScreen {
         let (tx, rx): (Sender<MusicPlayerInfo>, Receiver<MusicPlayerInfo>) = bounded(1);
         let active = Arc::new(AtomicBool::new(false));
+        let (main_canvas, companion_canvas) = Canvas::for_devices(&config_manager);
 
         let mut this = MediaInfoScreen {
             screen: Screen {
@@ -537,7 +374,8 @@ impl MediaInfoScreen {
                                                     None => {}
                                                 }
                                             } else {
-                                                music_player_info.cover = vec![211; 320 * 170 * 3];
+                                                // drawn as an empty cover
+                                                music_player_info.cover = Vec::new();
                                             }
                                         } else {
                                             music_player_info.cover =
@@ -639,12 +477,14 @@ impl MediaInfoScreen {
                         thread::sleep(Duration::from_millis(200));
                     }
                 })),
+                main_canvas,
+                companion_canvas,
                 ..Default::default()
             },
             music_player_info: Default::default(),
             symbols: Rc::clone(&symbols),
-            title_x: 0,
-            artist_x: 0,
+            title: Default::default(),
+            artist: Default::default(),
             receiver: rx,
         };
         this.draw_screen(&Default::default());
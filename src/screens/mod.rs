@@ -1,5 +1,8 @@
+use crate::config::{Config, DisplayRole};
 use crate::config_manager::ConfigManager;
-use rusttype::Font;
+use crate::helpers::convert_image::Dithering;
+use image::{imageops, RgbImage};
+use rusttype::{Font, Scale};
 use std::rc::Rc;
 use std::sync::{atomic::AtomicBool, atomic::Ordering, Arc, RwLock};
 use std::thread::JoinHandle;
@@ -7,9 +10,107 @@ use std::time::Instant;
 pub mod bitpanda_screen;
 pub mod current_date_screen;
 pub mod ice_screen;
+pub mod marquee;
 pub mod media_info_screen;
+pub mod plugin_screen;
 pub mod system_info_screen;
 pub mod weather_screen;
+pub mod widgets;
+
+// the sizes the layouts of the screens were designed for
+const MAIN_LAYOUT: (u32, u32) = (256, 64);
+const COMPANION_LAYOUT: (u32, u32) = (320, 170);
+
+// Size of the display a screen renders for. Screens place everything in the pixels of
+// their layout size, the canvas scales them to the actual panel.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Canvas {
+    pub width: u32,
+    pub height: u32,
+    layout: (u32, u32),
+}
+
+impl Canvas {
+    pub fn new(role: DisplayRole, width: u32, height: u32) -> Canvas {
+        let layout = match role {
+            DisplayRole::Main => MAIN_LAYOUT,
+            DisplayRole::Companion => COMPANION_LAYOUT,
+        };
+        Canvas {
+            width: width.max(1),
+            height: height.max(1),
+            layout,
+        }
+    }
+
+    // the frame size of the first device of the role, the layout size without one
+    pub fn for_role(config: &Config, role: DisplayRole) -> Canvas {
+        let layout = Canvas::new(role, 0, 0).layout;
+        let (width, height) = config.canvas_size(role).unwrap_or(layout);
+        Canvas::new(role, width, height)
+    }
+
+    // main and companion canvas
+    pub fn for_devices(config_manager: &RwLock<ConfigManager>) -> (Canvas, Canvas) {
+        let config = &config_manager.read().unwrap().config;
+        (
+            Canvas::for_role(config, DisplayRole::Main),
+            Canvas::for_role(config, DisplayRole::Companion),
+        )
+    }
+
+    pub fn image(&self) -> RgbImage {
+        RgbImage::new(self.width, self.height)
+    }
+
+    // bytes of an rgb frame
+    pub fn buffer_len(&self) -> usize {
+        (self.width * self.height * 3) as usize
+    }
+
+    // horizontal position of a layout pixel
+    pub fn x(&self, x: i32) -> i32 {
+        (x as f32 * self.width as f32 / self.layout.0 as f32).round() as i32
+    }
+
+    // vertical position of a layout pixel
+    pub fn y(&self, y: i32) -> i32 {
+        (y as f32 * self.height as f32 / self.layout.1 as f32).round() as i32
+    }
+
+    pub fn width_of(&self, width: u32) -> u32 {
+        (self.x(width as i32) as u32).max(1)
+    }
+
+    pub fn height_of(&self, height: u32) -> u32 {
+        (self.y(height as i32) as u32).max(1)
+    }
+
+    // text and symbols keep their proportions, they grow with the smaller factor
+    pub fn scale(&self, size: f32) -> Scale {
+        let factor = (self.width as f32 / self.layout.0 as f32)
+            .min(self.height as f32 / self.layout.1 as f32);
+        Scale::uniform(size * factor)
+    }
+
+    // the rendered frame in the size of a device, further devices of a role may have
+    // another panel than the first one. Black, if the screen drew nothing for the canvas.
+    pub fn fit(&self, bytes: &[u8], width: u32, height: u32) -> Vec<u8> {
+        let black = || vec![0; (width * height * 3) as usize];
+        if bytes.len() != self.buffer_len() {
+            return black();
+        }
+        if (width, height) == (self.width, self.height) {
+            return bytes.to_vec();
+        }
+        match RgbImage::from_raw(self.width, self.height, bytes.to_vec()) {
+            Some(frame) => {
+                imageops::resize(&frame, width, height, imageops::FilterType::Triangle).into_raw()
+            }
+            None => black(),
+        }
+    }
+}
 
 pub struct Screen {
     pub description: String,
@@ -23,6 +124,8 @@ pub struct Screen {
     pub mode: u32,
     pub mode_timeout: Option<Instant>,
     pub config_manager: Arc<RwLock<ConfigManager>>,
+    pub main_canvas: Canvas,
+    pub companion_canvas: Canvas,
 }
 
 impl Default for Screen {
@@ -31,7 +134,7 @@ impl Default for Screen {
             description: String::from(""),
             key: String::from(""),
             main_screen_bytes: Vec::new(), // Oled display byte image
-            companion_screen_bytes: vec![0; 320 * 170 * 3], // companion display byte image
+            companion_screen_bytes: Vec::new(), // companion display byte image
             font: Rc::new(
                 Font::try_from_vec(Vec::from(include_bytes!("../Liberation.ttf") as &[u8]))
                     .unwrap(),
@@ -44,6 +147,12 @@ impl Default for Screen {
             mode: 0,
             mode_timeout: Some(Instant::now()),
             config_manager: Arc::new(RwLock::new(ConfigManager::new(None))),
+            main_canvas: Canvas::new(DisplayRole::Main, MAIN_LAYOUT.0, MAIN_LAYOUT.1),
+            companion_canvas: Canvas::new(
+                DisplayRole::Companion,
+                COMPANION_LAYOUT.0,
+                COMPANION_LAYOUT.1,
+            ),
         }
     }
 }
@@ -75,6 +184,16 @@ pub trait BasicScreen: Screenable {
         &screen.companion_screen_bytes
     }
 
+    // dithering for gray scale devices, which don't configure one themselves
+    fn dithering(&mut self) -> Dithering {
+        Dithering::None
+    }
+
+    // screens without anything useful to show are skipped by the carousel
+    fn has_content(&mut self) -> bool {
+        true
+    }
+
     fn start(&mut self) {
         let screen = self.get_screen();
         screen.active.store(true, Ordering::Release);
@@ -90,6 +209,14 @@ pub trait BasicScreen: Screenable {
         self.get_screen().active.store(false, Ordering::Release)
     }
 
+    // media keys, with the virtual key code, while the screen is shown
+    fn key_pressed(&mut self, _key: u32) {}
+
+    // recent errors of the screen, shown below its preview
+    fn log(&mut self) -> Vec<String> {
+        Vec::new()
+    }
+
     fn set_mode(&mut self, mode: u32) {
         let screen = self.get_screen();
         screen.mode_timeout = Some(Instant::now());
@@ -0,0 +1,857 @@
+// A screen drawn by an external program, so screens can be written in any language.
+// The program is started with the configured command and speaks line delimited json
+// over stdin / stdout: the host sends the canvas sizes, activation, key presses and
+// render requests, the plugin answers with widgets or raw rgb frames for the main and
+// the companion display and a `done`. The last lines of its stderr are shown below the
+// preview of the screen. A plugin, which exits or does not finish a render request in
+// time, is started again.
+use crate::{
+    config::PluginConfig,
+    config_manager::ConfigManager,
+    screens::widgets::{Align, BarStyle, Fit, Size, Widget},
+    screens::{BasicScreen, Canvas, Screen, Screenable},
+    CLOSE_REQUESTED,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use crossbeam_channel::{
+    bounded, unbounded, Receiver, RecvTimeoutError, Sender, TryRecvError, TrySendError,
+};
+use image::{Rgb, RgbImage};
+use rusttype::Font;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    io::{BufRead, BufReader, Write},
+    os::windows::process::CommandExt,
+    process::{Child, ChildStdin, Command, Stdio},
+    rc::Rc,
+    sync::{atomic::AtomicBool, atomic::Ordering, Arc, Mutex, RwLock},
+    thread,
+    time::{Duration, Instant},
+};
+
+// how often the worker looks for activation changes and key presses
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+// doubled with every start, which fails right away
+const RESTART_DELAY: Duration = Duration::from_secs(1);
+const MAX_RESTART_DELAY: Duration = Duration::from_secs(60);
+// lines of stderr and errors kept per plugin
+const LOG_LINES: usize = 10;
+// draws and frames waiting for the screen, older ones are dropped
+const QUEUED_MESSAGES: usize = 4;
+
+pub struct PluginScreen {
+    screen: Screen,
+    receiver: Receiver<PluginMessage>,
+    keys: Sender<u32>,
+    log: Log,
+}
+
+// the last lines of a plugin, shared with its threads
+#[derive(Clone, Default)]
+struct Log(Arc<Mutex<VecDeque<String>>>);
+
+impl Log {
+    fn push(&self, line: String) {
+        let mut lines = self.0.lock().unwrap();
+        if lines.len() == LOG_LINES {
+            lines.pop_front();
+        }
+        lines.push_back(line);
+    }
+
+    fn lines(&self) -> Vec<String> {
+        self.0.lock().unwrap().iter().cloned().collect()
+    }
+}
+
+// the worker's end of the channel to the screen
+struct Messages {
+    sender: Sender<PluginMessage>,
+    receiver: Receiver<PluginMessage>,
+}
+
+impl Messages {
+    // drops the oldest message, if the screen did not take the others yet
+    fn push(&self, message: PluginMessage) {
+        if let Err(TrySendError::Full(message)) = self.sender.try_send(message) {
+            self.receiver.try_recv().ok();
+            self.sender.try_send(message).unwrap_or_default();
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct CanvasSize {
+    width: u32,
+    height: u32,
+}
+
+// to the plugin
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum HostMessage {
+    // sent first after every start
+    Hello {
+        main: CanvasSize,
+        companion: CanvasSize,
+    },
+    Activate,
+    Deactivate,
+    // virtual key code of a media key
+    Key {
+        code: u32,
+    },
+    // answered with a `draw` or `frame` for each display, which should change, and a
+    // `done`, even if nothing changed
+    Render,
+}
+
+// from the plugin
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum PluginMessage {
+    Draw { display: Display, root: WidgetSpec },
+    // base64 encoded rgb in the canvas size of the display
+    Frame { display: Display, data: String },
+    // the answer to a render request is complete
+    Done,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+enum Display {
+    Main,
+    Companion,
+}
+
+// a widget in json, e.g. {"widget": "label", "text": "Hello", "align": "center"}
+#[derive(Deserialize)]
+struct WidgetSpec {
+    #[serde(flatten)]
+    kind: KindSpec,
+    #[serde(default)]
+    width: Option<Size>,
+    #[serde(default)]
+    height: Option<Size>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "widget", rename_all = "snake_case")]
+enum KindSpec {
+    Label {
+        text: String,
+        #[serde(default = "default_text_size")]
+        size: f32,
+        #[serde(default)]
+        align: Option<Align>,
+        #[serde(default)]
+        vertical_align: Option<Align>,
+        #[serde(default)]
+        ellipsis: bool,
+        #[serde(default)]
+        color: Option<[u8; 3]>,
+    },
+    // a glyph of the symbol font
+    Icon {
+        glyph: String,
+        #[serde(default = "default_text_size")]
+        size: f32,
+        #[serde(default)]
+        color: Option<[u8; 3]>,
+    },
+    ProgressBar {
+        progress: f32,
+        #[serde(default)]
+        style: Option<BarStyle>,
+        #[serde(default)]
+        color: Option<[u8; 3]>,
+    },
+    // base64 encoded rgb
+    Image {
+        image_width: u32,
+        image_height: u32,
+        data: String,
+        #[serde(default)]
+        fit: Option<Fit>,
+        #[serde(default)]
+        background: Option<[u8; 3]>,
+    },
+    Row {
+        children: Vec<WidgetSpec>,
+        #[serde(default)]
+        padding: u32,
+        #[serde(default)]
+        spacing: u32,
+        #[serde(default)]
+        background: Option<[u8; 3]>,
+    },
+    Column {
+        children: Vec<WidgetSpec>,
+        #[serde(default)]
+        padding: u32,
+        #[serde(default)]
+        spacing: u32,
+        #[serde(default)]
+        background: Option<[u8; 3]>,
+    },
+    Stack {
+        children: Vec<WidgetSpec>,
+        #[serde(default)]
+        padding: u32,
+        #[serde(default)]
+        background: Option<[u8; 3]>,
+    },
+    Space,
+}
+
+fn default_text_size() -> f32 {
+    16.0
+}
+
+impl WidgetSpec {
+    fn widget(
+        self,
+        font: &Rc<Font<'static>>,
+        symbols: &Rc<Font<'static>>,
+    ) -> Result<Widget, String> {
+        let children = |children: Vec<WidgetSpec>| {
+            children
+                .into_iter()
+                .map(|child| child.widget(font, symbols))
+                .collect::<Result<Vec<Widget>, String>>()
+        };
+        let mut widget = match self.kind {
+            KindSpec::Label {
+                text,
+                size,
+                align,
+                vertical_align,
+                ellipsis,
+                color,
+            } => {
+                let mut label = Widget::label(font, &text, size)
+                    .align(align.unwrap_or(Align::Start))
+                    .vertical_align(vertical_align.unwrap_or(Align::Start));
+                if ellipsis {
+                    label = label.ellipsis();
+                }
+                with_color(label, color)
+            }
+            KindSpec::Icon { glyph, size, color } => {
+                with_color(Widget::icon(symbols, &glyph, size), color)
+            }
+            KindSpec::ProgressBar {
+                progress,
+                style,
+                color,
+            } => with_color(
+                Widget::progress_bar(progress).bar_style(style.unwrap_or(BarStyle::Fill)),
+                color,
+            ),
+            KindSpec::Image {
+                image_width,
+                image_height,
+                data,
+                fit,
+                background,
+            } => {
+                let bytes = STANDARD.decode(data).map_err(|e| e.to_string())?;
+                let image =
+                    RgbImage::from_raw(image_width, image_height, bytes).ok_or_else(|| {
+                        format!(
+                            "image data does not match {} x {}",
+                            image_width, image_height
+                        )
+                    })?;
+                with_background(
+                    Widget::image(image).fit(fit.unwrap_or(Fit::Contain)),
+                    background,
+                )
+            }
+            KindSpec::Row {
+                children: row,
+                padding,
+                spacing,
+                background,
+            } => with_background(
+                Widget::row(children(row)?)
+                    .padding(padding)
+                    .spacing(spacing),
+                background,
+            ),
+            KindSpec::Column {
+                children: column,
+                padding,
+                spacing,
+                background,
+            } => with_background(
+                Widget::column(children(column)?)
+                    .padding(padding)
+                    .spacing(spacing),
+                background,
+            ),
+            KindSpec::Stack {
+                children: stack,
+                padding,
+                background,
+            } => with_background(Widget::stack(children(stack)?).padding(padding), background),
+            KindSpec::Space => Widget::space(),
+        };
+        if let Some(width) = self.width {
+            widget = widget.width(width);
+        }
+        if let Some(height) = self.height {
+            widget = widget.height(height);
+        }
+        Ok(widget)
+    }
+}
+
+fn with_color(widget: Widget, color: Option<[u8; 3]>) -> Widget {
+    match color {
+        Some(color) => widget.color(Rgb(color)),
+        None => widget,
+    }
+}
+
+fn with_background(widget: Widget, background: Option<[u8; 3]>) -> Widget {
+    match background {
+        Some(background) => widget.background(Rgb(background)),
+        None => widget,
+    }
+}
+
+impl Screenable for PluginScreen {
+    fn get_screen(&mut self) -> &mut Screen {
+        &mut self.screen
+    }
+}
+
+impl BasicScreen for PluginScreen {
+    fn update(&mut self) {
+        let messages: Vec<PluginMessage> = self.receiver.try_iter().collect();
+        for message in messages {
+            if let Err(e) = self.apply(message) {
+                self.log.push(e);
+            }
+        }
+    }
+
+    fn key_pressed(&mut self, key: u32) {
+        self.keys.try_send(key).unwrap_or_default();
+    }
+
+    fn log(&mut self) -> Vec<String> {
+        self.log.lines()
+    }
+}
+
+impl PluginScreen {
+    fn apply(&mut self, message: PluginMessage) -> Result<(), String> {
+        let display = match message {
+            PluginMessage::Draw { display, .. } | PluginMessage::Frame { display, .. } => display,
+            PluginMessage::Done => return Ok(()),
+        };
+        let canvas = match display {
+            Display::Main => self.screen.main_canvas,
+            Display::Companion => self.screen.companion_canvas,
+        };
+        let frame = match message {
+            PluginMessage::Draw { root, .. } => {
+                let mut image = canvas.image();
+                root.widget(&self.screen.font, &self.screen.symbols)?
+                    .render(&canvas, &mut image);
+                image.into_vec()
+            }
+            PluginMessage::Frame { data, .. } => {
+                let frame = STANDARD.decode(data).map_err(|e| e.to_string())?;
+                if frame.len() != canvas.buffer_len() {
+                    return Err(format!(
+                        "frame of {} bytes does not match {} x {}",
+                        frame.len(),
+                        canvas.width,
+                        canvas.height
+                    ));
+                }
+                frame
+            }
+            PluginMessage::Done => return Ok(()),
+        };
+        match display {
+            Display::Main => self.screen.main_screen_bytes = frame,
+            Display::Companion => self.screen.companion_screen_bytes = frame,
+        }
+        Ok(())
+    }
+
+    pub fn new(
+        plugin: PluginConfig,
+        font: Rc<Font<'static>>,
+        symbols: Rc<Font<'static>>,
+        config_manager: Arc<RwLock<ConfigManager>>,
+    ) -> PluginScreen {
+        let (tx, rx): (Sender<PluginMessage>, Receiver<PluginMessage>) = bounded(QUEUED_MESSAGES);
+        let messages = Messages {
+            sender: tx,
+            receiver: rx.clone(),
+        };
+        let (keys_tx, keys_rx): (Sender<u32>, Receiver<u32>) = bounded(16);
+        let active = Arc::new(AtomicBool::new(false));
+        let log = Log::default();
+        let plugin_log = log.clone();
+        let (main_canvas, companion_canvas) = Canvas::for_devices(&config_manager);
+        let hello = HostMessage::Hello {
+            main: CanvasSize {
+                width: main_canvas.width,
+                height: main_canvas.height,
+            },
+            companion: CanvasSize {
+                width: companion_canvas.width,
+                height: companion_canvas.height,
+            },
+        };
+        PluginScreen {
+            screen: Screen {
+                description: plugin.name.clone(),
+                key: plugin.key.clone(),
+                font,
+                symbols,
+                active: active.clone(),
+                handle: Some(thread::spawn(move || {
+                    run_plugin(&plugin, &hello, &active, &keys_rx, &messages, &plugin_log)
+                })),
+                config_manager,
+                main_canvas,
+                companion_canvas,
+                ..Default::default()
+            },
+            receiver: rx,
+            keys: keys_tx,
+            log,
+        }
+    }
+}
+
+// keeps the plugin running until the app closes or its screen is dropped
+fn run_plugin(
+    plugin: &PluginConfig,
+    hello: &HostMessage,
+    active: &AtomicBool,
+    keys: &Receiver<u32>,
+    messages: &Messages,
+    log: &Log,
+) {
+    let mut delay = RESTART_DELAY;
+    loop {
+        let started = Instant::now();
+        match PluginProcess::start(plugin, log) {
+            Ok(mut process) => {
+                if let Err(reason) = process.serve(plugin, hello, active, keys, messages, log) {
+                    log.push(format!("stopped: {}", reason));
+                }
+                process.kill();
+            }
+            Err(e) => log.push(format!("could not start: {}", e)),
+        }
+        // the keys disconnect, once the screen is dropped
+        if CLOSE_REQUESTED.load(Ordering::Acquire)
+            || matches!(keys.try_recv(), Err(TryRecvError::Disconnected))
+        {
+            return;
+        }
+
+        // a plugin, which ran for a while, is started again after the shortest delay
+        if started.elapsed() > MAX_RESTART_DELAY {
+            delay = RESTART_DELAY;
+        }
+        thread::sleep(delay);
+        delay = (delay * 2).min(MAX_RESTART_DELAY);
+    }
+}
+
+struct PluginProcess {
+    child: Child,
+    stdin: ChildStdin,
+    lines: Receiver<String>,
+}
+
+impl PluginProcess {
+    fn start(plugin: &PluginConfig, log: &Log) -> Result<PluginProcess, String> {
+        let mut child = Command::new(&plugin.command)
+            .args(&plugin.args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            // console programs would open a window of their own
+            .creation_flags(winapi::um::winbase::CREATE_NO_WINDOW)
+            .spawn()
+            .map_err(|e| e.to_string())?;
+        let (stdin, stdout, stderr) =
+            match (child.stdin.take(), child.stdout.take(), child.stderr.take()) {
+                (Some(stdin), Some(stdout), Some(stderr)) => (stdin, stdout, stderr),
+                _ => {
+                    child.kill().ok();
+                    return Err(String::from("no pipes to the process"));
+                }
+            };
+
+        let log = log.clone();
+        thread::spawn(move || {
+            for line in BufReader::new(stderr).lines().map_while(Result::ok) {
+                log.push(line);
+            }
+        });
+        // the channel disconnects, once the plugin closes its stdout
+        let (tx, rx) = unbounded();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                if tx.send(line).is_err() {
+                    return;
+                }
+            }
+        });
+        Ok(PluginProcess {
+            child,
+            stdin,
+            lines: rx,
+        })
+    }
+
+    fn send(&mut self, message: &HostMessage) -> Result<(), String> {
+        let line = serde_json::to_string(message).map_err(|e| e.to_string())?;
+        writeln!(self.stdin, "{}", line).map_err(|e| e.to_string())?;
+        self.stdin.flush().map_err(|e| e.to_string())
+    }
+
+    // returns, when the plugin exits, hangs or the app closes
+    fn serve(
+        &mut self,
+        plugin: &PluginConfig,
+        hello: &HostMessage,
+        active: &AtomicBool,
+        keys: &Receiver<u32>,
+        messages: &Messages,
+        log: &Log,
+    ) -> Result<(), String> {
+        self.send(hello)?;
+        let mut shown = false;
+        let mut next_render = Instant::now();
+        // when the pending render request was sent
+        let mut requested: Option<Instant> = None;
+        loop {
+            if CLOSE_REQUESTED.load(Ordering::Acquire) {
+                return Ok(());
+            }
+            if active.load(Ordering::Acquire) != shown {
+                shown = !shown;
+                self.send(if shown {
+                    &HostMessage::Activate
+                } else {
+                    &HostMessage::Deactivate
+                })?;
+                next_render = Instant::now();
+                requested = None;
+            }
+            // keys pressed while another screen was shown are not for the plugin
+            for code in keys.try_iter() {
+                if shown {
+                    self.send(&HostMessage::Key { code })?;
+                }
+            }
+            if shown && requested.is_none() && Instant::now() >= next_render {
+                self.send(&HostMessage::Render)?;
+                requested = Some(Instant::now());
+                next_render = Instant::now() + plugin.interval();
+            }
+            if matches!(requested, Some(requested) if requested.elapsed() > plugin.timeout()) {
+                return Err(format!(
+                    "render request not done within {} ms",
+                    plugin.timeout_ms
+                ));
+            }
+
+            match self.lines.recv_timeout(POLL_INTERVAL) {
+                Ok(line) => match serde_json::from_str::<PluginMessage>(&line) {
+                    Ok(PluginMessage::Done) => requested = None,
+                    Ok(message) => messages.push(message),
+                    Err(e) => log.push(format!("invalid message: {}", e)),
+                },
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(match self.child.wait() {
+                        Ok(status) => format!("exited with {}", status),
+                        Err(e) => e.to_string(),
+                    });
+                }
+            }
+        }
+    }
+
+    fn kill(&mut self) {
+        self.child.kill().ok();
+        self.child.wait().ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::DisplayRole;
+    use std::{env, io};
+
+    // a second test filter, which tells the ignored tests below, that the test binary was
+    // started as a plugin
+    const PLUGIN_CHILD: &str = "plugin_child";
+    const DRAW: &str = r#"{"type": "draw", "display": "main", "root": {"widget": "space"}}"#;
+
+    fn screen() -> PluginScreen {
+        let (_, receiver) = bounded(QUEUED_MESSAGES);
+        let (keys, _) = bounded(16);
+        PluginScreen {
+            screen: Screen {
+                main_canvas: Canvas::new(DisplayRole::Main, 256, 64),
+                companion_canvas: Canvas::new(DisplayRole::Companion, 320, 170),
+                ..Default::default()
+            },
+            receiver,
+            keys,
+            log: Log::default(),
+        }
+    }
+
+    fn message(json: &str) -> PluginMessage {
+        serde_json::from_str(json).unwrap()
+    }
+
+    // the test binary runs one of the ignored tests below as the plugin
+    fn plugin(script: &str) -> PluginConfig {
+        PluginConfig {
+            name: String::from("Test"),
+            key: String::from("plugin_test"),
+            command: env::current_exe().unwrap().to_string_lossy().into_owned(),
+            args: [
+                &format!("screens::plugin_screen::tests::{}", script),
+                "--exact",
+                "--ignored",
+                "--nocapture",
+                "--quiet",
+                PLUGIN_CHILD,
+            ]
+            .map(String::from)
+            .to_vec(),
+            active: true,
+            interval_ms: 50,
+            timeout_ms: 500,
+        }
+    }
+
+    fn hello() -> HostMessage {
+        HostMessage::Hello {
+            main: CanvasSize {
+                width: 256,
+                height: 64,
+            },
+            companion: CanvasSize {
+                width: 320,
+                height: 170,
+            },
+        }
+    }
+
+    fn messages() -> (Messages, Receiver<PluginMessage>) {
+        let (sender, receiver) = bounded(QUEUED_MESSAGES);
+        (
+            Messages {
+                sender,
+                receiver: receiver.clone(),
+            },
+            receiver,
+        )
+    }
+
+    #[test]
+    #[ignore]
+    fn plugin_that_answers_twice() {
+        if !env::args().any(|arg| arg == PLUGIN_CHILD) {
+            return;
+        }
+        let mut renders = 0;
+        for line in io::stdin().lines().map_while(Result::ok) {
+            if line.contains(r#""type":"render""#) {
+                renders += 1;
+                if renders <= 2 {
+                    println!("{}", DRAW);
+                    println!(r#"{{"type": "done"}}"#);
+                }
+            }
+        }
+    }
+
+    #[test]
+    #[ignore]
+    fn plugin_that_exits() {
+        if !env::args().any(|arg| arg == PLUGIN_CHILD) {
+            return;
+        }
+        io::stdin().lines().next();
+        println!("{}", DRAW);
+        std::process::exit(3);
+    }
+
+    #[test]
+    fn draws_are_rendered_for_their_display() {
+        let mut screen = screen();
+        let draw = message(
+            r#"{"type": "draw", "display": "companion", "root": {
+                "widget": "row", "padding": 2, "children": [
+                    {"widget": "label", "text": "Hi", "width": {"fixed": 40}},
+                    {"widget": "progress_bar", "progress": 1.0, "color": [255, 0, 0]}
+                ]
+            }}"#,
+        );
+        screen.apply(draw).unwrap();
+
+        let frame = &screen.screen.companion_screen_bytes;
+        assert_eq!(frame.len(), 320 * 170 * 3);
+        assert!(frame.chunks(3).any(|pixel| pixel == [255, 0, 0]));
+        assert!(screen.screen.main_screen_bytes.is_empty());
+    }
+
+    #[test]
+    fn frames_are_taken_in_the_canvas_size() {
+        let mut screen = screen();
+        let data = STANDARD.encode(vec![7u8; 256 * 64 * 3]);
+        let frame = format!(
+            r#"{{"type": "frame", "display": "main", "data": "{}"}}"#,
+            data
+        );
+        screen.apply(message(&frame)).unwrap();
+        assert_eq!(screen.screen.main_screen_bytes, vec![7u8; 256 * 64 * 3]);
+
+        let data = STANDARD.encode(vec![7u8; 320 * 170 * 3]);
+        let frame = format!(
+            r#"{{"type": "frame", "display": "main", "data": "{}"}}"#,
+            data
+        );
+        assert_eq!(
+            screen.apply(message(&frame)),
+            Err(String::from(
+                "frame of 163200 bytes does not match 256 x 64"
+            ))
+        );
+    }
+
+    #[test]
+    fn broken_data_is_rejected() {
+        let mut screen = screen();
+        let frame = r#"{"type": "frame", "display": "main", "data": "not base64!"}"#;
+        assert!(screen.apply(message(frame)).is_err());
+
+        let image = r#"{"type": "draw", "display": "main", "root": {
+            "widget": "image", "image_width": 2, "image_height": 2, "data": "AAAA"
+        }}"#;
+        assert_eq!(
+            screen.apply(message(image)),
+            Err(String::from("image data does not match 2 x 2"))
+        );
+        assert!(screen.screen.main_screen_bytes.is_empty());
+    }
+
+    #[test]
+    fn unknown_widgets_are_invalid_messages() {
+        let draw = r#"{"type": "draw", "display": "main", "root": {"widget": "video"}}"#;
+        assert!(serde_json::from_str::<PluginMessage>(draw).is_err());
+        let draw = r#"{"type": "draw", "display": "side", "root": {"widget": "space"}}"#;
+        assert!(serde_json::from_str::<PluginMessage>(draw).is_err());
+        assert!(matches!(
+            message(r#"{"type": "done"}"#),
+            PluginMessage::Done
+        ));
+    }
+
+    #[test]
+    fn old_messages_are_dropped() {
+        let (messages, receiver) = messages();
+        for display in ["main", "main", "main", "main", "companion"] {
+            let draw = DRAW.replace("main", display);
+            messages.push(message(&draw));
+        }
+        let displays: Vec<bool> = receiver
+            .try_iter()
+            .map(|message| {
+                matches!(
+                    message,
+                    PluginMessage::Draw {
+                        display: Display::Companion,
+                        ..
+                    }
+                )
+            })
+            .collect();
+        assert_eq!(displays, vec![false, false, false, true]);
+    }
+
+    #[test]
+    fn plugin_is_stopped_when_it_does_not_answer() {
+        let plugin = plugin("plugin_that_answers_twice");
+        let (messages, receiver) = messages();
+        let (_keys, keys_receiver) = bounded(16);
+        let log = Log::default();
+        let mut process = PluginProcess::start(&plugin, &log).unwrap();
+
+        let started = Instant::now();
+        let result = process.serve(
+            &plugin,
+            &hello(),
+            &AtomicBool::new(true),
+            &keys_receiver,
+            &messages,
+            &log,
+        );
+        process.kill();
+        assert_eq!(
+            result,
+            Err(String::from("render request not done within 500 ms"))
+        );
+        assert!(started.elapsed() >= plugin.timeout());
+        assert_eq!(receiver.try_iter().count(), 2);
+    }
+
+    #[test]
+    fn plugin_is_started_again_after_it_exits() {
+        let plugin = plugin("plugin_that_exits");
+        let (messages, receiver) = messages();
+        let (keys, keys_receiver) = bounded(16);
+        let log = Log::default();
+        let worker_log = log.clone();
+        let worker = thread::spawn(move || {
+            run_plugin(
+                &plugin,
+                &hello(),
+                &AtomicBool::new(false),
+                &keys_receiver,
+                &messages,
+                &worker_log,
+            )
+        });
+
+        let timeout = Duration::from_secs(10);
+        receiver.recv_timeout(timeout).unwrap();
+        let exited = Instant::now();
+        receiver.recv_timeout(timeout).unwrap();
+        // the first restart waits for the shortest delay
+        assert!(exited.elapsed() >= RESTART_DELAY);
+        assert!(exited.elapsed() < RESTART_DELAY * 2);
+
+        // the worker stops with the screen, after the plugin exited once more at most
+        drop(keys);
+        worker.join().unwrap();
+        let stops = log
+            .lines()
+            .iter()
+            .filter(|line| line.starts_with("stopped: exited with"))
+            .count();
+        assert!((2..=3).contains(&stops));
+    }
+}
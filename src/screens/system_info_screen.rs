@@ -1,16 +1,12 @@
 extern crate cpu_monitor;
 use crate::{
     config_manager::ConfigManager,
-    screens::{BasicScreen, Screen, Screenable},
+    screens::widgets::{Align, Size, Widget},
+    screens::{BasicScreen, Canvas, Screen, Screenable},
 };
 use cpu_monitor::CpuInstant;
 use crossbeam_channel::{bounded, Receiver, Sender};
-use image::{ImageBuffer, Rgb, RgbImage};
-use imageproc::{
-    drawing::{draw_filled_rect_mut, draw_hollow_rect_mut, draw_text_mut},
-    rect::Rect,
-};
-use rusttype::{Font, Scale};
+use rusttype::Font;
 use std::{
     rc::Rc,
     sync::{atomic::AtomicBool, atomic::Ordering, Arc, RwLock},
@@ -23,10 +19,32 @@ pub struct SystemInfoScreen {
     receiver: Receiver<SystemInfoState>,
 }
 
-#[derive(Default)]
-struct SystemInfoState {
-    cpu_usage: f64,
-    ram_usage: f64,
+// in percent, also written to the sensor log
+pub struct SystemInfoState {
+    pub cpu_usage: f64,
+    pub ram_usage: f64,
+}
+
+impl SystemInfoState {
+    // takes a second, the cpu usage is measured over it
+    pub fn measure(sys: &System) -> Option<SystemInfoState> {
+        let start = CpuInstant::now();
+        thread::sleep(Duration::from_millis(1000));
+        let duration = CpuInstant::now().ok()? - start.ok()?;
+        match sys.memory() {
+            Ok(mem) => {
+                let ram_usage = saturating_sub_bytes(mem.total, mem.free).as_u64() as f64;
+                Some(SystemInfoState {
+                    cpu_usage: (duration.non_idle() * 100.0).floor(),
+                    ram_usage: ((ram_usage / mem.total.as_u64() as f64) * 100.0).floor(),
+                })
+            }
+            Err(x) => {
+                eprintln!("\nMemory: error: {}", x);
+                None
+            }
+        }
+    }
 }
 
 impl Screenable for SystemInfoScreen {
@@ -48,90 +66,29 @@ impl BasicScreen for SystemInfoScreen {
 }
 
 impl SystemInfoScreen {
-    pub fn draw_cpu(
-        &mut self,
-        image: &mut ImageBuffer<Rgb<u8>, Vec<u8>>,
-        cpu_usage: f64,
-        scale: Scale,
-    ) {
-        let cpu_text = format!("{: >3}%", cpu_usage.to_string());
-        draw_text_mut(
-            image,
-            Rgb([255u8, 255u8, 255u8]),
-            0,
-            0,
-            scale,
-            &self.screen.font,
-            "CPU",
-        );
-        draw_text_mut(
-            image,
-            Rgb([255u8, 255u8, 255u8]),
-            222,
-            0,
-            scale,
-            &self.screen.font,
-            &cpu_text,
-        );
-        draw_hollow_rect_mut(
-            image,
-            Rect::at(0, 16).of_size(256, 10),
-            Rgb([255u8, 255u8, 255u8]),
-        );
-
-        let cpu_filled = ((cpu_usage * 2.56) + 1.0).floor() as u32;
-        draw_filled_rect_mut(
-            image,
-            Rect::at(0, 16).of_size(cpu_filled, 10),
-            Rgb([255u8, 255u8, 255u8]),
-        );
-    }
-    pub fn draw_memory(
-        &mut self,
-        image: &mut ImageBuffer<Rgb<u8>, Vec<u8>>,
-        ram_usage: f64,
-        scale: Scale,
-    ) {
-        let memory_text = format!("{: >3}%", ram_usage.to_string());
-        draw_text_mut(
-            image,
-            Rgb([255u8, 255u8, 255u8]),
-            0,
-            30,
-            scale,
-            &self.screen.font,
-            "RAM",
-        );
-        draw_text_mut(
-            image,
-            Rgb([255u8, 255u8, 255u8]),
-            222,
-            30,
-            scale,
-            &self.screen.font,
-            &memory_text,
-        );
-        draw_hollow_rect_mut(
-            image,
-            Rect::at(0, 48).of_size(256, 10),
-            Rgb([255u8, 255u8, 255u8]),
-        );
-
-        let memory_filled = ((ram_usage * 2.56) + 1.0).floor() as u32;
-        draw_filled_rect_mut(
-            image,
-            Rect::at(0, 48).of_size(memory_filled, 10),
-            Rgb([255u8, 255u8, 255u8]),
-        );
+    // name and value above a bar
+    fn usage(&self, name: &str, usage: f64) -> Widget {
+        let font = &self.screen.font;
+        Widget::column(vec![
+            Widget::row(vec![
+                Widget::label(font, name, 16.0),
+                Widget::label(font, &format!("{}%", usage), 16.0).align(Align::End),
+            ])
+            .height(Size::Fixed(16)),
+            Widget::progress_bar(usage as f32 / 100.0).height(Size::Fixed(10)),
+        ])
     }
 
     fn draw_screen(&mut self, cpu_usage: f64, ram_usage: f64) {
         // draw initial image
-        let mut image = RgbImage::new(256, 64);
-        let scale = Scale { x: 16.0, y: 16.0 };
-
-        self.draw_cpu(&mut image, cpu_usage, scale);
-        self.draw_memory(&mut image, ram_usage, scale);
+        let canvas = self.screen.main_canvas;
+        let mut image = canvas.image();
+        Widget::column(vec![
+            self.usage("CPU", cpu_usage),
+            self.usage("RAM", ram_usage),
+        ])
+        .spacing(6)
+        .render(&canvas, &mut image);
         self.screen.main_screen_bytes = image.into_vec();
     }
 
@@ -143,6 +100,7 @@ impl SystemInfoScreen {
     ) -> SystemInfoScreen {
         let (tx, rx): (Sender<SystemInfoState>, Receiver<SystemInfoState>) = bounded(1);
         let active = Arc::new(AtomicBool::new(false));
+        let (main_canvas, companion_canvas) = Canvas::for_devices(&config_manager);
         let mut this = SystemInfoScreen {
             screen: Screen {
                 description,
@@ -157,30 +115,17 @@ impl SystemInfoScreen {
                         while !active.load(Ordering::Acquire) {
                             thread::park();
                         }
-                        let start = cpu_monitor::CpuInstant::now().unwrap();
-                        thread::sleep(Duration::from_millis(1000));
-                        let end = CpuInstant::now().unwrap();
-                        let duration = end - start;
-                        let mut system_info: SystemInfoState = Default::default();
-                        system_info.cpu_usage = (duration.non_idle() * 100.0).floor();
-                        match sys.memory() {
-                            Ok(mem) => {
-                                system_info.ram_usage =
-                                    saturating_sub_bytes(mem.total, mem.free).as_u64() as f64;
-                                system_info.ram_usage =
-                                    ((system_info.ram_usage / mem.total.as_u64() as f64) * 100.0)
-                                        .floor();
-
-                                // we are right now not interested in the error value.
-                                // since we only want to have the most recent screen,
-                                // it is ok, if screen infos get lost
-                                sender.try_send(system_info).unwrap_or_default();
-                            }
-                            Err(x) => eprintln!("\nMemory: error: {}", x),
+                        if let Some(system_info) = SystemInfoState::measure(&sys) {
+                            // we are right now not interested in the error value.
+                            // since we only want to have the most recent screen,
+                            // it is ok, if screen infos get lost
+                            sender.try_send(system_info).unwrap_or_default();
                         }
                     }
                 })),
                 config_manager,
+                main_canvas,
+                companion_canvas,
                 ..Default::default()
             },
             receiver: rx,
@@ -1,22 +1,25 @@
 use crate::config_manager::ConfigManager;
+use crate::screens::widgets::{Align, Size, Widget};
 use crate::screens::BasicScreen;
+use crate::screens::Canvas;
 use crate::screens::Screen;
 use crate::screens::Screenable;
+use crate::sensor::{Quantity, Trend};
 use crate::weather::*;
-use crate::LAST_BME_INFO;
+use crate::SENSOR_HISTORY;
 use chrono::Datelike;
 use crossbeam_channel::bounded;
 use crossbeam_channel::{Receiver, Sender};
-use image::{ImageBuffer, Rgb, RgbImage};
-use imageproc::drawing::draw_text_mut;
 use rusttype::Font;
-use rusttype::Scale;
 use std::rc::Rc;
 use std::sync::{atomic::AtomicBool, atomic::Ordering, Arc, RwLock};
 use std::thread;
 use std::time::Duration;
 use std::time::Instant;
 
+const INDOOR_TREND_SPAN: Duration = Duration::from_secs(30 * 60);
+const INDOOR_RANGE_SPAN: Duration = Duration::from_secs(6 * 60 * 60);
+
 pub struct WeatherScreen {
     screen: Screen,
     symbols: Rc<Font<'static>>,
@@ -64,152 +67,151 @@ impl BasicScreen for WeatherScreen {
 impl WeatherScreen {
     fn draw_companion_screen(&mut self, weather_info: &WeatherInfo) {
         // draw initial image
-        let mut image = RgbImage::new(320, 170);
-
-        let mut x: i32 = 24;
-        for forecast in &weather_info.weather_forecast {
-            // day
-            draw_text_mut(
-                &mut image,
-                Rgb([255u8, 255u8, 255u8]),
-                x,
-                6,
-                Scale { x: 38.0, y: 38.0 },
-                &self.screen.font,
-                forecast.day.as_str(),
-            );
-
-            // icon
-            draw_text_mut(
-                &mut image,
-                Rgb([255u8, 255u8, 255u8]),
-                x - 8,
-                40,
-                Scale { x: 32.0, y: 32.0 },
-                &self.symbols,
-                format!(
-                    "{: >3}",
-                    WeatherScreen::get_weather_icon(forecast.weather_icon, 1)
-                )
-                .as_str(),
-            );
-
-            // min
-            draw_text_mut(
-                &mut image,
-                Rgb([255u8, 255u8, 255u8]),
-                x,
-                80,
-                Scale { x: 22.0, y: 22.0 },
-                &self.screen.font,
-                format!("{: >2} \u{00B0}C", forecast.min.round() as i64).as_str(),
-            );
-
-            // max
-            draw_text_mut(
-                &mut image,
-                Rgb([255u8, 255u8, 255u8]),
-                x,
-                100,
-                Scale { x: 22.0, y: 22.0 },
-                &self.screen.font,
-                format!("{: >2} \u{00B0}C", forecast.max.round() as i64).as_str(),
-            );
+        let canvas = self.screen.companion_canvas;
+        let mut image = canvas.image();
+        let font = &self.screen.font;
 
-            x += 103;
-        }
+        let forecast = weather_info
+            .weather_forecast
+            .iter()
+            .map(|forecast| {
+                Widget::column(vec![
+                    Widget::label(font, &forecast.day, 38.0).align(Align::Center),
+                    Widget::icon(
+                        &self.symbols,
+                        &WeatherScreen::get_weather_icon(forecast.weather_icon, 1),
+                        32.0,
+                    )
+                    .height(Size::Fixed(40)),
+                    Widget::label(
+                        font,
+                        &format!("{} \u{00B0}C", forecast.min.round() as i64),
+                        22.0,
+                    )
+                    .align(Align::Center),
+                    Widget::label(
+                        font,
+                        &format!("{} \u{00B0}C", forecast.max.round() as i64),
+                        22.0,
+                    )
+                    .align(Align::Center),
+                ])
+            })
+            .collect();
+        let mut rows = vec![Widget::row(forecast).height(Size::Fixed(122))];
+        rows.extend(self.indoor_climate());
+        Widget::column(rows).padding(6).render(&canvas, &mut image);
 
         self.screen.companion_screen_bytes = image.into_vec();
     }
 
     fn draw_screen(&mut self, weather_info: &WeatherInfo) {
         // draw initial image
-        let mut image = RgbImage::new(256, 64);
-        self.draw_weather_info(weather_info, &mut image);
-        self.screen.main_screen_bytes = image.into_vec();
-    }
-    fn draw_weather_info(
-        &mut self,
-        weather_info: &WeatherInfo,
-        image: &mut ImageBuffer<Rgb<u8>, Vec<u8>>,
-    ) {
-        // icon
-        draw_text_mut(
-            image,
-            Rgb([255u8, 255u8, 255u8]),
-            6,
-            6,
-            Scale { x: 40.0, y: 40.0 },
-            self.symbols.as_ref(),
-            WeatherScreen::get_weather_icon(weather_info.weather_icon, weather_info.is_day)
-                .as_str(),
-        );
+        let canvas = self.screen.main_canvas;
+        let mut image = canvas.image();
+        let font = &self.screen.font;
 
-        // temperature
-        draw_text_mut(
-            image,
-            Rgb([255u8, 255u8, 255u8]),
-            72,
-            6,
-            Scale { x: 32.0, y: 32.0 },
-            &self.screen.font,
-            format!("{}\u{00B0}C", (weather_info.temperature.round() as i64)).as_str(),
-        );
+        let indoor = match SENSOR_HISTORY.lock().unwrap().latest() {
+            Some(reading) => Widget::label(
+                font,
+                &format!("{:.1}°C / {:.0}%", reading.temperature, reading.humidity),
+                14.0,
+            ),
+            None => Widget::space(),
+        };
+        let wind = Widget::column(vec![
+            Widget::row(vec![
+                Widget::icon(&self.symbols, "\u{f72e}", 14.0).width(Size::Fixed(18)),
+                Widget::label(
+                    font,
+                    &format!("{} km/h", (weather_info.wind * 3.6).round()),
+                    14.0,
+                ),
+            ])
+            .height(Size::Fixed(14)),
+            Widget::row(vec![
+                Widget::space().width(Size::Fixed(18)),
+                Widget::label(font, &weather_info.wind_direction, 14.0),
+            ])
+            .height(Size::Fixed(14)),
+        ])
+        .padding(4);
 
-        // city
-        draw_text_mut(
-            image,
-            Rgb([255u8, 255u8, 255u8]),
-            4,
-            50,
-            Scale { x: 14.0, y: 14.0 },
-            &self.screen.font,
-            weather_info.city.as_str(),
-        );
+        Widget::column(vec![
+            Widget::row(vec![
+                Widget::icon(
+                    &self.symbols,
+                    &WeatherScreen::get_weather_icon(
+                        weather_info.weather_icon,
+                        weather_info.is_day,
+                    ),
+                    40.0,
+                )
+                .width(Size::Fixed(60)),
+                Widget::column(vec![
+                    Widget::label(
+                        font,
+                        &format!("{}\u{00B0}C", weather_info.temperature.round() as i64),
+                        32.0,
+                    ),
+                    indoor,
+                ])
+                .width(Size::Fixed(92)),
+                wind,
+            ]),
+            Widget::label(font, &weather_info.city, 14.0).ellipsis(),
+        ])
+        .padding(4)
+        .render(&canvas, &mut image);
 
-        // wind symbol
-        draw_text_mut(
-            image,
-            Rgb([255u8, 255u8, 255u8]),
-            160,
-            10,
-            Scale { x: 14.0, y: 14.0 },
-            self.symbols.as_ref(),
-            "\u{f72e}".to_string().as_str(),
-        );
-        // wind speed
-        draw_text_mut(
-            image,
-            Rgb([255u8, 255u8, 255u8]),
-            178,
-            10,
-            Scale { x: 14.0, y: 14.0 },
-            &self.screen.font,
-            format!("{} km/h", ((weather_info.wind) * 3.6).round()).as_str(),
-        );
+        self.screen.main_screen_bytes = image.into_vec();
+    }
 
-        // wind direction
-        draw_text_mut(
-            image,
-            Rgb([255u8, 255u8, 255u8]),
-            178,
-            24,
-            Scale { x: 14.0, y: 14.0 },
-            &self.screen.font,
-            weather_info.wind_direction.to_string().as_str(),
+    // current values with trend and the range of the last hours
+    fn indoor_climate(&self) -> Vec<Widget> {
+        let font = &self.screen.font;
+        let history = SENSOR_HISTORY.lock().unwrap();
+        let reading = match history.latest() {
+            Some(reading) => reading,
+            None => return Vec::new(),
+        };
+        let trend = |quantity| match history.trend(quantity, INDOOR_TREND_SPAN) {
+            Some(Trend::Rising) => "\u{2191}",
+            Some(Trend::Falling) => "\u{2193}",
+            _ => "",
+        };
+        let mut current = format!(
+            "Indoor {:.1} \u{00B0}C{} {:.0} %",
+            reading.temperature,
+            trend(Quantity::Temperature),
+            reading.humidity
         );
+        if let Some(pressure) = reading.pressure {
+            current.push_str(&format!(
+                " {:.0} hPa{}",
+                pressure,
+                trend(Quantity::Pressure)
+            ));
+        }
+        let mut lines = vec![Widget::label(font, &current, 18.0)
+            .align(Align::Center)
+            .ellipsis()];
 
-        // indoor temperature / indoor humidity
-        let (temperature, humidity) = LAST_BME_INFO.lock().unwrap().clone();
-        draw_text_mut(
-            image,
-            Rgb([255u8, 255u8, 255u8]),
-            72,
-            38,
-            Scale { x: 14.0, y: 14.0 },
-            &self.screen.font,
-            format!("{}°C / {}%", temperature, humidity).as_str(),
-        );
+        if let (Some(temperature), Some(humidity)) = (
+            history.min_max(Quantity::Temperature, INDOOR_RANGE_SPAN),
+            history.min_max(Quantity::Humidity, INDOOR_RANGE_SPAN),
+        ) {
+            let range = format!(
+                "{:.1} - {:.1} \u{00B0}C, {:.0} - {:.0} % in 6 h",
+                temperature.0, temperature.1, humidity.0, humidity.1
+            );
+            lines.push(
+                Widget::label(font, &range, 18.0)
+                    .align(Align::Center)
+                    .ellipsis(),
+            );
+        }
+        lines
     }
 
     fn get_weather_icon(code: u8, is_day: u8) -> String {
@@ -250,6 +252,7 @@ impl WeatherScreen {
     ) -> WeatherScreen {
         let (tx, rx): (Sender<WeatherInfo>, Receiver<WeatherInfo>) = bounded(1);
         let active = Arc::new(AtomicBool::new(false));
+        let (main_canvas, companion_canvas) = Canvas::for_devices(&config_manager);
         let mut this = WeatherScreen {
             screen: Screen {
                 description,
@@ -362,6 +365,8 @@ impl WeatherScreen {
                         thread::sleep(Duration::from_millis(1000));
                     }
                 })),
+                main_canvas,
+                companion_canvas,
                 ..Default::default()
             },
             symbols: Rc::clone(&symbols),
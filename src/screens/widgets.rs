@@ -0,0 +1,853 @@
+// A small widget tree for the screens. Screens declare labels, icons, bars and images
+// in rows, columns and stacks, sizes and paddings are given in pixels of the layout
+// size, the canvas turns them into pixels of the panel when the tree is rendered.
+use super::marquee::Scroll;
+use super::Canvas;
+use image::{imageops, GenericImage, Rgb, RgbImage};
+use imageproc::{
+    drawing::{draw_filled_rect_mut, draw_hollow_rect_mut, draw_line_segment_mut, draw_text_mut},
+    rect::Rect,
+};
+use rusttype::{point, Font, Scale};
+use serde::Deserialize;
+use std::rc::Rc;
+use unicode_segmentation::UnicodeSegmentation;
+
+const WHITE: Rgb<u8> = Rgb([255u8, 255u8, 255u8]);
+const ELLIPSIS: &str = "\u{2026}";
+const JOINER: char = '\u{200d}';
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Size {
+    // an equal share of the space the other children leave
+    Fill,
+    // in layout pixels
+    Fixed(u32),
+    // as large as the content
+    Shrink,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Align {
+    Start,
+    Center,
+    End,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Fit {
+    // whole image, keeps the aspect ratio
+    Contain,
+    // fills the box, keeps the aspect ratio and crops the overlap
+    Cover,
+    Stretch,
+    // original size, centered
+    None,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum BarStyle {
+    Fill,
+    // a block at the position with a dashed line up to it
+    Marker,
+}
+
+pub struct Widget {
+    kind: Kind,
+    width: Size,
+    height: Size,
+}
+
+enum Kind {
+    Label(Label),
+    ProgressBar(ProgressBar),
+    Image(ImageBox),
+    Row(Layout),
+    Column(Layout),
+    Stack(Layout),
+    Space,
+}
+
+struct Label {
+    text: String,
+    font: Rc<Font<'static>>,
+    size: f32,
+    color: Rgb<u8>,
+    align: Align,
+    vertical_align: Align,
+    ellipsis: bool,
+    scroll: Option<Scroll>,
+}
+
+struct ProgressBar {
+    // 0 - 1
+    progress: f32,
+    style: BarStyle,
+    color: Rgb<u8>,
+}
+
+struct ImageBox {
+    image: RgbImage,
+    fit: Fit,
+    background: Option<Rgb<u8>>,
+}
+
+struct Layout {
+    children: Vec<Widget>,
+    padding: u32,
+    spacing: u32,
+    background: Option<Rgb<u8>>,
+}
+
+// area of a widget in panel pixels
+#[derive(Clone, Copy)]
+struct Bounds {
+    x: i32,
+    y: i32,
+    width: u32,
+    height: u32,
+}
+
+impl Widget {
+    fn new(kind: Kind) -> Widget {
+        Widget {
+            kind,
+            width: Size::Fill,
+            height: Size::Fill,
+        }
+    }
+
+    // one line of text, as high as its font size
+    pub fn label(font: &Rc<Font<'static>>, text: &str, size: f32) -> Widget {
+        Widget {
+            height: Size::Shrink,
+            ..Widget::new(Kind::Label(Label {
+                text: drawn_text(font, text),
+                font: font.clone(),
+                size,
+                color: WHITE,
+                align: Align::Start,
+                vertical_align: Align::Start,
+                ellipsis: false,
+                scroll: None,
+            }))
+        }
+    }
+
+    // a glyph of the symbol font, centered in its box
+    pub fn icon(symbols: &Rc<Font<'static>>, glyph: &str, size: f32) -> Widget {
+        Widget::label(symbols, glyph, size)
+            .height(Size::Fill)
+            .align(Align::Center)
+            .vertical_align(Align::Center)
+    }
+
+    pub fn progress_bar(progress: f32) -> Widget {
+        Widget::new(Kind::ProgressBar(ProgressBar {
+            progress: progress.clamp(0.0, 1.0),
+            style: BarStyle::Fill,
+            color: WHITE,
+        }))
+    }
+
+    pub fn image(image: RgbImage) -> Widget {
+        Widget::new(Kind::Image(ImageBox {
+            image,
+            fit: Fit::Contain,
+            background: None,
+        }))
+    }
+
+    pub fn row(children: Vec<Widget>) -> Widget {
+        Widget::new(Kind::Row(Layout::new(children)))
+    }
+
+    pub fn column(children: Vec<Widget>) -> Widget {
+        Widget::new(Kind::Column(Layout::new(children)))
+    }
+
+    // all children on top of each other, the first one at the bottom
+    pub fn stack(children: Vec<Widget>) -> Widget {
+        Widget::new(Kind::Stack(Layout::new(children)))
+    }
+
+    pub fn space() -> Widget {
+        Widget::new(Kind::Space)
+    }
+
+    pub fn width(mut self, width: Size) -> Widget {
+        self.width = width;
+        self
+    }
+
+    pub fn height(mut self, height: Size) -> Widget {
+        self.height = height;
+        self
+    }
+
+    // horizontal alignment of a label, text wider than its box starts at the left
+    pub fn align(mut self, align: Align) -> Widget {
+        if let Kind::Label(label) = &mut self.kind {
+            label.align = align;
+        }
+        self
+    }
+
+    pub fn vertical_align(mut self, align: Align) -> Widget {
+        if let Kind::Label(label) = &mut self.kind {
+            label.vertical_align = align;
+        }
+        self
+    }
+
+    // cuts text, which does not fit into its box, with "…"
+    pub fn ellipsis(mut self) -> Widget {
+        if let Kind::Label(label) = &mut self.kind {
+            label.ellipsis = true;
+        }
+        self
+    }
+
+    // scrolls text, which does not fit into its box, see `Marquee`
+    pub fn scroll(mut self, scroll: Scroll) -> Widget {
+        if let Kind::Label(label) = &mut self.kind {
+            label.scroll = Some(scroll);
+        }
+        self
+    }
+
+    pub fn color(mut self, color: Rgb<u8>) -> Widget {
+        match &mut self.kind {
+            Kind::Label(label) => label.color = color,
+            Kind::ProgressBar(bar) => bar.color = color,
+            _ => {}
+        }
+        self
+    }
+
+    pub fn bar_style(mut self, style: BarStyle) -> Widget {
+        if let Kind::ProgressBar(bar) = &mut self.kind {
+            bar.style = style;
+        }
+        self
+    }
+
+    pub fn fit(mut self, fit: Fit) -> Widget {
+        if let Kind::Image(image) = &mut self.kind {
+            image.fit = fit;
+        }
+        self
+    }
+
+    pub fn background(mut self, color: Rgb<u8>) -> Widget {
+        match &mut self.kind {
+            Kind::Image(image) => image.background = Some(color),
+            Kind::Row(layout) | Kind::Column(layout) | Kind::Stack(layout) => {
+                layout.background = Some(color)
+            }
+            _ => {}
+        }
+        self
+    }
+
+    // around the children of a row, column or stack
+    pub fn padding(mut self, padding: u32) -> Widget {
+        if let Kind::Row(layout) | Kind::Column(layout) | Kind::Stack(layout) = &mut self.kind {
+            layout.padding = padding;
+        }
+        self
+    }
+
+    // between the children of a row or column
+    pub fn spacing(mut self, spacing: u32) -> Widget {
+        if let Kind::Row(layout) | Kind::Column(layout) = &mut self.kind {
+            layout.spacing = spacing;
+        }
+        self
+    }
+
+    // draws the tree over the whole canvas
+    pub fn render(&self, canvas: &Canvas, image: &mut RgbImage) {
+        let bounds = Bounds {
+            x: 0,
+            y: 0,
+            width: canvas.width,
+            height: canvas.height,
+        };
+        self.draw(canvas, image, bounds);
+    }
+
+    // size of the content in panel pixels
+    fn measure(&self, canvas: &Canvas) -> (u32, u32) {
+        match &self.kind {
+            Kind::Label(label) => {
+                let scale = canvas.scale(label.size);
+                (
+                    text_width(&label.font, scale, &label.text),
+                    scale.y.ceil() as u32,
+                )
+            }
+            Kind::Image(image) => image.image.dimensions(),
+            Kind::Row(layout) => layout.measure(canvas, true),
+            Kind::Column(layout) => layout.measure(canvas, false),
+            Kind::Stack(layout) => {
+                let padding = layout.padding_of(canvas);
+                let (width, height) = layout
+                    .children
+                    .iter()
+                    .map(|child| child.outer_size(canvas))
+                    .fold((0, 0), |size, child| {
+                        (size.0.max(child.0), size.1.max(child.1))
+                    });
+                (width + padding.0 * 2, height + padding.1 * 2)
+            }
+            Kind::ProgressBar(_) | Kind::Space => (0, 0),
+        }
+    }
+
+    // size a parent reserves, fill counts as nothing
+    fn outer_size(&self, canvas: &Canvas) -> (u32, u32) {
+        (
+            self.size_along(canvas, true).unwrap_or(0),
+            self.size_along(canvas, false).unwrap_or(0),
+        )
+    }
+
+    // none for fill
+    fn size_along(&self, canvas: &Canvas, horizontal: bool) -> Option<u32> {
+        match (
+            horizontal,
+            if horizontal { self.width } else { self.height },
+        ) {
+            (_, Size::Fill) => None,
+            (true, Size::Fixed(width)) => Some(layout_pixels(canvas, width, true)),
+            (false, Size::Fixed(height)) => Some(layout_pixels(canvas, height, false)),
+            (true, Size::Shrink) => Some(self.measure(canvas).0),
+            (false, Size::Shrink) => Some(self.measure(canvas).1),
+        }
+    }
+
+    // children fill their parent across its axis, unless they have a size for it
+    fn place_across(&self, canvas: &Canvas, bounds: Bounds, horizontal: Option<bool>) -> Bounds {
+        let width = match horizontal {
+            Some(true) => bounds.width,
+            _ => self
+                .size_along(canvas, true)
+                .map_or(bounds.width, |width| width.min(bounds.width)),
+        };
+        let height = match horizontal {
+            Some(false) => bounds.height,
+            _ => self
+                .size_along(canvas, false)
+                .map_or(bounds.height, |height| height.min(bounds.height)),
+        };
+        Bounds {
+            width,
+            height,
+            ..bounds
+        }
+    }
+
+    fn draw(&self, canvas: &Canvas, image: &mut RgbImage, bounds: Bounds) {
+        if bounds.width == 0 || bounds.height == 0 {
+            return;
+        }
+        match &self.kind {
+            Kind::Label(label) => label.draw(canvas, image, bounds),
+            Kind::ProgressBar(bar) => bar.draw(canvas, image, bounds),
+            Kind::Image(image_box) => image_box.draw(image, bounds),
+            Kind::Row(layout) => layout.draw(canvas, image, bounds, Some(true)),
+            Kind::Column(layout) => layout.draw(canvas, image, bounds, Some(false)),
+            Kind::Stack(layout) => layout.draw(canvas, image, bounds, None),
+            Kind::Space => {}
+        }
+    }
+}
+
+impl Label {
+    fn draw(&self, canvas: &Canvas, image: &mut RgbImage, bounds: Bounds) {
+        let scale = canvas.scale(self.size);
+        let mut text = self.text.clone();
+        let mut width = text_width(&self.font, scale, &text);
+        if self.ellipsis && width > bounds.width {
+            let mut graphemes: Vec<&str> = self.text.graphemes(true).collect();
+            while width > bounds.width && graphemes.pop().is_some() {
+                text = [graphemes.concat().trim_end(), ELLIPSIS].concat();
+                width = text_width(&self.font, scale, &text);
+            }
+        }
+
+        let height = scale.y.ceil() as u32;
+        let y = match self.vertical_align {
+            _ if height >= bounds.height => bounds.y,
+            Align::Start => bounds.y,
+            Align::Center => bounds.y + (bounds.height - height) as i32 / 2,
+            Align::End => bounds.y + (bounds.height - height) as i32,
+        };
+        if let Some(scroll) = self.scroll.filter(|_| width > bounds.width) {
+            self.draw_scrolled(canvas, image, bounds, y, &text, scroll);
+            return;
+        }
+
+        // labels are not clipped to their box, only to the canvas
+        let x = match self.align {
+            _ if width >= bounds.width => bounds.x,
+            Align::Start => bounds.x,
+            Align::Center => bounds.x + (bounds.width - width) as i32 / 2,
+            Align::End => bounds.x + (bounds.width - width) as i32,
+        };
+        draw_text_mut(image, self.color, x, y, scale, &self.font, &text);
+    }
+
+    // clipped to the width of the box, so other widgets of the row stay readable
+    fn draw_scrolled(
+        &self,
+        canvas: &Canvas,
+        image: &mut RgbImage,
+        bounds: Bounds,
+        y: i32,
+        text: &str,
+        scroll: Scroll,
+    ) {
+        let scale = canvas.scale(self.size);
+        let left = bounds.x.max(0) as u32;
+        let width = bounds.width.min(image.width().saturating_sub(left));
+        let height = image.height();
+        if width == 0 {
+            return;
+        }
+        let positions = scroll.positions(canvas, text_width(&self.font, scale, text), bounds.width);
+        let mut view = image.sub_image(left, 0, width, height);
+        for position in positions {
+            let x = bounds.x - left as i32 + position;
+            draw_text_mut(&mut *view, self.color, x, y, scale, &self.font, text);
+        }
+    }
+}
+
+impl ProgressBar {
+    fn draw(&self, canvas: &Canvas, image: &mut RgbImage, bounds: Bounds) {
+        draw_hollow_rect_mut(image, bounds.rect(), self.color);
+        match self.style {
+            BarStyle::Fill => {
+                let filled = (self.progress * bounds.width as f32).round() as u32;
+                if filled > 0 {
+                    draw_filled_rect_mut(
+                        image,
+                        Rect::at(bounds.x, bounds.y).of_size(filled, bounds.height),
+                        self.color,
+                    );
+                }
+            }
+            BarStyle::Marker => {
+                let marker = canvas.width_of(6).min(bounds.width);
+                let position =
+                    bounds.x + (self.progress * (bounds.width - marker) as f32).round() as i32;
+                draw_filled_rect_mut(
+                    image,
+                    Rect::at(position, bounds.y).of_size(marker, bounds.height),
+                    self.color,
+                );
+
+                let y = (bounds.y + bounds.height as i32 / 2) as f32;
+                let mut x = bounds.x;
+                while x < position {
+                    let end = (x + marker as i32 / 2).min(position);
+                    draw_line_segment_mut(image, (x as f32, y), (end as f32, y), self.color);
+                    x += marker as i32;
+                }
+            }
+        }
+    }
+}
+
+impl ImageBox {
+    fn draw(&self, image: &mut RgbImage, bounds: Bounds) {
+        if let Some(background) = self.background {
+            draw_filled_rect_mut(image, bounds.rect(), background);
+        }
+        let (width, height) = self.image.dimensions();
+        if width == 0 || height == 0 {
+            return;
+        }
+        let resize = |width: f32, height: f32| {
+            imageops::resize(
+                &self.image,
+                (width.round() as u32).max(1),
+                (height.round() as u32).max(1),
+                imageops::FilterType::Lanczos3,
+            )
+        };
+        let factor_x = bounds.width as f32 / width as f32;
+        let factor_y = bounds.height as f32 / height as f32;
+        let fitted = match self.fit {
+            Fit::Contain => {
+                let factor = factor_x.min(factor_y);
+                resize(width as f32 * factor, height as f32 * factor)
+            }
+            Fit::Cover => {
+                let factor = factor_x.max(factor_y);
+                let covered = resize(width as f32 * factor, height as f32 * factor);
+                let x = (covered.width().saturating_sub(bounds.width)) / 2;
+                let y = (covered.height().saturating_sub(bounds.height)) / 2;
+                imageops::crop_imm(&covered, x, y, bounds.width, bounds.height).to_image()
+            }
+            Fit::Stretch => resize(bounds.width as f32, bounds.height as f32),
+            Fit::None => self.image.clone(),
+        };
+
+        // centered, the parts outside of the box are cut off
+        let x = bounds.x as i64 + (bounds.width as i64 - fitted.width() as i64) / 2;
+        let y = bounds.y as i64 + (bounds.height as i64 - fitted.height() as i64) / 2;
+        let visible_x = (bounds.x as i64 - x).max(0) as u32;
+        let visible_y = (bounds.y as i64 - y).max(0) as u32;
+        let visible = imageops::crop_imm(
+            &fitted,
+            visible_x,
+            visible_y,
+            bounds.width.min(fitted.width()),
+            bounds.height.min(fitted.height()),
+        );
+        imageops::overlay(
+            image,
+            &visible.to_image(),
+            x + visible_x as i64,
+            y + visible_y as i64,
+        );
+    }
+}
+
+impl Layout {
+    fn new(children: Vec<Widget>) -> Layout {
+        Layout {
+            children,
+            padding: 0,
+            spacing: 0,
+            background: None,
+        }
+    }
+
+    fn padding_of(&self, canvas: &Canvas) -> (u32, u32) {
+        (
+            layout_pixels(canvas, self.padding, true),
+            layout_pixels(canvas, self.padding, false),
+        )
+    }
+
+    // children next to each other along the axis, the largest one across it
+    fn measure(&self, canvas: &Canvas, horizontal: bool) -> (u32, u32) {
+        let padding = self.padding_of(canvas);
+        let spacing = layout_pixels(canvas, self.spacing, horizontal)
+            * self.children.len().saturating_sub(1) as u32;
+        let (along, across) = self
+            .children
+            .iter()
+            .map(|child| child.outer_size(canvas))
+            .map(|(width, height)| {
+                if horizontal {
+                    (width, height)
+                } else {
+                    (height, width)
+                }
+            })
+            .fold((spacing, 0), |(along, across), child| {
+                (along + child.0, across.max(child.1))
+            });
+        if horizontal {
+            (along + padding.0 * 2, across + padding.1 * 2)
+        } else {
+            (across + padding.0 * 2, along + padding.1 * 2)
+        }
+    }
+
+    // along the axis of a row or a column, none for a stack
+    fn draw(
+        &self,
+        canvas: &Canvas,
+        image: &mut RgbImage,
+        bounds: Bounds,
+        horizontal: Option<bool>,
+    ) {
+        if let Some(background) = self.background {
+            draw_filled_rect_mut(image, bounds.rect(), background);
+        }
+        let padding = self.padding_of(canvas);
+        let inner = Bounds {
+            x: bounds.x + padding.0 as i32,
+            y: bounds.y + padding.1 as i32,
+            width: bounds.width.saturating_sub(padding.0 * 2),
+            height: bounds.height.saturating_sub(padding.1 * 2),
+        };
+        let horizontal = match horizontal {
+            Some(horizontal) => horizontal,
+            None => {
+                for child in &self.children {
+                    child.draw(canvas, image, child.place_across(canvas, inner, None));
+                }
+                return;
+            }
+        };
+
+        let available = if horizontal {
+            inner.width
+        } else {
+            inner.height
+        };
+        let spacing = layout_pixels(canvas, self.spacing, horizontal);
+        let sizes: Vec<Option<u32>> = self
+            .children
+            .iter()
+            .map(|child| child.size_along(canvas, horizontal))
+            .collect();
+        let used = sizes.iter().flatten().sum::<u32>()
+            + spacing * self.children.len().saturating_sub(1) as u32;
+        let fills = sizes.iter().filter(|size| size.is_none()).count().max(1) as u32;
+        let share = available.saturating_sub(used) / fills;
+
+        let mut position = 0u32;
+        for (child, size) in self.children.iter().zip(sizes) {
+            let size = size
+                .unwrap_or(share)
+                .min(available.saturating_sub(position));
+            let bounds = if horizontal {
+                Bounds {
+                    x: inner.x + position as i32,
+                    width: size,
+                    ..inner
+                }
+            } else {
+                Bounds {
+                    y: inner.y + position as i32,
+                    height: size,
+                    ..inner
+                }
+            };
+            child.draw(
+                canvas,
+                image,
+                child.place_across(canvas, bounds, Some(horizontal)),
+            );
+            position += size + spacing;
+        }
+    }
+}
+
+impl Bounds {
+    fn rect(&self) -> Rect {
+        Rect::at(self.x, self.y).of_size(self.width, self.height)
+    }
+}
+
+fn layout_pixels(canvas: &Canvas, pixels: u32, horizontal: bool) -> u32 {
+    let pixels = if horizontal {
+        canvas.x(pixels as i32)
+    } else {
+        canvas.y(pixels as i32)
+    };
+    pixels.max(0) as u32
+}
+
+// rusttype draws every char on its own, so a grapheme of several chars, like an emoji
+// with a skin tone or a family joined by zero width joiners, would take the place of
+// several glyphs. Keeps the first char of each grapheme and the marks of the font
+// drawn onto it, up to a joiner.
+fn drawn_text(font: &Font, text: &str) -> String {
+    let is_mark = |c: char| {
+        let glyph = font.glyph(c);
+        glyph.id().0 != 0 && glyph.scaled(Scale::uniform(1.0)).h_metrics().advance_width == 0.0
+    };
+    text.graphemes(true)
+        .flat_map(|grapheme| {
+            let mut chars = grapheme.chars();
+            chars
+                .next()
+                .into_iter()
+                .chain(chars.take_while(|c| *c != JOINER).filter(|c| is_mark(*c)))
+        })
+        .collect()
+}
+
+// width of a line of text in panel pixels
+pub fn text_width(font: &Font, scale: Scale, text: &str) -> u32 {
+    font.layout(text, scale, point(0.0, 0.0))
+        .last()
+        .map_or(0.0, |glyph| {
+            glyph.position().x + glyph.unpositioned().h_metrics().advance_width
+        })
+        .ceil() as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::DisplayRole;
+
+    const RED: Rgb<u8> = Rgb([255u8, 0u8, 0u8]);
+    const GREEN: Rgb<u8> = Rgb([0u8, 255u8, 0u8]);
+    const BLUE: Rgb<u8> = Rgb([0u8, 0u8, 255u8]);
+
+    fn font() -> Rc<Font<'static>> {
+        Rc::new(
+            Font::try_from_vec(Vec::from(include_bytes!("../Liberation.ttf") as &[u8])).unwrap(),
+        )
+    }
+
+    // a bar fills its whole box
+    fn block(color: Rgb<u8>) -> Widget {
+        Widget::progress_bar(1.0).color(color)
+    }
+
+    fn render(widget: &Widget, canvas: &Canvas) -> RgbImage {
+        let mut image = canvas.image();
+        widget.render(canvas, &mut image);
+        image
+    }
+
+    // x, y, width and height around the pixels of the color
+    fn area(image: &RgbImage, color: Rgb<u8>) -> Option<(u32, u32, u32, u32)> {
+        let pixels: Vec<(u32, u32)> = image
+            .enumerate_pixels()
+            .filter(|(_, _, pixel)| **pixel == color)
+            .map(|(x, y, _)| (x, y))
+            .collect();
+        let left = pixels.iter().map(|pixel| pixel.0).min()?;
+        let top = pixels.iter().map(|pixel| pixel.1).min()?;
+        let right = pixels.iter().map(|pixel| pixel.0).max()?;
+        let bottom = pixels.iter().map(|pixel| pixel.1).max()?;
+        Some((left, top, right - left + 1, bottom - top + 1))
+    }
+
+    #[test]
+    fn fills_share_what_the_other_children_leave() {
+        let canvas = Canvas::new(DisplayRole::Main, 256, 64);
+        let row = Widget::row(vec![
+            block(RED).width(Size::Fixed(56)),
+            block(GREEN),
+            block(BLUE),
+        ]);
+        let image = render(&row, &canvas);
+        assert_eq!(area(&image, RED), Some((0, 0, 56, 64)));
+        assert_eq!(area(&image, GREEN), Some((56, 0, 100, 64)));
+        assert_eq!(area(&image, BLUE), Some((156, 0, 100, 64)));
+
+        // as wide as the image, which is drawn in its original size
+        let picture = RgbImage::from_pixel(20, 10, RED);
+        let row = Widget::row(vec![
+            Widget::image(picture).fit(Fit::None).width(Size::Shrink),
+            block(GREEN),
+        ]);
+        let image = render(&row, &canvas);
+        assert_eq!(area(&image, RED), Some((0, 27, 20, 10)));
+        assert_eq!(area(&image, GREEN), Some((20, 0, 236, 64)));
+    }
+
+    #[test]
+    fn padding_and_spacing_grow_with_the_canvas() {
+        let column = Widget::column(vec![block(RED), block(GREEN)])
+            .padding(4)
+            .spacing(2);
+
+        let image = render(&column, &Canvas::new(DisplayRole::Main, 256, 64));
+        assert_eq!(area(&image, RED), Some((4, 4, 248, 27)));
+        assert_eq!(area(&image, GREEN), Some((4, 33, 248, 27)));
+
+        let image = render(&column, &Canvas::new(DisplayRole::Main, 512, 128));
+        assert_eq!(area(&image, RED), Some((8, 8, 496, 54)));
+        assert_eq!(area(&image, GREEN), Some((8, 66, 496, 54)));
+    }
+
+    #[test]
+    fn ellipsis_keeps_text_in_its_box() {
+        let canvas = Canvas::new(DisplayRole::Main, 256, 64);
+        let text = "a rather long title of a song";
+        let label = |ellipsis: bool| {
+            let label = Widget::label(&font(), text, 16.0).width(Size::Fixed(60));
+            let label = if ellipsis { label.ellipsis() } else { label };
+            Widget::row(vec![label, Widget::space()])
+        };
+        let right = |image: &RgbImage| {
+            image
+                .enumerate_pixels()
+                .filter(|(_, _, pixel)| pixel.0 != [0, 0, 0])
+                .map(|(x, _, _)| x)
+                .max()
+                .unwrap()
+        };
+
+        assert!(right(&render(&label(false), &canvas)) >= 60);
+        let cut = right(&render(&label(true), &canvas));
+        // still most of the box
+        assert!((40..60).contains(&cut));
+    }
+
+    #[test]
+    fn cover_crops_the_overlap() {
+        let canvas = Canvas::new(DisplayRole::Main, 256, 64);
+        // red, green and blue from the left
+        let picture = RgbImage::from_fn(40, 10, |x, _| match x {
+            0..=9 => RED,
+            10..=29 => GREEN,
+            _ => BLUE,
+        });
+        let boxed = |fit: Fit| {
+            Widget::stack(vec![Widget::image(picture.clone())
+                .fit(fit)
+                .width(Size::Fixed(20))
+                .height(Size::Fixed(20))])
+        };
+        let greenish = |pixel: &Rgb<u8>| pixel.0[0] < 30 && pixel.0[1] > 220 && pixel.0[2] < 30;
+
+        // the middle of the picture, scaled to the height of the box
+        let image = render(&boxed(Fit::Cover), &canvas);
+        for y in 0..20 {
+            for x in 0..20 {
+                assert!(greenish(image.get_pixel(x, y)), "{} {}", x, y);
+            }
+            assert_eq!(*image.get_pixel(20, y), Rgb([0, 0, 0]));
+        }
+        assert!(image
+            .rows()
+            .nth(20)
+            .unwrap()
+            .all(|pixel| pixel.0 == [0, 0, 0]));
+
+        // the whole picture, centered with black above and below
+        let image = render(&boxed(Fit::Contain), &canvas);
+        assert_eq!(*image.get_pixel(10, 0), Rgb([0, 0, 0]));
+        assert!(greenish(image.get_pixel(10, 9)));
+        assert!(image.get_pixel(1, 9).0[0] > 220);
+        assert!(image.get_pixel(18, 9).0[2] > 220);
+    }
+
+    #[test]
+    fn marker_bar_has_a_dashed_line_up_to_the_marker() {
+        let canvas = Canvas::new(DisplayRole::Main, 256, 64);
+        let bar = Widget::stack(vec![Widget::progress_bar(0.5)
+            .bar_style(BarStyle::Marker)
+            .color(RED)
+            .width(Size::Fixed(106))
+            .height(Size::Fixed(10))]);
+        let image = render(&bar, &canvas);
+
+        // a marker 6 pixels wide in the middle of the 100 it can move
+        for y in 0..10 {
+            for x in 50..56 {
+                assert_eq!(*image.get_pixel(x, y), RED);
+            }
+        }
+        // nothing but the outline after it
+        for y in 1..9 {
+            assert_ne!(*image.get_pixel(60, y), RED, "{}", y);
+        }
+        assert_eq!(*image.get_pixel(1, 5), RED);
+        assert_ne!(*image.get_pixel(4, 5), RED);
+        assert_eq!(*image.get_pixel(7, 5), RED);
+        assert_ne!(*image.get_pixel(80, 5), RED);
+        // the outline of the whole bar
+        assert_eq!(*image.get_pixel(105, 5), RED);
+    }
+}
@@ -0,0 +1,247 @@
+// Readings of the environmental sensor (bme280) of the devices and their recent
+// history, screens query it for the current values, min / max and the trend.
+use chrono::{DateTime, Local};
+use std::{collections::VecDeque, time::Duration};
+
+// six hours, the sensor is read every two seconds
+pub const HISTORY_LENGTH: usize = 6 * 60 * 30;
+
+// what the bme280 can measure at all, everything else is a broken reading
+const TEMPERATURE_RANGE: std::ops::RangeInclusive<f32> = -40.0..=85.0;
+const HUMIDITY_RANGE: std::ops::RangeInclusive<f32> = 0.0..=100.0;
+const PRESSURE_RANGE: std::ops::RangeInclusive<f32> = 300.0..=1100.0;
+
+#[derive(Clone, Debug)]
+pub struct SensorReading {
+    // °C
+    pub temperature: f32,
+    // relative, in percent
+    pub humidity: f32,
+    // hPa, only reported by firmware with `FEATURE_PRESSURE`
+    pub pressure: Option<f32>,
+    pub timestamp: DateTime<Local>,
+    // name of the device the sensor is attached to
+    pub device: String,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Quantity {
+    Temperature,
+    Humidity,
+    Pressure,
+}
+
+impl Quantity {
+    // smaller changes are noise
+    fn steady_threshold(&self) -> f32 {
+        match self {
+            Quantity::Temperature => 0.3,
+            Quantity::Humidity => 2.0,
+            Quantity::Pressure => 1.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Trend {
+    Rising,
+    Steady,
+    Falling,
+}
+
+impl SensorReading {
+    pub fn validate(&self) -> Result<(), String> {
+        if !TEMPERATURE_RANGE.contains(&self.temperature) {
+            return Err(format!("temperature {} °C out of range", self.temperature));
+        }
+        if !HUMIDITY_RANGE.contains(&self.humidity) {
+            return Err(format!("humidity {} % out of range", self.humidity));
+        }
+        match self.pressure {
+            Some(pressure) if !PRESSURE_RANGE.contains(&pressure) => {
+                Err(format!("pressure {} hPa out of range", pressure))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn value(&self, quantity: Quantity) -> Option<f32> {
+        match quantity {
+            Quantity::Temperature => Some(self.temperature),
+            Quantity::Humidity => Some(self.humidity),
+            Quantity::Pressure => self.pressure,
+        }
+    }
+}
+
+// ring buffer of the latest readings of all devices, oldest first
+pub struct SensorHistory {
+    readings: VecDeque<SensorReading>,
+    capacity: usize,
+}
+
+impl SensorHistory {
+    pub fn new(capacity: usize) -> SensorHistory {
+        SensorHistory {
+            readings: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub fn push(&mut self, reading: SensorReading) {
+        if self.readings.len() == self.capacity {
+            self.readings.pop_front();
+        }
+        self.readings.push_back(reading);
+    }
+
+    pub fn latest(&self) -> Option<&SensorReading> {
+        self.readings.back()
+    }
+
+    // readings of the given time span, oldest first
+    pub fn since(&self, duration: Duration) -> impl Iterator<Item = &SensorReading> {
+        let start = Local::now()
+            - chrono::Duration::from_std(duration).unwrap_or_else(|_| chrono::Duration::zero());
+        self.readings
+            .iter()
+            .skip_while(move |reading| reading.timestamp < start)
+    }
+
+    pub fn min_max(&self, quantity: Quantity, duration: Duration) -> Option<(f32, f32)> {
+        self.since(duration)
+            .filter_map(|reading| reading.value(quantity))
+            .fold(None, |min_max, value| match min_max {
+                Some((min, max)) => Some((value.min(min), value.max(max))),
+                None => Some((value, value)),
+            })
+    }
+
+    // compares the first and the last reading of the time span
+    pub fn trend(&self, quantity: Quantity, duration: Duration) -> Option<Trend> {
+        let mut values = self
+            .since(duration)
+            .filter_map(|reading| reading.value(quantity));
+        let first = values.next()?;
+        let last = values.last()?;
+        Some(if last - first > quantity.steady_threshold() {
+            Trend::Rising
+        } else if first - last > quantity.steady_threshold() {
+            Trend::Falling
+        } else {
+            Trend::Steady
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    fn reading(minutes_ago: i64, temperature: f32, pressure: Option<f32>) -> SensorReading {
+        SensorReading {
+            temperature,
+            humidity: 45.0,
+            pressure,
+            timestamp: Local::now() - chrono::Duration::minutes(minutes_ago),
+            device: String::from("teensy"),
+        }
+    }
+
+    fn history(readings: Vec<SensorReading>) -> SensorHistory {
+        let mut history = SensorHistory::new(HISTORY_LENGTH);
+        for reading in readings {
+            history.push(reading);
+        }
+        history
+    }
+
+    #[test]
+    fn readings_outside_of_the_sensor_range_are_invalid() {
+        assert!(reading(0, 21.5, Some(1013.0)).validate().is_ok());
+        assert!(reading(0, -40.0, None).validate().is_ok());
+        assert!(reading(0, 85.0, Some(300.0)).validate().is_ok());
+        assert!(reading(0, 85.1, None).validate().is_err());
+        assert!(reading(0, f32::NAN, None).validate().is_err());
+        assert!(reading(0, 21.5, Some(1100.5)).validate().is_err());
+
+        let mut humid = reading(0, 21.5, None);
+        humid.humidity = 100.0;
+        assert!(humid.validate().is_ok());
+        humid.humidity = -0.5;
+        assert!(humid.validate().is_err());
+    }
+
+    #[test]
+    fn oldest_readings_are_dropped_after_six_hours() {
+        let mut history = SensorHistory::new(HISTORY_LENGTH);
+        // one reading every two seconds
+        for index in 0..HISTORY_LENGTH + 2 {
+            history.push(reading(0, index as f32, None));
+        }
+        assert_eq!(history.readings.len(), HISTORY_LENGTH);
+        assert_eq!(history.readings.front().unwrap().temperature, 2.0);
+        assert_eq!(
+            history.latest().unwrap().temperature,
+            (HISTORY_LENGTH + 1) as f32
+        );
+    }
+
+    #[test]
+    fn min_max_of_the_time_span() {
+        let history = history(vec![
+            reading(90, -5.0, None),
+            reading(50, 19.0, Some(1010.0)),
+            reading(30, 23.5, None),
+            reading(10, 21.0, Some(1015.5)),
+        ]);
+        assert_eq!(
+            history.min_max(Quantity::Temperature, 60 * MINUTE),
+            Some((19.0, 23.5))
+        );
+        assert_eq!(
+            history.min_max(Quantity::Temperature, 120 * MINUTE),
+            Some((-5.0, 23.5))
+        );
+        // readings without pressure are left out
+        assert_eq!(
+            history.min_max(Quantity::Pressure, 60 * MINUTE),
+            Some((1010.0, 1015.5))
+        );
+        assert_eq!(history.min_max(Quantity::Temperature, MINUTE), None);
+    }
+
+    #[test]
+    fn trend_compares_first_and_last_reading() {
+        let rising = history(vec![
+            reading(50, 20.0, None),
+            reading(40, 25.0, None),
+            reading(10, 20.5, None),
+        ]);
+        assert_eq!(
+            rising.trend(Quantity::Temperature, 60 * MINUTE),
+            Some(Trend::Rising)
+        );
+        // only the first and the last reading count
+        assert_eq!(
+            rising.trend(Quantity::Temperature, 45 * MINUTE),
+            Some(Trend::Falling)
+        );
+        assert_eq!(
+            rising.trend(Quantity::Humidity, 60 * MINUTE),
+            Some(Trend::Steady)
+        );
+
+        // small changes are noise
+        let steady = history(vec![reading(20, 20.0, None), reading(10, 20.2, None)]);
+        assert_eq!(
+            steady.trend(Quantity::Temperature, 60 * MINUTE),
+            Some(Trend::Steady)
+        );
+        // a single reading has no trend
+        assert_eq!(steady.trend(Quantity::Temperature, 15 * MINUTE), None);
+        assert_eq!(steady.trend(Quantity::Pressure, 60 * MINUTE), None);
+    }
+}
@@ -0,0 +1,360 @@
+// Persists the sensor readings (and optionally the system stats) in a csv or sqlite
+// file, so the room climate can be charted over weeks. A background thread takes a
+// sample every `interval_seconds` and removes samples older than the retention.
+use crate::{
+    config::{SensorLogConfig, SensorLogFormat},
+    screens::system_info_screen::SystemInfoState,
+    sensor::SensorReading,
+    CLOSE_REQUESTED, SENSOR_HISTORY,
+};
+use chrono::{DateTime, Local};
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    thread,
+    time::{Duration, Instant},
+};
+use systemstat::{Platform, System};
+
+const CSV_HEADER: &str = "timestamp,device,temperature,humidity,pressure,cpu_usage,ram_usage";
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+pub struct Sample {
+    pub timestamp: DateTime<Local>,
+    // none, if no new reading arrived since the last sample
+    pub reading: Option<SensorReading>,
+    pub system_info: Option<SystemInfoState>,
+}
+
+pub trait SensorLog: Send {
+    fn append(&mut self, sample: &Sample) -> Result<(), String>;
+    // removes everything older than the given point in time
+    fn prune(&mut self, before: DateTime<Local>) -> Result<(), String>;
+}
+
+fn open_sensor_log(config: &SensorLogConfig) -> Result<Box<dyn SensorLog>, String> {
+    match config.format {
+        SensorLogFormat::Csv => CsvLog::open(&config.path).map(|log| Box::new(log) as _),
+        SensorLogFormat::Sqlite => SqliteLog::open(&config.path).map(|log| Box::new(log) as _),
+    }
+}
+
+pub fn start_sensor_logger(config: SensorLogConfig) {
+    let mut log = match open_sensor_log(&config) {
+        Ok(log) => log,
+        Err(e) => {
+            eprintln!("Could not open sensor log {}: {}", config.path, e);
+            return;
+        }
+    };
+    thread::spawn(move || {
+        let sys = System::new();
+        let interval = Duration::from_secs(config.interval_seconds.max(1));
+        let mut last_reading = None;
+        let mut last_prune: Option<Instant> = None;
+        loop {
+            if config.retention_days > 0
+                && !matches!(last_prune, Some(last_prune) if last_prune.elapsed() < PRUNE_INTERVAL)
+            {
+                let before = Local::now() - chrono::Duration::days(config.retention_days as i64);
+                if let Err(e) = log.prune(before) {
+                    eprintln!("Could not prune sensor log {}: {}", config.path, e);
+                }
+                last_prune = Some(Instant::now());
+            }
+
+            // a sensor, which stopped answering, is not logged with its last values
+            let reading = SENSOR_HISTORY.lock().unwrap().latest().cloned();
+            let reading = match reading {
+                Some(reading) if Some(reading.timestamp) != last_reading => {
+                    last_reading = Some(reading.timestamp);
+                    Some(reading)
+                }
+                _ => None,
+            };
+            let system_info = if config.system_stats {
+                SystemInfoState::measure(&sys)
+            } else {
+                None
+            };
+            if reading.is_some() || system_info.is_some() {
+                let sample = Sample {
+                    timestamp: Local::now(),
+                    reading,
+                    system_info,
+                };
+                if let Err(e) = log.append(&sample) {
+                    eprintln!("Could not write sensor log {}: {}", config.path, e);
+                }
+            }
+
+            if CLOSE_REQUESTED.load(std::sync::atomic::Ordering::Acquire) {
+                return;
+            }
+            thread::sleep(interval);
+        }
+    });
+}
+
+pub struct CsvLog {
+    path: String,
+    file: File,
+}
+
+impl CsvLog {
+    pub fn open(path: &str) -> Result<CsvLog, String> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| e.to_string())?;
+        if file.metadata().map_err(|e| e.to_string())?.len() == 0 {
+            writeln!(file, "{}", CSV_HEADER).map_err(|e| e.to_string())?;
+        }
+        Ok(CsvLog {
+            path: String::from(path),
+            file,
+        })
+    }
+}
+
+impl SensorLog for CsvLog {
+    fn append(&mut self, sample: &Sample) -> Result<(), String> {
+        let reading = sample.reading.as_ref();
+        let system_info = sample.system_info.as_ref();
+        writeln!(
+            self.file,
+            "{},{},{},{},{},{},{}",
+            sample.timestamp.to_rfc3339(),
+            reading.map_or(String::new(), |reading| reading.device.replace(',', " ")),
+            optional(reading.map(|reading| reading.temperature)),
+            optional(reading.map(|reading| reading.humidity)),
+            optional(reading.and_then(|reading| reading.pressure)),
+            optional(system_info.map(|system_info| system_info.cpu_usage)),
+            optional(system_info.map(|system_info| system_info.ram_usage)),
+        )
+        .map_err(|e| e.to_string())
+    }
+
+    // rewrites the file without the old lines
+    fn prune(&mut self, before: DateTime<Local>) -> Result<(), String> {
+        let lines: Vec<String> = BufReader::new(File::open(&self.path).map_err(|e| e.to_string())?)
+            .lines()
+            .map_while(Result::ok)
+            .skip(1)
+            .filter(|line| {
+                match line
+                    .split(',')
+                    .next()
+                    .and_then(|timestamp| DateTime::parse_from_rfc3339(timestamp).ok())
+                {
+                    Some(timestamp) => timestamp.timestamp() >= before.timestamp(),
+                    None => false,
+                }
+            })
+            .collect();
+        // written next to the log and swapped in, a crash in between keeps the old one
+        let temporary_path = format!("{}.tmp", self.path);
+        let mut file = File::create(&temporary_path).map_err(|e| e.to_string())?;
+        writeln!(file, "{}", CSV_HEADER).map_err(|e| e.to_string())?;
+        for line in lines {
+            writeln!(file, "{}", line).map_err(|e| e.to_string())?;
+        }
+        file.sync_all().map_err(|e| e.to_string())?;
+        std::fs::rename(&temporary_path, &self.path).map_err(|e| e.to_string())?;
+        // the appending handle still points to the replaced file
+        self.file = OpenOptions::new()
+            .append(true)
+            .open(&self.path)
+            .map_err(|e| e.to_string())?;
+        Ok(())
+    }
+}
+
+fn optional<T: std::fmt::Display>(value: Option<T>) -> String {
+    value.map_or(String::new(), |value| value.to_string())
+}
+
+pub struct SqliteLog {
+    connection: rusqlite::Connection,
+}
+
+impl SqliteLog {
+    pub fn open(path: &str) -> Result<SqliteLog, String> {
+        let connection = rusqlite::Connection::open(path).map_err(|e| e.to_string())?;
+        connection
+            .execute_batch(
+                "CREATE TABLE IF NOT EXISTS samples (
+                    timestamp INTEGER NOT NULL,
+                    device TEXT,
+                    temperature REAL,
+                    humidity REAL,
+                    pressure REAL,
+                    cpu_usage REAL,
+                    ram_usage REAL
+                );
+                CREATE INDEX IF NOT EXISTS samples_timestamp ON samples (timestamp);",
+            )
+            .map_err(|e| e.to_string())?;
+        Ok(SqliteLog { connection })
+    }
+}
+
+impl SensorLog for SqliteLog {
+    // the timestamp is stored in unix seconds
+    fn append(&mut self, sample: &Sample) -> Result<(), String> {
+        let reading = sample.reading.as_ref();
+        let system_info = sample.system_info.as_ref();
+        self.connection
+            .execute(
+                "INSERT INTO samples VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                rusqlite::params![
+                    sample.timestamp.timestamp(),
+                    reading.map(|reading| reading.device.clone()),
+                    reading.map(|reading| reading.temperature),
+                    reading.map(|reading| reading.humidity),
+                    reading.and_then(|reading| reading.pressure),
+                    system_info.map(|system_info| system_info.cpu_usage),
+                    system_info.map(|system_info| system_info.ram_usage),
+                ],
+            )
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+
+    fn prune(&mut self, before: DateTime<Local>) -> Result<(), String> {
+        self.connection
+            .execute(
+                "DELETE FROM samples WHERE timestamp < ?1",
+                [before.timestamp()],
+            )
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn directory(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!(
+            "awesome_info_display_{}_{}",
+            name,
+            std::process::id()
+        ));
+        std::fs::remove_dir_all(&directory).ok();
+        std::fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    fn sample(days_ago: i64, temperature: f32) -> Sample {
+        let timestamp = Local::now() - chrono::Duration::days(days_ago);
+        Sample {
+            timestamp,
+            reading: Some(SensorReading {
+                temperature,
+                humidity: 45.0,
+                pressure: None,
+                timestamp,
+                device: String::from("teensy, left"),
+            }),
+            system_info: Some(SystemInfoState {
+                cpu_usage: 12.5,
+                ram_usage: 40.0,
+            }),
+        }
+    }
+
+    fn system_sample(days_ago: i64) -> Sample {
+        Sample {
+            reading: None,
+            ..sample(days_ago, 0.0)
+        }
+    }
+
+    fn csv_lines(path: &str) -> Vec<String> {
+        std::fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(String::from)
+            .collect()
+    }
+
+    #[test]
+    fn csv_log_drops_old_lines() {
+        let directory = directory("csv_log");
+        let path = directory.join("sensors.csv").to_string_lossy().into_owned();
+        let mut log = CsvLog::open(&path).unwrap();
+        log.append(&sample(10, 18.5)).unwrap();
+        log.append(&sample(1, 21.5)).unwrap();
+        log.append(&system_sample(0)).unwrap();
+
+        let lines = csv_lines(&path);
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0], CSV_HEADER);
+        assert!(lines[1].ends_with(",teensy  left,18.5,45,,12.5,40"));
+        assert!(lines[3].ends_with(",,,,,12.5,40"));
+
+        log.prune(Local::now() - chrono::Duration::days(7)).unwrap();
+        let lines = csv_lines(&path);
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], CSV_HEADER);
+        assert!(lines[1].ends_with(",teensy  left,21.5,45,,12.5,40"));
+        assert!(!directory.join("sensors.csv.tmp").exists());
+
+        // appended to the pruned file, not to the replaced one
+        log.append(&sample(0, 22.0)).unwrap();
+        assert_eq!(csv_lines(&path).len(), 4);
+
+        // a log opened again keeps its header
+        drop(log);
+        let mut log = CsvLog::open(&path).unwrap();
+        log.append(&sample(0, 22.5)).unwrap();
+        let lines = csv_lines(&path);
+        assert_eq!(lines.len(), 5);
+        assert_eq!(lines.iter().filter(|line| *line == CSV_HEADER).count(), 1);
+        std::fs::remove_dir_all(&directory).ok();
+    }
+
+    #[test]
+    fn sqlite_log_drops_old_samples() {
+        let directory = directory("sqlite_log");
+        let path = directory.join("sensors.db").to_string_lossy().into_owned();
+        let mut log = SqliteLog::open(&path).unwrap();
+        log.append(&sample(10, 18.5)).unwrap();
+        log.append(&sample(1, 21.5)).unwrap();
+        log.append(&system_sample(0)).unwrap();
+
+        let temperatures = |log: &SqliteLog| -> Vec<Option<f64>> {
+            let mut statement = log
+                .connection
+                .prepare("SELECT temperature FROM samples ORDER BY timestamp")
+                .unwrap();
+            let rows = statement.query_map([], |row| row.get(0)).unwrap();
+            rows.map(Result::unwrap).collect()
+        };
+        assert_eq!(temperatures(&log), vec![Some(18.5), Some(21.5), None]);
+
+        log.prune(Local::now() - chrono::Duration::days(7)).unwrap();
+        assert_eq!(temperatures(&log), vec![Some(21.5), None]);
+        let (device, cpu_usage): (String, f64) = log
+            .connection
+            .query_row(
+                "SELECT device, cpu_usage FROM samples WHERE temperature IS NOT NULL",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(device, "teensy, left");
+        assert_eq!(cpu_usage, 12.5);
+
+        // the table is kept, when the log is opened again
+        drop(log);
+        let log = SqliteLog::open(&path).unwrap();
+        assert_eq!(temperatures(&log).len(), 2);
+        drop(log);
+        std::fs::remove_dir_all(&directory).ok();
+    }
+}
@@ -0,0 +1,290 @@
+use crate::{
+    capabilities::*,
+    config::{DeviceConfig, DeviceImageFormat},
+    dada_packet::{split_sequenced_payload, DadaPacketDecoder},
+    helpers::{
+        convert_image::{convert_from_gray_scale, convert_from_rgb565, decode_rle},
+        dirty_rect::split_region_payload,
+    },
+    protocol::{Command, Reply},
+    sinks::DisplaySink,
+};
+use std::{
+    collections::VecDeque,
+    io::{Read, Write},
+    path::PathBuf,
+};
+
+// image of a firmware upload, written next to the frames once it is verified
+#[derive(Default)]
+struct FirmwareUpload {
+    size: u32,
+    crc: u32,
+    image: Vec<u8>,
+}
+
+// Writes every frame a device would show as png into a directory.
+pub struct FileSink {
+    directory: PathBuf,
+    config: DeviceConfig,
+    command: Option<u8>,
+    frames: u32,
+    decoder: DadaPacketDecoder,
+    answers: VecDeque<u8>,
+    // what the device currently shows, partial updates are drawn onto it
+    screen: image::RgbImage,
+    firmware: FirmwareUpload,
+}
+
+impl FileSink {
+    pub fn new(path: &str, config: &DeviceConfig) -> Option<FileSink> {
+        let directory = PathBuf::from(path);
+        match std::fs::create_dir_all(&directory) {
+            Ok(_) => Some(FileSink {
+                directory,
+                config: config.clone(),
+                command: None,
+                frames: 0,
+                decoder: DadaPacketDecoder::default(),
+                answers: VecDeque::new(),
+                screen: image::RgbImage::new(config.width, config.height),
+                firmware: FirmwareUpload::default(),
+            }),
+            Err(e) => {
+                eprintln!("Could not create {:?}: {:?}", path, e);
+                None
+            }
+        }
+    }
+
+    // everything a current firmware supports, except for the sensor and buttons
+    fn send_capabilities(&mut self) {
+        let (pixel_format, encodings) = match self.config.image_format {
+            DeviceImageFormat::Bmp => (PIXEL_FORMAT_GRAY4, ENCODING_GRAY4 | ENCODING_GRAY4_RLE),
+            _ => (
+                PIXEL_FORMAT_RGB888,
+                ENCODING_WEBP | ENCODING_RGB565 | ENCODING_JPEG | ENCODING_QOI,
+            ),
+        };
+        let features = if self.config.use_dada_packet {
+            FEATURE_BRIGHTNESS
+                | FEATURE_SEQUENCED_FRAMES
+                | FEATURE_PARTIAL_UPDATES
+                | FEATURE_FIRMWARE_UPDATE
+        } else {
+            FEATURE_FIRMWARE_UPDATE
+        };
+        let capabilities = Capabilities {
+            version: PROTOCOL_VERSION,
+            width: self.config.width as u16,
+            height: self.config.height as u16,
+            pixel_format,
+            encodings,
+            features,
+        };
+        self.answers
+            .extend(Reply::Capabilities(capabilities).as_bytes());
+    }
+
+    fn unpack(&mut self, payload: &[u8]) -> Option<Vec<u8>> {
+        if !self.config.use_dada_packet {
+            return Some(payload.to_vec());
+        }
+        self.unpack_packet(payload)
+    }
+
+    // firmware commands always come in a dada packet
+    fn unpack_packet(&mut self, payload: &[u8]) -> Option<Vec<u8>> {
+        for packet in self.decoder.decode(payload) {
+            match packet {
+                Ok(packet) => return Some(packet),
+                Err(e) => eprintln!("Invalid packet for file sink: {}", e),
+            }
+        }
+        None
+    }
+
+    // the same image continues where it stopped, another one starts over
+    fn receive_firmware_begin(&mut self, payload: &[u8]) {
+        if let Some([s0, s1, s2, s3, c0, c1, c2, c3]) = self.unpack_packet(payload).as_deref() {
+            let size = u32::from_le_bytes([*s0, *s1, *s2, *s3]);
+            let crc = u32::from_le_bytes([*c0, *c1, *c2, *c3]);
+            if size != self.firmware.size || crc != self.firmware.crc {
+                self.firmware = FirmwareUpload {
+                    size,
+                    crc,
+                    image: Vec::with_capacity(size as usize),
+                };
+            }
+        }
+        self.answers
+            .extend(Reply::FirmwareOffset(self.firmware.image.len() as u32).as_bytes());
+    }
+
+    fn receive_firmware_chunk(&mut self, payload: &[u8]) {
+        if let Some(packet) = self.unpack_packet(payload) {
+            if let Some(header) = packet.get(..8) {
+                let offset = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+                let crc = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+                let data = &packet[8..];
+                let expected = self.firmware.image.len();
+                if offset as usize == expected
+                    && crc32fast::hash(data) == crc
+                    && expected + data.len() <= self.firmware.size as usize
+                {
+                    self.firmware.image.extend_from_slice(data);
+                }
+            }
+        }
+        self.answers
+            .extend(Reply::FirmwareOffset(self.firmware.image.len() as u32).as_bytes());
+    }
+
+    fn verify_firmware(&mut self) {
+        let verified = self.firmware.image.len() == self.firmware.size as usize
+            && crc32fast::hash(&self.firmware.image) == self.firmware.crc;
+        if verified {
+            let path = self
+                .directory
+                .join(format!("{}_firmware.bin", self.config.name));
+            if let Err(e) = std::fs::write(&path, &self.firmware.image) {
+                eprintln!("Could not write {:?}: {:?}", path, e);
+            }
+        }
+        self.answers
+            .extend(Reply::FirmwareVerified(verified).as_bytes());
+    }
+
+    fn receive_sequenced_frame(&mut self, payload: &[u8]) {
+        if let Some(packet) = self.unpack(payload) {
+            if let Some((sequence, frame)) = split_sequenced_payload(&packet) {
+                self.answers.extend(Reply::Ack(sequence).as_bytes());
+                self.save_frame(frame);
+            }
+        }
+    }
+
+    fn receive_compressed_frame(&mut self, payload: &[u8]) {
+        let expected_len = (self.config.width * self.config.height / 2) as usize;
+        match payload
+            .get(2..)
+            .and_then(|encoded| decode_rle(encoded, expected_len))
+        {
+            Some(frame) => self.save_frame(&frame),
+            None => eprintln!("Invalid compressed frame for file sink"),
+        }
+    }
+
+    fn receive_frame(&mut self, payload: &[u8]) {
+        if let Some(frame) = self.unpack(payload) {
+            self.save_frame(&frame);
+        }
+    }
+
+    fn receive_region(&mut self, payload: &[u8]) {
+        if let Some(packet) = self.unpack(payload) {
+            let region = split_region_payload(&packet).and_then(|(region, encoded)| {
+                self.decode(encoded, region.width, region.height)
+                    .map(|image| (region, image))
+            });
+            match region {
+                Some((region, image)) => {
+                    image::imageops::replace(
+                        &mut self.screen,
+                        &image.to_rgb8(),
+                        region.x as i64,
+                        region.y as i64,
+                    );
+                    self.save_screen();
+                }
+                None => eprintln!("Could not decode region for file sink"),
+            }
+        }
+    }
+
+    fn decode(&self, bytes: &[u8], width: u32, height: u32) -> Option<image::DynamicImage> {
+        let format = match self.config.image_format {
+            DeviceImageFormat::Bmp => {
+                return Some(image::DynamicImage::ImageLuma8(convert_from_gray_scale(
+                    bytes, width, height,
+                )))
+            }
+            DeviceImageFormat::Rgb565 => {
+                return Some(image::DynamicImage::ImageRgb8(convert_from_rgb565(
+                    bytes, width, height,
+                )))
+            }
+            DeviceImageFormat::WebP => image::ImageFormat::WebP,
+            DeviceImageFormat::Jpeg => image::ImageFormat::Jpeg,
+            DeviceImageFormat::Qoi => image::ImageFormat::Qoi,
+        };
+        image::load_from_memory_with_format(bytes, format).ok()
+    }
+
+    fn save_frame(&mut self, frame: &[u8]) {
+        match self.decode(frame, self.config.width, self.config.height) {
+            Some(image) => {
+                self.screen = image.to_rgb8();
+                self.save_screen();
+            }
+            None => eprintln!("Could not decode frame {}", self.frames + 1),
+        }
+    }
+
+    fn save_screen(&mut self) {
+        self.frames += 1;
+        let path = self
+            .directory
+            .join(format!("{}_{:06}.png", self.config.name, self.frames));
+        match self.screen.save(&path) {
+            Ok(_) => {}
+            Err(e) => eprintln!("Could not write {:?}: {:?}", path, e),
+        }
+    }
+}
+
+impl DisplaySink for FileSink {}
+
+impl Read for FileSink {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let count = buf.len().min(self.answers.len());
+        for (target, answer) in buf.iter_mut().zip(self.answers.drain(..count)) {
+            *target = answer;
+        }
+        Ok(count)
+    }
+}
+
+impl Write for FileSink {
+    // the device writes every command and every payload with a single call
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self.command.take() {
+            Some(Command::FRAME) => self.receive_frame(buf),
+            Some(Command::SEQUENCED_FRAME) => self.receive_sequenced_frame(buf),
+            Some(Command::REGION) => self.receive_region(buf),
+            Some(Command::COMPRESSED_FRAME) => self.receive_compressed_frame(buf),
+            Some(Command::FIRMWARE_BEGIN) => self.receive_firmware_begin(buf),
+            Some(Command::FIRMWARE_CHUNK) => self.receive_firmware_chunk(buf),
+            // brightness and everything else without a picture
+            Some(_) => {}
+            None => match buf {
+                [Command::CAPABILITIES] => self.send_capabilities(),
+                [Command::FIRMWARE_VERIFY] => self.verify_firmware(),
+                [Command::BRIGHTNESS]
+                | [Command::FRAME]
+                | [Command::SEQUENCED_FRAME]
+                | [Command::REGION]
+                | [Command::COMPRESSED_FRAME]
+                | [Command::SET_ENCODING]
+                | [Command::FIRMWARE_BEGIN]
+                | [Command::FIRMWARE_CHUNK] => self.command = Some(buf[0]),
+                _ => {}
+            },
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,44 @@
+use crate::config::{DeviceConfig, TransportConfig};
+use std::{
+    io::{Read, Write},
+    time::Duration,
+};
+
+pub mod file_sink;
+pub mod null_sink;
+pub mod serial_sink;
+pub mod tcp_sink;
+
+// Everything a device can be attached through. Commands and payloads are written
+// as bytes, answers of the device (e.g. bme readings or acks) are read back.
+pub trait DisplaySink: Read + Write + Send {
+    // a read gives up after `timeout` at the latest, so a reply can be waited for shorter
+    // than the timeout of the port, sinks which never block ignore it
+    fn set_reply_timeout(&mut self, _timeout: Duration) {}
+}
+
+pub fn open_sink(config: &DeviceConfig) -> Option<Box<dyn DisplaySink>> {
+    match &config.transport {
+        TransportConfig::Serial => serial_sink::SerialSink::open(
+            &config.identifier,
+            config.serial_number.as_deref(),
+            config.baud,
+        )
+        .map(|sink| Box::new(sink) as Box<dyn DisplaySink>),
+        TransportConfig::Tcp { host, port } => {
+            tcp_sink::connect(host, *port).map(|stream| Box::new(stream) as Box<dyn DisplaySink>)
+        }
+        TransportConfig::File { path } => file_sink::FileSink::new(path, config)
+            .map(|sink| Box::new(sink) as Box<dyn DisplaySink>),
+        TransportConfig::Null => Some(Box::new(null_sink::NullSink {})),
+    }
+}
+
+// how long to wait before the next connection attempt
+pub fn reconnect_delay(config: &DeviceConfig, failed_attempts: u32) -> Duration {
+    match &config.transport {
+        TransportConfig::Tcp { .. } => tcp_sink::reconnect_delay(failed_attempts),
+        // local ports are cheap to probe, plugged in devices should show up right away
+        _ => Duration::ZERO,
+    }
+}
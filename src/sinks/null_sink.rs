@@ -0,0 +1,23 @@
+use crate::sinks::DisplaySink;
+use std::io::{Read, Write};
+
+// swallows everything, for running the application without any hardware
+pub struct NullSink {}
+
+impl DisplaySink for NullSink {}
+
+impl Read for NullSink {
+    fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+        Ok(0)
+    }
+}
+
+impl Write for NullSink {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,53 @@
+use crate::{
+    helpers::display_serial_com::{init_serial, release_port, PORT_TIMEOUT},
+    sinks::DisplaySink,
+};
+use std::{
+    io::{Read, Write},
+    time::Duration,
+};
+
+// A claimed usb serial port, the claim is released once the device drops the sink.
+pub struct SerialSink {
+    port_name: String,
+    port: Box<dyn serialport::SerialPort>,
+}
+
+impl SerialSink {
+    pub fn open(identifier: &str, serial_number: Option<&str>, baud: u32) -> Option<SerialSink> {
+        init_serial(identifier, serial_number, baud)
+            .map(|(port_name, port)| SerialSink { port_name, port })
+    }
+}
+
+impl DisplaySink for SerialSink {
+    fn set_reply_timeout(&mut self, timeout: Duration) {
+        self.port.set_timeout(timeout).unwrap_or_default();
+    }
+}
+
+impl Read for SerialSink {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.port.read(buf)
+    }
+}
+
+impl Write for SerialSink {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        // the timeout of the port is shared by reads and writes, a reply may have shortened it
+        if self.port.timeout() != PORT_TIMEOUT {
+            self.port.set_timeout(PORT_TIMEOUT)?;
+        }
+        self.port.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.port.flush()
+    }
+}
+
+impl Drop for SerialSink {
+    fn drop(&mut self) {
+        release_port(&self.port_name);
+    }
+}
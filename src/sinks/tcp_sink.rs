@@ -0,0 +1,145 @@
+use crate::sinks::DisplaySink;
+use std::{
+    net::{TcpStream, ToSocketAddrs},
+    time::Duration,
+};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_millis(2000);
+const MIN_RECONNECT_DELAY: Duration = Duration::from_millis(500);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
+impl DisplaySink for TcpStream {
+    fn set_reply_timeout(&mut self, timeout: Duration) {
+        // a zero timeout would block forever
+        self.set_read_timeout(Some(timeout.max(Duration::from_millis(1))))
+            .unwrap_or_default();
+    }
+}
+
+pub fn connect(host: &str, port: u16) -> Option<TcpStream> {
+    let addresses = match (host, port).to_socket_addrs() {
+        Ok(addresses) => addresses,
+        Err(e) => {
+            eprintln!("Could not resolve {}:{}: {:?}", host, port, e);
+            return None;
+        }
+    };
+    for address in addresses {
+        match TcpStream::connect_timeout(&address, CONNECT_TIMEOUT) {
+            Ok(stream) => {
+                // a dead connection shows up as failing write, the device will reconnect then
+                stream.set_nodelay(true).ok()?;
+                stream
+                    .set_read_timeout(Some(Duration::from_millis(1000)))
+                    .ok()?;
+                stream.set_write_timeout(Some(CONNECT_TIMEOUT)).ok()?;
+                return Some(stream);
+            }
+            Err(_) => continue,
+        }
+    }
+    None
+}
+
+// exponential backoff, so an unreachable display does not stall the writer
+pub fn reconnect_delay(failed_attempts: u32) -> Duration {
+    MIN_RECONNECT_DELAY
+        .saturating_mul(2u32.saturating_pow(failed_attempts.saturating_sub(1)))
+        .min(MAX_RECONNECT_DELAY)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        dada_packet::{DadaPacket, DadaPacketDecoder},
+        helpers::display_serial_com::{read_packet, send_command, write_screen_buffer},
+        protocol::Command,
+    };
+    use std::{
+        io::{Read, Write},
+        net::TcpListener,
+        thread,
+    };
+
+    fn listen() -> (TcpListener, u16) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        (listener, port)
+    }
+
+    fn open(port: u16) -> Option<Box<dyn DisplaySink>> {
+        connect("127.0.0.1", port).map(|stream| Box::new(stream) as Box<dyn DisplaySink>)
+    }
+
+    #[test]
+    fn sends_commands_and_frames() {
+        let (listener, port) = listen();
+        let device = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut received = [0u8; 4];
+            stream.read_exact(&mut received).unwrap();
+            received
+        });
+
+        let mut sink = open(port);
+        assert!(send_command(&mut sink, &[Command::FRAME]));
+        assert!(write_screen_buffer(&mut sink, &[1, 2, 3]));
+        assert_eq!(device.join().unwrap(), [Command::FRAME, 1, 2, 3]);
+    }
+
+    #[test]
+    fn waits_for_late_replies() {
+        let (listener, port) = listen();
+        let device = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut command = [0u8; 1];
+            stream.read_exact(&mut command).unwrap();
+            // later than the read timeout of the socket
+            thread::sleep(Duration::from_millis(1500));
+            stream
+                .write_all(&DadaPacket::new(command.to_vec()).as_bytes())
+                .unwrap();
+            stream
+        });
+
+        let mut sink = open(port);
+        assert!(send_command(&mut sink, &[Command::CAPABILITIES]));
+        let reply = read_packet(
+            &mut sink,
+            &mut DadaPacketDecoder::default(),
+            Duration::from_secs(5),
+        );
+        assert_eq!(reply, Some(vec![Command::CAPABILITIES]));
+        device.join().unwrap();
+    }
+
+    #[test]
+    fn gives_up_on_missing_replies() {
+        let (listener, port) = listen();
+        let mut sink = open(port);
+        let (_stream, _) = listener.accept().unwrap();
+
+        let reply = read_packet(
+            &mut sink,
+            &mut DadaPacketDecoder::default(),
+            Duration::from_millis(1500),
+        );
+        assert_eq!(reply, None);
+    }
+
+    #[test]
+    fn fails_without_listener() {
+        let (listener, port) = listen();
+        drop(listener);
+        assert!(connect("127.0.0.1", port).is_none());
+    }
+
+    #[test]
+    fn backs_off_exponentially() {
+        assert_eq!(reconnect_delay(1), MIN_RECONNECT_DELAY);
+        assert_eq!(reconnect_delay(2), MIN_RECONNECT_DELAY * 2);
+        assert_eq!(reconnect_delay(4), MIN_RECONNECT_DELAY * 8);
+        assert_eq!(reconnect_delay(100), MAX_RECONNECT_DELAY);
+    }
+}
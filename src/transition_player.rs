@@ -0,0 +1,91 @@
+// Plays the transitions between screens on a thread of its own, at about 30 fps no matter
+// how often iced renders the view or how many frames per second the devices are paced to.
+use crate::config::TransitionConfig;
+use crate::helpers::transition::{self, Transition};
+use crate::screens::Canvas;
+use std::sync::{
+    atomic::{AtomicU32, Ordering},
+    Arc, Mutex,
+};
+use std::thread;
+use std::time::Duration;
+
+const FRAME_INTERVAL: Duration = Duration::from_millis(33);
+
+// rgb frames of the main and the companion display
+#[derive(Clone, Default)]
+pub struct Frames {
+    pub main: Vec<u8>,
+    pub companion: Vec<u8>,
+}
+
+#[derive(Default)]
+pub struct TransitionPlayer {
+    // what the incoming screen shows right now, kept up to date by the view
+    target: Arc<Mutex<Frames>>,
+    // counts the transitions, a newer one stops the running one
+    started: Arc<AtomicU32>,
+    finished: Arc<AtomicU32>,
+}
+
+impl TransitionPlayer {
+    pub fn set_target(&self, frames: Frames) {
+        *self.target.lock().unwrap() = frames;
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.started.load(Ordering::Acquire) != self.finished.load(Ordering::Acquire)
+    }
+
+    // `show` gets the blended frames in the size of the canvases, the last one is the
+    // incoming screen itself
+    pub fn play(
+        &self,
+        transition: Transition,
+        config: TransitionConfig,
+        (main_canvas, companion_canvas): (Canvas, Canvas),
+        show: impl Fn(Frames) + Send + 'static,
+    ) {
+        let generation = self.started.fetch_add(1, Ordering::AcqRel) + 1;
+        let started = self.started.clone();
+        let finished = self.finished.clone();
+        let target = self.target.clone();
+        thread::spawn(move || {
+            let blend = |kind, canvas: Canvas, from: &[u8], to: &[u8], progress| {
+                transition::blend(
+                    kind,
+                    &canvas.fit(from, canvas.width, canvas.height),
+                    &canvas.fit(to, canvas.width, canvas.height),
+                    canvas.width,
+                    canvas.height,
+                    progress,
+                )
+            };
+            while started.load(Ordering::Acquire) == generation {
+                let progress = transition.progress(config.duration()).unwrap_or(1.0);
+                let frames = target.lock().unwrap().clone();
+                show(Frames {
+                    main: blend(
+                        config.main,
+                        main_canvas,
+                        transition.main(),
+                        &frames.main,
+                        progress,
+                    ),
+                    companion: blend(
+                        config.companion,
+                        companion_canvas,
+                        transition.companion(),
+                        &frames.companion,
+                        progress,
+                    ),
+                });
+                if progress >= 1.0 {
+                    break;
+                }
+                thread::sleep(FRAME_INTERVAL);
+            }
+            finished.fetch_max(generation, Ordering::AcqRel);
+        });
+    }
+}
@@ -0,0 +1,89 @@
+// Drives the device emulator binary over tcp, the way the application talks to a
+// companion on wifi.
+use std::{
+    io::{Read, Write},
+    net::{TcpListener, TcpStream},
+    path::PathBuf,
+    process::{Child, Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+const READ_BME: u8 = 205;
+const RESET: u8 = 17;
+const FRAME: u8 = 228;
+
+// killed, even if an assertion fails
+struct Emulator(Child);
+
+impl Drop for Emulator {
+    fn drop(&mut self) {
+        self.0.kill().ok();
+        self.0.wait().ok();
+    }
+}
+
+fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+fn wait_for<T>(what: &str, mut poll: impl FnMut() -> Option<T>) -> T {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        if let Some(value) = poll() {
+            return value;
+        }
+        assert!(Instant::now() < deadline, "timed out waiting for {}", what);
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+#[test]
+fn teensy_over_tcp() {
+    let directory =
+        std::env::temp_dir().join(format!("device_emulator_tcp_{}", std::process::id()));
+    std::fs::remove_dir_all(&directory).ok();
+    let port = free_port();
+    let _emulator = Emulator(
+        Command::new(env!("CARGO_BIN_EXE_device_emulator"))
+            .arg("teensy")
+            .arg(&directory)
+            .args(["--tcp", &port.to_string()])
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .spawn()
+            .unwrap(),
+    );
+
+    let mut stream = wait_for("the emulator to listen", || {
+        TcpStream::connect(("127.0.0.1", port)).ok()
+    });
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+
+    stream.write_all(&[RESET, FRAME]).unwrap();
+    stream.write_all(&[0xff; 256 * 64 / 2]).unwrap();
+    let png: PathBuf = directory.join("teensy_000001.png");
+    wait_for("the first frame", || png.exists().then_some(()));
+
+    stream.write_all(&[READ_BME]).unwrap();
+    let mut reply = [0u8; 14];
+    stream.read_exact(&mut reply).unwrap();
+    assert_eq!(&reply, b"21.50 45.00\0\0\0");
+
+    // the next host is served, once the first one is gone
+    drop(stream);
+    let mut stream = wait_for("the second connection", || {
+        TcpStream::connect(("127.0.0.1", port)).ok()
+    });
+    stream.write_all(&[FRAME]).unwrap();
+    stream.write_all(&[0; 256 * 64 / 2]).unwrap();
+    let png = directory.join("teensy_000002.png");
+    wait_for("the second frame", || png.exists().then_some(()));
+    std::fs::remove_dir_all(&directory).ok();
+}